@@ -1,18 +1,14 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
+use log::debug;
 
 use crate::util;
 use crate::opcodes::bootload::*;
 
-pub fn get_app_ver<T: UsbContext>(device_handle: &DeviceHandle<T>) {
+pub fn get_app_ver<T: Transport>(device_handle: &T) -> Result<u8> {
     let mut buf: [u8; 3] = [0; 3];
-    util::read_device(device_handle, &mut buf, 10, GET_APP_VER, 0, 0);
+    util::read_device(device_handle, &mut buf, 10, GET_APP_VER, 0, 0)?;
     let app_version = buf[2];
-    println!("App version is: {}", app_version);
-    // TODO: Send back error for different app_versions
-    if APP_VERSION != app_version {
-        println!(
-            "ERROR got the wrong app version. Should be {} but got {}",
-            APP_VERSION, app_version
-        );
-    }
+    debug!("App version is: {}", app_version);
+    return Ok(app_version);
 }
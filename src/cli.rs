@@ -0,0 +1,423 @@
+use log::LevelFilter;
+
+use inlrust::nes::{self, Board, HeaderFormat, Timing};
+use inlrust::naming;
+use inlrust::programmer::DeviceFilter;
+use inlrust::verify::{Mode, Verify};
+
+const CONSOLES: [&str; 5] = ["nes", "snes", "gb", "gba", "genesis"];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Info,
+    DumpRom,
+    DumpSave,
+    WriteSave,
+    TestCart,
+    Diagnose,
+    ListDevices,
+    ListMappers,
+}
+
+// Command line options
+#[derive(Debug)]
+pub struct CommandLineOptions {
+    pub command: Command,
+    pub console: String, // lowercase
+    pub file: String,
+    pub mapper: String,
+    pub prg_size: Option<u16>, // x, detected if None
+    pub chr_size: Option<u16>, // y, detected if None
+    pub wram_size: Option<u16>, // w, dump-rom also dumps the save if given
+    pub board: Board,
+    pub emulate: String,
+    pub record: String,
+    pub replay: String,
+    pub device: DeviceFilter,
+    pub log_level: LevelFilter,
+    pub resume: bool,
+    pub verify: Verify,
+    pub dat: String,
+    pub name: String, // template, used when there is no file
+    pub json: bool,
+    pub yes: bool, // write-save without asking
+}
+
+pub fn help() {
+    println!("
+Usage: inlrust <command> [options] [file]
+
+Commands:
+  info                                          Read and print the cartridge header
+  dump-rom                                      Dump the cartridge ROM to file, named from the header without one
+  dump-save                                     Dump the cartridge save RAM to file, named like the ROM without one
+  write-save                                    Write file to the cartridge save RAM
+  test-cart                                     Test the cartridge and print what was found
+  diagnose                                      Look for bad address and data lines between cart and programmer
+  list-devices                                  List connected programmers
+  list-mappers                                  List the NES mappers that can be dumped with their iNES number
+
+Options/Flags:
+  --help, -h                                    Displays this message.
+  -c console                                    Console port, (NES, SNES, GB, GBA, GENESIS)
+  -m mapper                                     NES-only: ({}) or its iNES number
+  -x size_kbytes                                NES-only, size of PRG-ROM in kilobytes, detected if not given
+  -y size_kbytes                                NES-only, size of CHR-ROM in kilobytes, detected if not given
+  -w size_kbytes                                NES-only, size of battery backed WRAM, dump-rom also writes it to a .sav
+  --submapper number                            NES-only, NES 2.0 submapper
+  --prg-ram size_kbytes                         NES-only, size of PRG-RAM without battery
+  --prg-nvram size_kbytes                       NES-only, size of battery backed PRG-RAM
+  --chr-ram size_kbytes                         NES-only, size of CHR-RAM, sensed without CHR-ROM if not given
+  --battery                                     NES-only, the cart has a battery or other non-volatile memory
+  --four-screen                                 NES-only, the cart has four screen VRAM
+  --timing timing                               NES-only, (ntsc, pal, multi, dendy), ntsc if not given
+  --ines1                                       NES-only, write an iNES 1.0 header instead of NES 2.0
+  -e filename                                   Emulate the programmer with this ROM image inserted
+  -r filename                                   Record every USB transfer to this trace file
+  -p filename                                   Replay a recorded trace file instead of using the programmer
+  --bus number                                  Use the programmer on this USB bus
+  --address number                              Use the programmer with this USB device address
+  --serial string                               Use the programmer with this serial number
+  --resume                                      SNES, GBA and GENESIS dump-rom: continue an interrupted dump
+  --verify passes                               Read every bank this many times and fail where they differ
+  --majority                                    With --verify, keep the value most passes read instead of failing
+  --dat filename                                dump-rom: look the dump up in this No-Intro DAT file
+  --json                                        info: print the raw and decoded header as JSON
+  --name template                               dump-rom, dump-save: name the file like {{title}} ({{region}}) [{{crc32}}].{{ext}}
+  --yes                                         write-save: overwrite the save on the cart without asking
+  -v, -vv                                       Log more, -vv also logs every device call
+  -q                                            Only log errors and hide the progress bar
+", mapper_names())
+}
+
+fn mapper_names() -> String {
+    let names: Vec<&str> = nes::mappers().iter().map(|(name, _)| *name).collect();
+    return names.join(", ");
+}
+
+pub fn parse_command_line(args: &[String]) -> Result<CommandLineOptions, String> {
+    if args.len() < 2 {
+        return Err(String::from("Not enough arguments."))
+    }
+    let command = match args[1].as_str() {
+        "-h" | "--help" => return Err(String::from("")),
+        "info" => Command::Info,
+        "dump-rom" => Command::DumpRom,
+        "dump-save" => Command::DumpSave,
+        "write-save" => Command::WriteSave,
+        "test-cart" => Command::TestCart,
+        "diagnose" => Command::Diagnose,
+        "list-devices" => Command::ListDevices,
+        "list-mappers" => Command::ListMappers,
+        other => return Err(format!("Unknown command \"{}\".", other)),
+    };
+    let mut console = "".to_owned();
+    let mut file = "".to_owned();
+    let mut mapper = "".to_owned();
+    let mut prg_size = None;
+    let mut chr_size = None;
+    let mut wram_size = None;
+    let mut emulate = "".to_owned();
+    let mut record = "".to_owned();
+    let mut replay = "".to_owned();
+    let mut device = DeviceFilter::default();
+    let mut verbose = 0;
+    let mut quiet = false;
+    let mut resume = false;
+    let mut passes = None;
+    let mut majority = false;
+    let mut dat = "".to_owned();
+    let mut name = None;
+    let mut json = false;
+    let mut yes = false;
+    let mut board = Board::default();
+    let mut board_flags = Vec::new();
+
+    let mut i = 2;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if arg == "-h" || arg == "--help" {
+            return Err(String::from(""));
+        }
+        if !arg.starts_with('-') {
+            if !file.is_empty() {
+                return Err(format!("Unexpected argument \"{}\".", arg));
+            }
+            file = arg.to_owned();
+            i += 1;
+            continue;
+        }
+        match arg {
+            "-v" | "-vv" => {
+                verbose += arg.len() - 1;
+                i += 1;
+                continue;
+            }
+            "-q" => {
+                quiet = true;
+                i += 1;
+                continue;
+            }
+            "--resume" => {
+                resume = true;
+                i += 1;
+                continue;
+            }
+            "--majority" => {
+                majority = true;
+                i += 1;
+                continue;
+            }
+            "--json" => {
+                json = true;
+                i += 1;
+                continue;
+            }
+            "--yes" => {
+                yes = true;
+                i += 1;
+                continue;
+            }
+            "--battery" | "--four-screen" | "--ines1" => {
+                match arg {
+                    "--battery" => board.battery = true,
+                    "--four-screen" => board.four_screen = true,
+                    _ => board.format = HeaderFormat::Ines,
+                }
+                board_flags.push(arg);
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let value = args.get(i + 1).ok_or_else(|| format!("Option {} needs a value.", arg))?;
+        match arg {
+            "-c" => console = value.to_lowercase(),
+            "-m" => mapper = value.to_lowercase(),
+            "-x" => prg_size = Some(parse_number(value)?),
+            "-y" => chr_size = Some(parse_number(value)?),
+            "-w" => wram_size = Some(parse_number(value)?),
+            "-e" => emulate = value.clone(),
+            "-r" => record = value.clone(),
+            "-p" => replay = value.clone(),
+            "--bus" => device.bus = Some(parse_byte(value)?),
+            "--address" => device.address = Some(parse_byte(value)?),
+            "--serial" => device.serial = Some(value.clone()),
+            "--verify" => passes = Some(parse_byte(value)?),
+            "--dat" => dat = value.clone(),
+            "--name" => name = Some(value.clone()),
+            "--submapper" | "--prg-ram" | "--prg-nvram" | "--chr-ram" | "--timing" => {
+                match arg {
+                    "--submapper" => board.submapper = parse_submapper(value)?,
+                    "--prg-ram" => board.prg_ram = parse_number(value)? as u32 * 1024,
+                    "--prg-nvram" => board.prg_nvram = parse_number(value)? as u32 * 1024,
+                    "--chr-ram" => board.chr_ram = Some(parse_number(value)? as u32 * 1024),
+                    _ => board.timing = parse_timing(value)?,
+                }
+                board_flags.push(arg);
+            }
+            _ => return Err(format!("Unknown option \"{}\".", arg)),
+        }
+        i += 2;
+    }
+
+    if quiet && verbose > 0 {
+        return Err(String::from("-q and -v can not be used together."));
+    }
+    let log_level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    if passes.is_some_and(|passes| passes < 2) {
+        return Err(String::from("--verify needs at least 2 passes."));
+    }
+    if majority && passes.unwrap_or(0) < 3 {
+        return Err(String::from("--majority needs --verify 3 or more, two passes can not outvote each other."));
+    }
+    let verify = match passes {
+        Some(passes) => Verify { passes, mode: if majority { Mode::Majority } else { Mode::Fail } },
+        None => Verify::OFF,
+    };
+
+    // The header tells emulators about the save that is dumped along.
+    if let Some(wram_size) = wram_size {
+        if board.prg_ram == 0 && board.prg_nvram == 0 {
+            board.prg_nvram = wram_size as u32 * 1024;
+        }
+    }
+
+    let template = naming::default_template(&console).to_owned();
+    let options = CommandLineOptions {
+        command,
+        console,
+        file,
+        // By name from here on, also when given by number.
+        mapper: nes::mapper_name(&mapper).unwrap_or(&mapper).to_owned(),
+        prg_size,
+        chr_size,
+        wram_size,
+        board,
+        emulate,
+        record,
+        replay,
+        device,
+        log_level,
+        resume,
+        verify,
+        dat,
+        name: name.clone().unwrap_or(template),
+        json,
+        yes,
+    };
+    let mut nes_only = vec![
+        ("-m", !options.mapper.is_empty()),
+        ("-x", prg_size.is_some()),
+        ("-y", chr_size.is_some()),
+        ("-w", wram_size.is_some()),
+    ];
+    nes_only.extend(board_flags.iter().map(|flag| (*flag, true)));
+    validate(&options, &nes_only)?;
+    if let Some(flag) = board_flags.first() {
+        if options.command != Command::DumpRom {
+            return Err(format!("{} is only valid for dump-rom.", flag));
+        }
+    }
+    if wram_size.is_some() && options.command != Command::DumpRom && options.command != Command::DumpSave {
+        return Err(String::from("-w is only valid for dump-rom and dump-save."));
+    }
+    if name.is_some() {
+        if !options.file.is_empty() {
+            return Err(String::from("Give a file or --name, not both."));
+        }
+        if options.command != Command::DumpRom && options.command != Command::DumpSave {
+            return Err(String::from("--name is only valid for dump-rom and dump-save."));
+        }
+        naming::check(&options.name).map_err(|err| err.to_string())?;
+    }
+    // Nothing on an NES cart tells which ROM dump the save belongs to.
+    if options.console == "nes" && options.command == Command::DumpSave && options.file.is_empty() {
+        return Err(String::from("dump-save for NES needs a file, or dump the save along with the ROM with dump-rom -w."));
+    }
+    return Ok(options);
+}
+
+// Reject combinations that would otherwise be silently ignored.
+fn validate(options: &CommandLineOptions, nes_only: &[(&str, bool)]) -> Result<(), String> {
+    if !options.emulate.is_empty() && !options.replay.is_empty() {
+        return Err(String::from("-e and -p can not be used together."));
+    }
+    let picks_device = options.device.bus.is_some() || options.device.address.is_some() || options.device.serial.is_some();
+    if picks_device && (!options.emulate.is_empty() || !options.replay.is_empty()) {
+        return Err(String::from("--bus, --address and --serial can not be used with -e or -p."));
+    }
+    if options.command == Command::ListDevices || options.command == Command::ListMappers {
+        if !options.console.is_empty() || !options.file.is_empty() {
+            return Err(String::from("list-devices and list-mappers take no console or file."));
+        }
+        return Ok(());
+    }
+
+    if options.console.is_empty() {
+        return Err(String::from("A console is needed, use -c."));
+    }
+    if !CONSOLES.contains(&options.console.as_str()) {
+        return Err(format!("Console {} is not supported!", options.console));
+    }
+    if options.console != "nes" {
+        if let Some((flag, _)) = nes_only.iter().find(|(_, given)| *given) {
+            return Err(format!("{} is only valid for NES.", flag));
+        }
+    }
+
+    // Dumps without a file are named from the cart header.
+    let needs_file = options.command == Command::WriteSave;
+    let takes_file = needs_file || options.command == Command::DumpRom || options.command == Command::DumpSave;
+    if needs_file && options.file.is_empty() {
+        return Err(String::from("A file is needed for this command."));
+    }
+    if !takes_file && !options.file.is_empty() {
+        return Err(format!("Unexpected argument \"{}\".", options.file));
+    }
+
+    let resumable = options.command == Command::DumpRom && ["snes", "gba", "genesis"].contains(&options.console.as_str());
+    if options.resume && !resumable {
+        return Err(String::from("--resume is only valid for dump-rom on SNES, GBA and GENESIS."));
+    }
+
+    let dumps = options.command == Command::DumpRom || options.command == Command::DumpSave;
+    if options.verify != Verify::OFF && !dumps {
+        return Err(String::from("--verify is only valid for dump-rom and dump-save."));
+    }
+
+    if options.json && options.command != Command::Info {
+        return Err(String::from("--json is only valid for info."));
+    }
+
+    if options.yes && options.command != Command::WriteSave {
+        return Err(String::from("--yes is only valid for write-save."));
+    }
+
+    if !options.dat.is_empty() && options.command != Command::DumpRom {
+        return Err(String::from("--dat is only valid for dump-rom."));
+    }
+
+    let is_save = options.command == Command::DumpSave || options.command == Command::WriteSave;
+    if is_save && options.console == "gba" {
+        return Err(String::from("GBA save RAM is not supported."));
+    }
+
+    if options.console == "nes" {
+        let needs_mapper = options.command == Command::DumpRom
+            || options.command == Command::DumpSave
+            || options.command == Command::WriteSave
+            || options.command == Command::TestCart;
+        if needs_mapper && options.mapper.is_empty() {
+            return Err(format!("NES needs a mapper, use -m ({}).", mapper_names()));
+        }
+        if !options.mapper.is_empty() && nes::mapper_name(&options.mapper).is_none() {
+            return Err(format!("Mapper {} is not supported, see list-mappers.", options.mapper));
+        }
+        if !options.mapper.is_empty() {
+            nes::check_rom_sizes(&options.mapper, options.prg_size, options.chr_size).map_err(|err| err.to_string())?;
+        }
+    }
+    return Ok(());
+}
+
+fn parse_number(argument: &String) -> Result<u16, String> {
+    let input_opt = argument.clone().parse::<u16>();
+    let size = match input_opt {
+        Ok(size) => size,
+        Err(e) => {
+            return Err(format!("While parsing \"{}\" got err: {}", argument, e));
+        }
+    };
+    return Ok(size);
+}
+
+fn parse_submapper(argument: &String) -> Result<u8, String> {
+    let submapper = parse_byte(argument)?;
+    if submapper > 15 {
+        return Err(format!("Submapper {} does not fit in a NES 2.0 header, it goes up to 15.", submapper));
+    }
+    return Ok(submapper);
+}
+
+fn parse_timing(argument: &str) -> Result<Timing, String> {
+    return match argument.to_lowercase().as_str() {
+        "ntsc" => Ok(Timing::Ntsc),
+        "pal" => Ok(Timing::Pal),
+        "multi" => Ok(Timing::Multi),
+        "dendy" => Ok(Timing::Dendy),
+        _ => Err(format!("Unknown timing \"{}\", use ntsc, pal, multi or dendy.", argument)),
+    };
+}
+
+fn parse_byte(argument: &String) -> Result<u8, String> {
+    return argument
+        .parse::<u8>()
+        .map_err(|e| format!("While parsing \"{}\" got err: {}", argument, e));
+}
@@ -0,0 +1,196 @@
+// Hashes of a dumped ROM and looking them up in a No-Intro (Logiqx XML) DAT
+// file, to tell a good dump from one that needs the cart cleaned.
+use std::fmt;
+use std::fs;
+use std::io;
+
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hashes {
+    pub size: u64,
+    pub crc32: u32,
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+}
+
+impl Hashes {
+    pub fn of(data: &[u8]) -> Hashes {
+        return Hashes {
+            size: data.len() as u64,
+            crc32: crc32fast::hash(data),
+            md5: Md5::digest(data).into(),
+            sha1: Sha1::digest(data).into(),
+        };
+    }
+
+    // Hash a dump file like No-Intro does: NES without the iNES header the
+    // dump starts with and SNES without a 512 byte copier header.
+    pub fn of_dump(console: &str, filename: &str) -> Result<Hashes> {
+        let data = fs::read(filename)?;
+        let rom = match console.to_lowercase().as_str() {
+            "nes" if data.starts_with(b"NES\x1A") && data.len() >= 16 => &data[16..],
+            "snes" if data.len() % 1024 == 512 => &data[512..],
+            _ => &data[..],
+        };
+        return Ok(Hashes::of(rom));
+    }
+}
+
+pub fn print_hashes(hashes: &Hashes) {
+    println!("Size: {} bytes", hashes.size);
+    println!("CRC32: {:08X}", hashes.crc32);
+    println!("MD5: {}", to_hex(&hashes.md5));
+    println!("SHA-1: {}", to_hex(&hashes.sha1));
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    // In the DAT as a good dump, with its name there.
+    Good(String),
+    // In the DAT marked as a bad dump.
+    Bad(String),
+    Unknown,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Good(name) => write!(f, "Verified good dump: {}", name),
+            Status::Bad(name) => write!(f, "Known bad dump: {}", name),
+            Status::Unknown => write!(f, "Unknown: not in the DAT, the cart may need cleaning"),
+        }
+    }
+}
+
+// One <rom> entry, hashes are kept as lowercase hex like they are written.
+struct Entry {
+    game: String,
+    size: Option<u64>,
+    crc: Option<String>,
+    md5: Option<String>,
+    sha1: Option<String>,
+    bad: bool,
+}
+
+pub struct Dat {
+    entries: Vec<Entry>,
+}
+
+impl Dat {
+    pub fn load(filename: &str) -> Result<Dat> {
+        let text = fs::read_to_string(filename)
+            .map_err(|err| io::Error::new(err.kind(), format!("Could not read {}: {}", filename, err)))?;
+        // No-Intro DATs start with the Logiqx DOCTYPE.
+        let options = roxmltree::ParsingOptions { allow_dtd: true, ..roxmltree::ParsingOptions::default() };
+        let document = roxmltree::Document::parse_with_options(&text, options)
+            .map_err(|err| Error::Dat(format!("{}: {}", filename, err)))?;
+
+        let mut entries = Vec::new();
+        for game in document.descendants().filter(|node| node.has_tag_name("game") || node.has_tag_name("machine")) {
+            let name = game.attribute("name").unwrap_or_default();
+            for rom in game.children().filter(|node| node.has_tag_name("rom")) {
+                let hex = |attribute| rom.attribute(attribute).map(str::to_lowercase);
+                let status = rom.attribute("status").unwrap_or_default();
+                entries.push(Entry {
+                    game: name.to_owned(),
+                    size: rom.attribute("size").and_then(|size| size.parse().ok()),
+                    crc: hex("crc"),
+                    md5: hex("md5"),
+                    sha1: hex("sha1"),
+                    // No-Intro also tags bad dumps with [b] in the name.
+                    bad: status == "baddump" || status == "nodump" || name.contains("[b"),
+                });
+            }
+        }
+        if entries.is_empty() {
+            return Err(Error::Dat(format!("{} has no game with a rom entry", filename)));
+        }
+        return Ok(Dat { entries });
+    }
+
+    // A rom matches when every hash it lists is equal, at least one has to be listed.
+    pub fn lookup(&self, hashes: &Hashes) -> Status {
+        let crc = format!("{:08x}", hashes.crc32);
+        let md5 = to_hex(&hashes.md5);
+        let sha1 = to_hex(&hashes.sha1);
+        let same = |expected: &Option<String>, actual: &str| expected.as_deref().map_or(true, |expected| expected == actual);
+
+        let found = self.entries.iter().find(|entry| {
+            let listed = entry.crc.is_some() || entry.md5.is_some() || entry.sha1.is_some();
+            listed
+                && entry.size.map_or(true, |size| size == hashes.size)
+                && same(&entry.crc, &crc)
+                && same(&entry.md5, &md5)
+                && same(&entry.sha1, &sha1)
+        });
+        return match found {
+            Some(entry) if entry.bad => Status::Bad(entry.game.clone()),
+            Some(entry) => Status::Good(entry.game.clone()),
+            None => Status::Unknown,
+        };
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hashes of "abc".
+    const CRC: &str = "352441c2";
+    const MD5: &str = "900150983cd24fb0d6963f7d28e17f72";
+    const SHA1: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+
+    fn entry(game: &str, size: Option<u64>, crc: Option<&str>, md5: Option<&str>, sha1: Option<&str>, bad: bool) -> Entry {
+        return Entry {
+            game: game.to_owned(),
+            size,
+            crc: crc.map(str::to_owned),
+            md5: md5.map(str::to_owned),
+            sha1: sha1.map(str::to_owned),
+            bad,
+        };
+    }
+
+    fn lookup(entries: Vec<Entry>) -> Status {
+        return Dat { entries }.lookup(&Hashes::of(b"abc"));
+    }
+
+    #[test]
+    fn every_listed_hash_matches() {
+        let status = lookup(vec![entry("Game", Some(3), Some(CRC), Some(MD5), Some(SHA1), false)]);
+        assert_eq!(status, Status::Good(String::from("Game")));
+        // Hashes missing from the entry are not compared.
+        assert_eq!(lookup(vec![entry("Game", None, None, None, Some(SHA1), false)]), Status::Good(String::from("Game")));
+        assert_eq!(lookup(vec![entry("Game", None, Some(CRC), None, None, false)]), Status::Good(String::from("Game")));
+    }
+
+    #[test]
+    fn one_differing_hash_or_size_is_no_match() {
+        assert_eq!(lookup(vec![entry("Game", Some(4), Some(CRC), Some(MD5), Some(SHA1), false)]), Status::Unknown);
+        assert_eq!(lookup(vec![entry("Game", Some(3), Some("00000000"), Some(MD5), Some(SHA1), false)]), Status::Unknown);
+        assert_eq!(lookup(vec![entry("Game", Some(3), Some(CRC), Some(MD5), Some(&SHA1[1..]), false)]), Status::Unknown);
+    }
+
+    #[test]
+    fn entry_without_hashes_never_matches() {
+        assert_eq!(lookup(vec![entry("Game", Some(3), None, None, None, false)]), Status::Unknown);
+    }
+
+    #[test]
+    fn bad_dump_and_first_match() {
+        let entries = vec![
+            entry("Other", None, Some("00000000"), None, None, false),
+            entry("Game [b1]", None, Some(CRC), None, None, true),
+            entry("Game", None, Some(CRC), None, None, false),
+        ];
+        assert_eq!(lookup(entries), Status::Bad(String::from("Game [b1]")));
+    }
+}
@@ -0,0 +1,308 @@
+// Finding bad address and data lines between the programmer and the cart.
+// Nothing is known about what the ROM holds, so faults are found from how the
+// reads relate to each other: a data line that never changes is stuck, two
+// lines that always agree are shorted and an address line that never changes
+// what is read is stuck or open. Each console module describes its bus and
+// edge pins and calls probe with its read function, see nes::diagnose.
+use std::fmt;
+
+use crate::error::Result;
+
+// Sample addresses, each is read once more per address line with that line flipped.
+const SAMPLES: usize = 32;
+
+// A signal on the cart edge connector, number is as printed on the pinout
+// of the console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub signal: &'static str,
+    pub number: &'static str,
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (pin {})", self.signal, self.number)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    // A data line that read high on every read.
+    DataStuckHigh(Pin),
+    DataStuckLow(Pin),
+    // Two data lines that read the same level on every read.
+    DataShorted(Pin, Pin),
+    // Flipping the address line never changed the data. Without knowing the
+    // ROM a line stuck high can not be told from one stuck low or open.
+    AddressStuck(Pin),
+    // Flipping either of the two lines always read the same location.
+    AddressShorted(Pin, Pin),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::DataStuckHigh(pin) => write!(f, "{} is always high, stuck high or open", pin),
+            Fault::DataStuckLow(pin) => write!(f, "{} is always low, stuck low", pin),
+            Fault::DataShorted(a, b) => write!(f, "{} and {} always read the same, shorted", a, b),
+            Fault::AddressStuck(pin) => write!(f, "{} does not change the data, stuck high, stuck low or open", pin),
+            Fault::AddressShorted(a, b) => write!(f, "{} and {} select the same data, shorted", a, b),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub connector: &'static str,
+    pub faults: Vec<Fault>,
+    // Address lines above the highest one that changes the data. Expected
+    // when the ROM is smaller than the bus, the ROM just repeats.
+    pub unused: Vec<Pin>,
+    // Every read returned this value, there is no cart or no working line.
+    pub constant: Option<u16>,
+    pub reads: usize,
+}
+
+// How a console's cart is read for the probe. base is set in every address,
+// address lists the tested lines with their bit in the address handed to the
+// read function and data the data lines from D0 up.
+pub(crate) struct Bus {
+    pub(crate) connector: &'static str,
+    pub(crate) base: u32,
+    pub(crate) address: &'static [(u8, Pin)],
+    pub(crate) data: &'static [Pin],
+}
+
+pub(crate) fn probe<F: FnMut(u32) -> Result<u16>>(bus: &Bus, mut read: F) -> Result<Report> {
+    let lines = bus.address.len();
+
+    // reads[s][0] is sample s, reads[s][1 + i] the same with address line i flipped.
+    let mut addresses = Vec::with_capacity(SAMPLES);
+    let mut reads = Vec::with_capacity(SAMPLES);
+    let mut seed: u32 = 0x2545_F491;
+    for sample in 0..SAMPLES {
+        // Every other sample only reaches part way up the bus, so a ROM that
+        // is smaller than the bus still gets read at all its lines.
+        let reach = match sample % 2 {
+            0 => lines,
+            _ => lines.min(8) + lines.saturating_sub(8) * (sample + 1) / SAMPLES,
+        };
+        let mask = bus.address[..reach].iter().fold(0, |mask, (bit, _)| mask | 1 << bit);
+        // xorshift, the same samples every run.
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let address = bus.base | (seed & mask);
+        let mut row = vec![read(address)?];
+        for (bit, _) in bus.address {
+            row.push(read(address ^ (1 << bit))?);
+        }
+        addresses.push(address);
+        reads.push(row);
+    }
+
+    let values: Vec<u16> = reads.iter().flatten().copied().collect();
+    let mut report = Report {
+        connector: bus.connector,
+        faults: Vec::new(),
+        unused: Vec::new(),
+        constant: None,
+        reads: values.len(),
+    };
+    if values.iter().all(|value| *value == values[0]) {
+        report.constant = Some(values[0]);
+        return Ok(report);
+    }
+
+    check_data(bus, &values, &mut report.faults);
+    check_address(bus, &addresses, &reads, &mut report);
+    return Ok(report);
+}
+
+fn check_data(bus: &Bus, values: &[u16], faults: &mut Vec<Fault>) {
+    let level = |value: u16, line: usize| value >> line & 1;
+    let mut stuck = vec![false; bus.data.len()];
+    for (line, pin) in bus.data.iter().enumerate() {
+        if values.iter().all(|value| level(*value, line) == 1) {
+            faults.push(Fault::DataStuckHigh(*pin));
+            stuck[line] = true;
+        } else if values.iter().all(|value| level(*value, line) == 0) {
+            faults.push(Fault::DataStuckLow(*pin));
+            stuck[line] = true;
+        }
+    }
+    for a in 0..bus.data.len() {
+        for b in a + 1..bus.data.len() {
+            if !stuck[a] && !stuck[b] && values.iter().all(|value| level(*value, a) == level(*value, b)) {
+                faults.push(Fault::DataShorted(bus.data[a], bus.data[b]));
+            }
+        }
+    }
+}
+
+fn check_address(bus: &Bus, addresses: &[u32], reads: &[Vec<u16>], report: &mut Report) {
+    let moves = |line: usize| reads.iter().any(|row| row[1 + line] != row[0]);
+    let highest = (0..bus.address.len()).rev().find(|line| moves(*line)).unwrap_or(0);
+    for (line, (_, pin)) in bus.address.iter().enumerate() {
+        if line > highest {
+            report.unused.push(*pin);
+        } else if !moves(line) {
+            report.faults.push(Fault::AddressStuck(*pin));
+        }
+    }
+
+    // Shorted lines end up at the same level whichever one is flipped, as long
+    // as they were at the same level before. Reads that all return the same
+    // value are past the end of the ROM, not shorted.
+    for a in 0..=highest {
+        for b in a + 1..=highest {
+            let (bit_a, pin_a) = bus.address[a];
+            let (bit_b, pin_b) = bus.address[b];
+            let same_level: Vec<usize> = (0..addresses.len())
+                .filter(|s| (addresses[*s] >> bit_a & 1) == (addresses[*s] >> bit_b & 1))
+                .collect();
+            let agree = same_level.iter().all(|s| reads[*s][1 + a] == reads[*s][1 + b]);
+            let moved = same_level.iter().any(|s| reads[*s][1 + a] != reads[*s][0]);
+            let varied = same_level.iter().any(|s| reads[*s][1 + a] != reads[same_level[0]][1 + a]);
+            if agree && moved && varied {
+                report.faults.push(Fault::AddressShorted(pin_a, pin_b));
+            }
+        }
+    }
+}
+
+pub fn print_report(report: &Report) {
+    println!("------------ LINES ------------");
+    println!("Connector: {}", report.connector);
+    println!("Reads: {}", report.reads);
+    if let Some(value) = report.constant {
+        println!("Every read returned 0x{:X}, no cart inserted or no line is working.", value);
+    } else if report.faults.is_empty() {
+        println!("No faulty lines found.");
+    }
+    for fault in &report.faults {
+        println!("{}", fault);
+    }
+    if !report.unused.is_empty() {
+        let pins: Vec<String> = report.unused.iter().map(|pin| pin.to_string()).collect();
+        println!("Not used by this ROM, or stuck: {}", pins.join(", "));
+    }
+    println!("-------------------------------");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn pin(signal: &'static str, number: &'static str) -> Pin {
+        return Pin { signal, number };
+    }
+
+    const ADDRESS: [(u8, Pin); 12] = [
+        (0, pin("A0", "1")),
+        (1, pin("A1", "2")),
+        (2, pin("A2", "3")),
+        (3, pin("A3", "4")),
+        (4, pin("A4", "5")),
+        (5, pin("A5", "6")),
+        (6, pin("A6", "7")),
+        (7, pin("A7", "8")),
+        (8, pin("A8", "9")),
+        (9, pin("A9", "10")),
+        (10, pin("A10", "11")),
+        (11, pin("A11", "12")),
+    ];
+
+    const DATA: [Pin; 8] = [
+        pin("D0", "20"),
+        pin("D1", "21"),
+        pin("D2", "22"),
+        pin("D3", "23"),
+        pin("D4", "24"),
+        pin("D5", "25"),
+        pin("D6", "26"),
+        pin("D7", "27"),
+    ];
+
+    const BUS: Bus = Bus { connector: "test", base: 0, address: &ADDRESS, data: &DATA };
+
+    fn rom(size: usize) -> Vec<u16> {
+        let mut state: u32 = 0x1234_5678;
+        let mut rom = Vec::with_capacity(size);
+        for _ in 0..size {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            rom.push((state >> 24) as u16);
+        }
+        return rom;
+    }
+
+    fn probe_rom<F: Fn(u32) -> u16>(read: F) -> Report {
+        return probe(&BUS, |address| Ok(read(address))).unwrap();
+    }
+
+    #[test]
+    fn healthy_cart() {
+        let rom = rom(4096);
+        let report = probe_rom(|address| rom[address as usize & 0xFFF]);
+        assert_eq!(report.faults, vec![]);
+        assert_eq!(report.unused, vec![]);
+        assert_eq!(report.constant, None);
+        assert_eq!(report.reads, SAMPLES * (1 + ADDRESS.len()));
+    }
+
+    #[test]
+    fn rom_smaller_than_bus() {
+        let rom = rom(1024);
+        let report = probe_rom(|address| rom[address as usize & 0x3FF]);
+        assert_eq!(report.faults, vec![]);
+        assert_eq!(report.unused, vec![ADDRESS[10].1, ADDRESS[11].1]);
+    }
+
+    #[test]
+    fn no_cart() {
+        let report = probe_rom(|_| 0xFF);
+        assert_eq!(report.constant, Some(0xFF));
+        assert_eq!(report.faults, vec![]);
+    }
+
+    #[test]
+    fn data_stuck() {
+        let rom = rom(4096);
+        let report = probe_rom(|address| rom[address as usize & 0xFFF] | 0x08);
+        assert_eq!(report.faults, vec![Fault::DataStuckHigh(DATA[3])]);
+        let report = probe_rom(|address| rom[address as usize & 0xFFF] & !0x80);
+        assert_eq!(report.faults, vec![Fault::DataStuckLow(DATA[7])]);
+    }
+
+    #[test]
+    fn data_shorted() {
+        let rom = rom(4096);
+        // Shorted lines both read low when either is driven low.
+        let report = probe_rom(|address| {
+            let value = rom[address as usize & 0xFFF];
+            let level = value >> 1 & value >> 2 & 1;
+            value & !0x06 | level << 1 | level << 2
+        });
+        assert_eq!(report.faults, vec![Fault::DataShorted(DATA[1], DATA[2])]);
+    }
+
+    #[test]
+    fn address_stuck() {
+        let rom = rom(4096);
+        let report = probe_rom(|address| rom[address as usize & 0xFFF & !0x20]);
+        assert_eq!(report.faults, vec![Fault::AddressStuck(ADDRESS[5].1)]);
+    }
+
+    #[test]
+    fn address_shorted() {
+        let rom = rom(4096);
+        let report = probe_rom(|address| {
+            let level = address >> 3 & address >> 4 & 1;
+            let address = address & !0x18 | level << 3 | level << 4;
+            rom[address as usize & 0xFFF]
+        });
+        assert_eq!(report.faults, vec![Fault::AddressShorted(ADDRESS[3].1, ADDRESS[4].1)]);
+    }
+}
@@ -0,0 +1,108 @@
+// Game Boy cartridge model for ROM only, MBC1, MBC3 and MBC5 carts.
+use crate::emulator::Cartridge;
+use crate::error::{Error, Result};
+
+#[derive(PartialEq)]
+enum Mbc {
+    None,
+    Mbc1,
+    Mbc3,
+    Mbc5,
+}
+
+pub struct GbCart {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mbc: Mbc,
+    rom_bank: usize,
+    upper_bits: usize,
+    ram_bank: usize,
+    ram_enabled: bool,
+    mbc1_mode: u8,
+}
+
+impl GbCart {
+    pub fn from_rom(image: &[u8]) -> Result<GbCart> {
+        if image.len() < 0x8000 {
+            return Err(Error::Header(String::from("Game Boy image is too small.")));
+        }
+        let mbc = match image[0x147] {
+            0x00 | 0x08 | 0x09 => Mbc::None,
+            0x01..=0x03 => Mbc::Mbc1,
+            0x0F..=0x13 => Mbc::Mbc3,
+            0x19..=0x1E => Mbc::Mbc5,
+            cart_type => return Err(Error::Unsupported(format!("Cart type 0x{:X} can not be emulated!", cart_type))),
+        };
+        let ram_size = match image[0x149] {
+            0x02 => 8 * 1024,
+            0x03 => 32 * 1024,
+            0x04 => 128 * 1024,
+            0x05 => 64 * 1024,
+            _ => 0,
+        };
+
+        return Ok(GbCart {
+            rom: image.to_vec(),
+            ram: vec![0; ram_size],
+            // Carts without MBC have their RAM always enabled.
+            ram_enabled: mbc == Mbc::None,
+            mbc,
+            rom_bank: 1,
+            upper_bits: 0,
+            ram_bank: 0,
+            mbc1_mode: 0,
+        });
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        let bank = match self.mbc {
+            Mbc::Mbc1 if self.mbc1_mode == 1 => self.upper_bits,
+            Mbc::Mbc1 | Mbc::None => 0,
+            _ => self.ram_bank,
+        };
+        return (bank * 0x2000 + (addr as usize - 0xA000)) % self.ram.len();
+    }
+}
+
+impl Cartridge for GbCart {
+    fn gb_rd(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => self.rom[addr as usize],
+            0x4000..=0x7FFF => {
+                let bank = match self.mbc {
+                    Mbc::None => 1,
+                    Mbc::Mbc1 => (self.upper_bits << 5) | self.rom_bank,
+                    _ => self.rom_bank,
+                };
+                let offset = bank * 0x4000 + (addr as usize - 0x4000);
+                self.rom[offset % self.rom.len()]
+            }
+            0xA000..=0xBFFF if self.ram_enabled && !self.ram.is_empty() => self.ram[self.ram_offset(addr)],
+            _ => 0xFF,
+        }
+    }
+
+    fn gb_wr(&mut self, addr: u16, data: u8) {
+        if (0xA000..=0xBFFF).contains(&addr) {
+            if self.ram_enabled && !self.ram.is_empty() {
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = data;
+            }
+            return;
+        }
+        let data = data as usize;
+        match (&self.mbc, addr) {
+            (Mbc::None, _) => {}
+            (_, 0x0000..=0x1FFF) => self.ram_enabled = (data & 0x0F) == 0x0A,
+            (Mbc::Mbc1, 0x2000..=0x3FFF) => self.rom_bank = (data & 0x1F).max(1),
+            (Mbc::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (data & 0x7F).max(1),
+            (Mbc::Mbc5, 0x2000..=0x2FFF) => self.rom_bank = (self.rom_bank & 0x100) | data,
+            (Mbc::Mbc5, 0x3000..=0x3FFF) => self.rom_bank = (self.rom_bank & 0xFF) | ((data & 1) << 8),
+            (Mbc::Mbc1, 0x4000..=0x5FFF) => self.upper_bits = data & 0x03,
+            (Mbc::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = data & 0x03,
+            (Mbc::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = data & 0x0F,
+            (Mbc::Mbc1, 0x6000..=0x7FFF) => self.mbc1_mode = data as u8 & 1,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// Game Boy Advance cartridge model. Reads past the end of the image return
+// 0xFFFF like an erased flash cart, which is what the size detection in
+// gba::dump_rom looks for.
+use crate::emulator::Cartridge;
+
+pub struct GbaCart {
+    rom: Vec<u8>,
+}
+
+impl GbaCart {
+    pub fn from_rom(image: &[u8]) -> GbaCart {
+        return GbaCart { rom: image.to_vec() };
+    }
+}
+
+impl Cartridge for GbaCart {
+    fn gba_rd(&self, word_addr: u32) -> u16 {
+        let offset = word_addr as usize * 2;
+        let lower = *self.rom.get(offset).unwrap_or(&0xFF) as u16;
+        let upper = *self.rom.get(offset + 1).unwrap_or(&0xFF) as u16;
+        return (upper << 8) | lower;
+    }
+}
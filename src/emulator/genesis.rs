@@ -0,0 +1,41 @@
+// Genesis cartridge model. Images are expected in plain big endian .md/.bin
+// order. Carts that declare odd byte SRAM at $200001 get it mapped there.
+use crate::emulator::Cartridge;
+
+const SRAM_BASE: u32 = 0x200000;
+
+pub struct GenesisCart {
+    rom: Vec<u8>,
+    sram: Vec<u8>,
+}
+
+impl GenesisCart {
+    pub fn from_rom(image: &[u8]) -> GenesisCart {
+        let mut sram_size = 0;
+        // "RA" 0xF8 0x20 start end, see genesis::get_header
+        if image.len() >= 0x1BC && &image[0x1B0..0x1B2] == b"RA" && image[0x1B2] == 0xF8 {
+            let start = u32::from_be_bytes([image[0x1B4], image[0x1B5], image[0x1B6], image[0x1B7]]);
+            let end = u32::from_be_bytes([image[0x1B8], image[0x1B9], image[0x1BA], image[0x1BB]]);
+            if end > start {
+                sram_size = ((end - start) / 2 + 1) as usize;
+            }
+        }
+        return GenesisCart { rom: image.to_vec(), sram: vec![0; sram_size] };
+    }
+}
+
+impl Cartridge for GenesisCart {
+    fn genesis_rd(&self, word_addr: u32) -> u16 {
+        let byte_addr = word_addr * 2;
+        if !self.sram.is_empty() && byte_addr >= SRAM_BASE {
+            let index = ((byte_addr - SRAM_BASE) / 2) as usize;
+            if index < self.sram.len() {
+                // Only D7-D0 are connected to the SRAM
+                return 0xFF00 | self.sram[index] as u16;
+            }
+        }
+        let even = *self.rom.get(byte_addr as usize).unwrap_or(&0xFF) as u16;
+        let odd = *self.rom.get(byte_addr as usize + 1).unwrap_or(&0xFF) as u16;
+        return (even << 8) | odd;
+    }
+}
@@ -0,0 +1,419 @@
+// Software model of the INL Retro-Prog firmware. It answers the same
+// dictionary requests as the real programmer but serves the cartridge from a
+// ROM image, so the dump paths can run without any hardware attached.
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::opcodes;
+use crate::opcodes::errors::*;
+use crate::transport::Transport;
+
+pub mod gb;
+pub mod gba;
+pub mod genesis;
+pub mod nes;
+pub mod snes;
+
+// Dictionary numbers, see shared_dictionaries.h
+const DICT_PINPORT: u8 = 1;
+const DICT_IO: u8 = 2;
+const DICT_NES: u8 = 3;
+const DICT_SNES: u8 = 4;
+const DICT_BUFFER: u8 = 5;
+const DICT_OPER: u8 = 7;
+const DICT_BOOTLOAD: u8 = 10;
+const DICT_GAMEBOY: u8 = 12;
+const DICT_GBA: u8 = 13;
+const DICT_SEGA: u8 = 14;
+
+// pinport CTL_RD operand for CIRAM A10
+const CIA10: u16 = 11;
+
+// operation values
+const OP_STARTDUMP: u16 = 0xD2;
+
+// buffer status values
+const STATUS_EMPTY: u8 = 0x00;
+const STATUS_DUMPED: u8 = 0xD8;
+
+// A cartridge as seen from the programmer's edge connector. Each console only
+// implements the buses it has, everything else reads as open bus.
+pub trait Cartridge {
+    fn nes_cpu_rd(&self, _addr: u16) -> u8 {
+        0xFF
+    }
+    fn nes_cpu_wr(&mut self, _addr: u16, _data: u8) {}
+    fn nes_ppu_rd(&self, _addr: u16) -> u8 {
+        0xFF
+    }
+    fn nes_ppu_wr(&mut self, _addr: u16, _data: u8) {}
+    // Level of the CIRAM A10 pin for the given PPU address.
+    fn nes_ciram_a10(&self, _ppu_addr: u16) -> bool {
+        false
+    }
+
+    // romsel is true when /ROMSEL is asserted.
+    fn snes_rd(&self, _bank: u8, _addr: u16, _romsel: bool) -> u8 {
+        0xFF
+    }
+
+    fn gb_rd(&self, _addr: u16) -> u8 {
+        0xFF
+    }
+    fn gb_wr(&mut self, _addr: u16, _data: u8) {}
+
+    // GBA ROM is addressed in 16 bit words.
+    fn gba_rd(&self, _word_addr: u32) -> u16 {
+        0xFFFF
+    }
+
+    // Genesis words as they appear on D15-D0, even byte in the upper half.
+    fn genesis_rd(&self, _word_addr: u32) -> u16 {
+        0xFFFF
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Buffer {
+    id: u8,
+    size: usize,
+    mem: u8,
+    map: u8,
+    page_num: u16,
+    reload: u16,
+}
+
+struct Firmware {
+    cart: Box<dyn Cartridge>,
+    addr: u16,
+    operation: u16,
+    buffers: [Buffer; 2],
+    cur_buff: usize,
+    snes_bank: u8,
+    gba_addr: u32,
+    genesis_bank: u32,
+}
+
+pub struct Emulator {
+    firmware: RefCell<Firmware>,
+}
+
+impl Emulator {
+    pub fn new(cart: Box<dyn Cartridge>) -> Emulator {
+        Emulator {
+            firmware: RefCell::new(Firmware {
+                cart,
+                addr: 0,
+                operation: 0,
+                buffers: [Buffer::default(); 2],
+                cur_buff: 0,
+                snes_bank: 0,
+                gba_addr: 0,
+                genesis_bank: 0,
+            }),
+        }
+    }
+}
+
+// Build an emulated programmer with the ROM image in filename inserted in the
+// given console port.
+pub fn load(console: &str, filename: &str) -> crate::error::Result<Emulator> {
+    let image = fs::read(filename)
+        .map_err(|err| Error::Io(io::Error::new(err.kind(), format!("Could not read {}: {}", filename, err))))?;
+    let cart: Box<dyn Cartridge> = match console.to_lowercase().as_str() {
+        "nes" => {
+            // A save next to the image is loaded into WRAM, like emulators do.
+            let save = fs::read(Path::new(filename).with_extension("sav")).unwrap_or_default();
+            Box::new(nes::NesCart::from_ines(&image)?.with_save(&save))
+        }
+        "snes" => Box::new(snes::SnesCart::from_rom(&image)?),
+        "gb" => Box::new(gb::GbCart::from_rom(&image)?),
+        "gba" => Box::new(gba::GbaCart::from_rom(&image)),
+        "genesis" => Box::new(genesis::GenesisCart::from_rom(&image)),
+        _ => return Err(Error::Unsupported(format!("Console {} can not be emulated!", console))),
+    };
+    return Ok(Emulator::new(cart));
+}
+
+impl Transport for Emulator {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> crate::error::Result<usize> {
+        let mut firmware = self.firmware.borrow_mut();
+        let opcode = value & 0xFF;
+        let misc = value >> 8;
+        let operand = index;
+
+        let result = match request {
+            DICT_PINPORT => firmware.pinport(opcode, operand),
+            DICT_IO => firmware.io(opcode),
+            DICT_NES => firmware.nes(opcode, operand, misc),
+            DICT_SNES => firmware.snes(opcode, operand),
+            DICT_BUFFER => {
+                if opcode == opcodes::buffer::BUFF_PAYLOAD {
+                    // Payload has no error code or length, it is all data.
+                    return Ok(firmware.buff_payload(buf));
+                }
+                firmware.buffer(opcode, operand, misc)
+            }
+            DICT_OPER => firmware.oper(opcode, operand),
+            DICT_BOOTLOAD => firmware.bootload(opcode),
+            DICT_GAMEBOY => firmware.gameboy(opcode, operand, misc),
+            DICT_GBA => firmware.gba(opcode, operand, misc),
+            DICT_SEGA => firmware.sega(opcode, operand),
+            _ => Err(ERR_UNKN_DICTIONARY),
+        };
+
+        return Ok(respond(buf, result));
+    }
+}
+
+// Responses are error code, data length and then the data.
+fn respond(buf: &mut [u8], result: Result<Vec<u8>, u8>) -> usize {
+    let data = match result {
+        Ok(data) => {
+            buf[0] = SUCCESS;
+            data
+        }
+        Err(error_code) => {
+            buf[0] = error_code;
+            Vec::new()
+        }
+    };
+    let mut len = 1;
+    if buf.len() > 1 {
+        buf[1] = data.len() as u8;
+        len += 1;
+    }
+    for (dst, src) in buf.iter_mut().skip(2).zip(data.iter()) {
+        *dst = *src;
+        len += 1;
+    }
+    return len;
+}
+
+impl Firmware {
+    fn pinport(&mut self, opcode: u16, operand: u16) -> Result<Vec<u8>, u8> {
+        match opcode {
+            opcodes::pinport::ADDR_SET => {
+                self.addr = operand;
+                Ok(vec![])
+            }
+            opcodes::pinport::CTL_RD => {
+                let level = operand == CIA10 && self.cart.nes_ciram_a10(self.addr);
+                Ok(vec![level as u8, 0])
+            }
+            _ => Err(ERR_UNKN_PINPORT_OPCODE),
+        }
+    }
+
+    fn io(&mut self, opcode: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::io::*;
+        match opcode {
+            IO_RESET | NES_INIT | SNES_INIT | GAMEBOY_INIT | GBA_INIT | SEGA_INIT => {
+                self.addr = 0;
+                self.snes_bank = 0;
+                self.gba_addr = 0;
+                self.genesis_bank = 0;
+                Ok(vec![])
+            }
+            GB_POWER_5V | GB_POWER_3V => Ok(vec![]),
+            // EXP0 is pulled up on every board we model.
+            EXP0_PULLUP_TEST => Ok(vec![1]),
+            _ => Err(ERR_UNKN_IO_OPCODE),
+        }
+    }
+
+    fn nes(&mut self, opcode: u16, operand: u16, misc: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::nes::*;
+        match opcode {
+            // Flash commands through EXP0 never reach a mask ROM board.
+            DISCRETE_EXP0_PRGROM_WR => Ok(vec![]),
+            NES_CPU_WR => {
+                self.cart.nes_cpu_wr(operand, misc as u8);
+                Ok(vec![])
+            }
+            NES_PPU_WR => {
+                self.cart.nes_ppu_wr(operand, misc as u8);
+                Ok(vec![])
+            }
+            NES_MMC1_WR => {
+                // The firmware shifts the value into the MMC1 one bit at a time.
+                for bit in 0..5 {
+                    self.cart.nes_cpu_wr(operand, ((misc >> bit) & 1) as u8);
+                }
+                Ok(vec![])
+            }
+            NES_CPU_RD => Ok(vec![self.cart.nes_cpu_rd(operand)]),
+            NES_PPU_RD => Ok(vec![self.cart.nes_ppu_rd(operand)]),
+            _ => Err(ERR_UNKN_NES_OPCODE),
+        }
+    }
+
+    fn snes(&mut self, opcode: u16, operand: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::snes::*;
+        match opcode {
+            SNES_SET_BANK => {
+                self.snes_bank = operand as u8;
+                Ok(vec![])
+            }
+            SNES_ROM_RD => Ok(vec![self.cart.snes_rd(self.snes_bank, operand, true)]),
+            _ => Err(ERR_UNKN_SNES_OPCODE),
+        }
+    }
+
+    fn buffer(&mut self, opcode: u16, operand: u16, misc: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::buffer::*;
+        match opcode {
+            RAW_BUFFER_RESET => {
+                self.buffers = [Buffer::default(); 2];
+                self.cur_buff = 0;
+                Ok(vec![])
+            }
+            ALLOCATE_BUFFER0 | ALLOCATE_BUFFER1 => {
+                let buff = &mut self.buffers[(opcode == ALLOCATE_BUFFER1) as usize];
+                // operand is id << 8 | base bank, misc is the number of 32 byte banks
+                buff.id = (operand >> 8) as u8;
+                buff.size = misc as usize * 32;
+                Ok(vec![])
+            }
+            SET_RELOAD_PAGENUM0 | SET_RELOAD_PAGENUM1 => {
+                let buff = &mut self.buffers[(opcode == SET_RELOAD_PAGENUM1) as usize];
+                buff.page_num = operand;
+                buff.reload = misc;
+                Ok(vec![])
+            }
+            SET_MEM_N_PART => {
+                let buff = self.buffers.get_mut(misc as usize).ok_or(ERR_BUFN_DOES_NOT_EXIST)?;
+                buff.mem = (operand >> 8) as u8;
+                Ok(vec![])
+            }
+            SET_MAP_N_MAPVAR => {
+                let buff = self.buffers.get_mut(misc as usize).ok_or(ERR_BUFN_DOES_NOT_EXIST)?;
+                buff.map = (operand >> 8) as u8;
+                Ok(vec![])
+            }
+            GET_CUR_BUFF_STATUS => {
+                // Reading from the model is instant so a buffer is always ready.
+                if self.operation == OP_STARTDUMP {
+                    Ok(vec![STATUS_DUMPED])
+                } else {
+                    Ok(vec![STATUS_EMPTY])
+                }
+            }
+            _ => Err(ERR_UNKN_BUFF_OPCODE),
+        }
+    }
+
+    fn buff_payload(&mut self, buf: &mut [u8]) -> usize {
+        let buff = self.buffers[self.cur_buff];
+        let len = buff.size.min(buf.len());
+        // For buffers smaller than a page the MSB of the id is A7.
+        let start = ((buff.page_num as u32) << 8) | (buff.id & 0x80) as u32;
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.page_rd(buff.mem as u16, buff.map as u32, start + i as u32);
+        }
+        self.buffers[self.cur_buff].page_num = buff.page_num.wrapping_add(buff.reload);
+        self.cur_buff = (self.cur_buff + 1) % self.buffers.len();
+        return len;
+    }
+
+    // Read one byte of a buffer dump, offset counts from the first page.
+    fn page_rd(&self, mem: u16, map: u32, offset: u32) -> u8 {
+        use crate::opcodes::buffer::*;
+        match mem {
+            NESCPU_4KB => self.cart.nes_cpu_rd(((map << 12) + offset) as u16),
+            NESPPU_1KB => self.cart.nes_ppu_rd(((map << 10) + offset) as u16),
+            SNESROM_PAGE => self.cart.snes_rd(self.snes_bank, ((map << 8) + offset) as u16, true),
+            SNESSYS_PAGE => self.cart.snes_rd(self.snes_bank, ((map << 8) + offset) as u16, false),
+            GAMEBOY_PAGE => self.cart.gb_rd(((map << 8) + offset) as u16),
+            GBA_ROM_PAGE => {
+                let byte_addr = self.gba_addr * 2 + offset;
+                let word = self.cart.gba_rd(byte_addr >> 1);
+                (word >> ((byte_addr & 1) * 8)) as u8
+            }
+            GENESIS_ROM_PAGE0 | GENESIS_ROM_PAGE1 => {
+                let mut byte_addr = self.genesis_bank * 0x20000 + offset;
+                if mem == GENESIS_ROM_PAGE1 {
+                    byte_addr += 0x10000;
+                }
+                let word = self.cart.genesis_rd(byte_addr >> 1);
+                if byte_addr & 1 == 0 {
+                    (word >> 8) as u8
+                } else {
+                    word as u8
+                }
+            }
+            // Only the odd (lower) byte of each word is read.
+            GENESIS_RAM_PAGE => self.cart.genesis_rd(self.genesis_bank * 0x10000 + offset) as u8,
+            _ => 0xFF,
+        }
+    }
+
+    fn oper(&mut self, opcode: u16, operand: u16) -> Result<Vec<u8>, u8> {
+        match opcode {
+            opcodes::operation::SET_OPERATION => {
+                self.operation = operand;
+                self.cur_buff = 0;
+                Ok(vec![])
+            }
+            _ => Err(ERR_UNKN_OPER_OPCODE),
+        }
+    }
+
+    fn bootload(&mut self, opcode: u16) -> Result<Vec<u8>, u8> {
+        match opcode {
+            opcodes::bootload::GET_APP_VER => Ok(vec![opcodes::bootload::APP_VERSION]),
+            _ => Err(ERR_UNKN_BOOTLOAD_OPCODE),
+        }
+    }
+
+    fn gameboy(&mut self, opcode: u16, operand: u16, misc: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::gb::*;
+        match opcode {
+            GAMEBOY_RD => Ok(vec![self.cart.gb_rd(operand)]),
+            GAMEBOY_WR => {
+                self.cart.gb_wr(operand, misc as u8);
+                Ok(vec![])
+            }
+            _ => Err(ERR_UNKN_GAMEBOY_OPCODE),
+        }
+    }
+
+    fn gba(&mut self, opcode: u16, operand: u16, misc: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::gba::*;
+        match opcode {
+            GBA_RD => {
+                // The cart increments its latched address after every read.
+                let word = self.cart.gba_rd(self.gba_addr);
+                self.gba_addr += 1;
+                Ok(vec![word as u8, (word >> 8) as u8])
+            }
+            GBA_LATCH_ADDR => {
+                // operand is A0-15, misc A16-23 of the word address
+                self.gba_addr = ((misc as u32 & 0xFF) << 16) | operand as u32;
+                Ok(vec![])
+            }
+            GBA_RELEASE_BUS => Ok(vec![]),
+            _ => Err(ERR_UNKN_GBA_OPCODE),
+        }
+    }
+
+    fn sega(&mut self, opcode: u16, operand: u16) -> Result<Vec<u8>, u8> {
+        use crate::opcodes::genesis::*;
+        match opcode {
+            GEN_ROM_RD => {
+                let word = self.cart.genesis_rd(self.genesis_bank * 0x10000 + operand as u32);
+                // D7-D0 first
+                Ok(vec![word as u8, (word >> 8) as u8])
+            }
+            GEN_SET_BANK => {
+                // Bank selects A17-A23
+                self.genesis_bank = operand as u32;
+                Ok(vec![])
+            }
+            _ => Err(ERR_UNKN_SEGA_OPCODE),
+        }
+    }
+}
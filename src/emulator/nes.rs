@@ -0,0 +1,284 @@
+// NES cartridge model with the mappers this crate can dump.
+// Bus conflicts on the discrete boards are not modelled.
+use crate::emulator::Cartridge;
+use crate::error::{Error, Result};
+use crate::nes::Mirroring;
+
+enum Mapper {
+    Nrom,
+    Cnrom { chr_bank: u8 },
+    Unrom { prg_bank: u8 },
+    Mmc1 { shift: u8, count: u8, control: u8, chr0: u8, chr1: u8, prg: u8 },
+    Mmc3 { bank_select: u8, regs: [u8; 8], mirroring: u8, wram_protect: u8 },
+}
+
+pub struct NesCart {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    // WRAM at CPU $6000-$7FFF, empty on boards without it.
+    wram: Vec<u8>,
+    mirroring: Mirroring,
+    mapper: Mapper,
+}
+
+impl NesCart {
+    // Build a cart from an iNES or NES 2.0 image.
+    pub fn from_ines(image: &[u8]) -> Result<NesCart> {
+        if image.len() < 16 || &image[0..4] != b"NES\x1A" {
+            return Err(Error::Header(String::from("Not an iNES image.")));
+        }
+        let prg_size = image[4] as usize * 16 * 1024;
+        let chr_size = image[5] as usize * 8 * 1024;
+        let trainer = if image[6] & 0x04 != 0 { 512 } else { 0 };
+        let number = (image[6] >> 4) | (image[7] & 0xF0);
+
+        let prg_start = 16 + trainer;
+        let chr_start = prg_start + prg_size;
+        if image.len() < chr_start + chr_size {
+            return Err(Error::Header(String::from("iNES image is shorter than its header says.")));
+        }
+
+        let mapper = match number {
+            0 => Mapper::Nrom,
+            1 => Mapper::Mmc1 { shift: 0, count: 0, control: 0x0C, chr0: 0, chr1: 0, prg: 0 },
+            2 => Mapper::Unrom { prg_bank: 0 },
+            3 => Mapper::Cnrom { chr_bank: 0 },
+            4 => Mapper::Mmc3 { bank_select: 0, regs: [0; 8], mirroring: 0, wram_protect: 0 },
+            _ => return Err(Error::Unsupported(format!("Mapper {} can not be emulated!", number))),
+        };
+
+        let mirroring = if image[6] & 0x01 != 0 { Mirroring::VERT } else { Mirroring::HORZ };
+
+        // Boards without CHR-ROM have 8KB of CHR-RAM.
+        let chr_ram = chr_size == 0;
+        let chr = if chr_ram { vec![0; 8 * 1024] } else { image[chr_start..chr_start + chr_size].to_vec() };
+
+        // NES 2.0 gives the PRG-RAM and PRG-NVRAM sizes as shift counts, iNES
+        // images of MMC1 and MMC3 carts are taken to have 8KB.
+        let wram_size = if image[7] & 0x0C == 0x08 {
+            let size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            size(image[10] & 0x0F) + size(image[10] >> 4)
+        } else if number == 1 || number == 4 {
+            8 * 1024
+        } else {
+            0
+        };
+
+        return Ok(NesCart {
+            prg: image[prg_start..chr_start].to_vec(),
+            chr,
+            chr_ram,
+            wram: vec![0; wram_size],
+            mirroring,
+            mapper,
+        });
+    }
+
+    // Fill WRAM with a save, as if the battery had kept it.
+    pub fn with_save(mut self, save: &[u8]) -> NesCart {
+        let len = save.len().min(self.wram.len());
+        self.wram[..len].copy_from_slice(&save[..len]);
+        return self;
+    }
+
+    // Where addr is in WRAM, None while the mapper has it disabled.
+    fn wram_offset(&self, addr: u16) -> Option<usize> {
+        if self.wram.is_empty() {
+            return None;
+        }
+        let addr = addr as usize & 0x1FFF;
+        let bank = match self.mapper {
+            // MMC1B disables it with PRG register bit 4, SNROM also with CHR bit 4.
+            Mapper::Mmc1 { prg, .. } if prg & 0x10 != 0 => return None,
+            Mapper::Mmc1 { chr0, .. } => match self.wram.len() / 0x2000 {
+                0 | 1 if chr0 & 0x10 != 0 => return None,
+                0 | 1 => 0,
+                // SOROM
+                2 => (chr0 as usize >> 3) & 0x01,
+                // SXROM
+                _ => (chr0 as usize >> 2) & 0x03,
+            },
+            Mapper::Mmc3 { wram_protect, .. } if wram_protect & 0x80 == 0 => return None,
+            _ => 0,
+        };
+        return Some((bank * 0x2000 + addr) % self.wram.len());
+    }
+
+    fn prg_byte(&self, bank_16k: usize, addr: u16) -> u8 {
+        let offset = bank_16k * 0x4000 + (addr as usize & 0x3FFF);
+        return self.prg[offset % self.prg.len()];
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x1FFF;
+        let offset = match self.mapper {
+            Mapper::Cnrom { chr_bank } => chr_bank as usize * 0x2000 + addr,
+            Mapper::Mmc1 { control, chr0, chr1, .. } => {
+                if control & 0x10 == 0 {
+                    // 8KB mode ignores the low bit
+                    (chr0 as usize & 0x1E) * 0x1000 + addr
+                } else if addr < 0x1000 {
+                    chr0 as usize * 0x1000 + addr
+                } else {
+                    chr1 as usize * 0x1000 + (addr & 0x0FFF)
+                }
+            }
+            Mapper::Mmc3 { bank_select, regs, .. } => {
+                // A12 inversion swaps the 2KB and 1KB halves
+                let addr = if bank_select & 0x80 != 0 { addr ^ 0x1000 } else { addr };
+                let bank_1k = match addr >> 10 {
+                    0 => regs[0] & 0xFE,
+                    1 => regs[0] | 0x01,
+                    2 => regs[1] & 0xFE,
+                    3 => regs[1] | 0x01,
+                    n => regs[n - 2],
+                };
+                bank_1k as usize * 0x400 + (addr & 0x3FF)
+            }
+            _ => addr,
+        };
+        return offset % self.chr.len();
+    }
+
+    fn mmc1_write(&mut self, addr: u16, data: u8) {
+        if let Mapper::Mmc1 { shift, count, control, chr0, chr1, prg } = &mut self.mapper {
+            if data & 0x80 != 0 {
+                *shift = 0;
+                *count = 0;
+                *control |= 0x0C;
+                return;
+            }
+            *shift |= (data & 1) << *count;
+            *count += 1;
+            if *count < 5 {
+                return;
+            }
+            let value = *shift;
+            *shift = 0;
+            *count = 0;
+            match addr & 0xE000 {
+                0x8000 => *control = value,
+                0xA000 => *chr0 = value,
+                0xC000 => *chr1 = value,
+                _ => *prg = value,
+            }
+        }
+    }
+
+    fn mmc3_write(&mut self, addr: u16, data: u8) {
+        if let Mapper::Mmc3 { bank_select, regs, mirroring, wram_protect } = &mut self.mapper {
+            match (addr & 0xE000, addr & 1) {
+                (0x8000, 0) => *bank_select = data,
+                (0x8000, _) => regs[(*bank_select & 0x07) as usize] = data,
+                (0xA000, 0) => *mirroring = data & 1,
+                (0xA000, _) => *wram_protect = data,
+                // IRQ registers don't affect reads
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Cartridge for NesCart {
+    fn nes_cpu_rd(&self, addr: u16) -> u8 {
+        if addr < 0x8000 {
+            let wram = if addr >= 0x6000 { self.wram_offset(addr) } else { None };
+            return wram.map_or(0xFF, |offset| self.wram[offset]);
+        }
+        let last = self.prg.len() / 0x4000 - 1;
+        let upper = addr >= 0xC000;
+        match &self.mapper {
+            Mapper::Nrom | Mapper::Cnrom { .. } => self.prg_byte(upper as usize, addr),
+            Mapper::Unrom { prg_bank } => {
+                if upper {
+                    self.prg_byte(last, addr)
+                } else {
+                    self.prg_byte(*prg_bank as usize, addr)
+                }
+            }
+            Mapper::Mmc1 { control, prg, .. } => {
+                let bank = (*prg & 0x0F) as usize;
+                match (control >> 2) & 0x03 {
+                    // 32KB mode ignores the low bit
+                    0 | 1 => self.prg_byte((bank & 0x0E) + upper as usize, addr),
+                    2 => self.prg_byte(if upper { bank } else { 0 }, addr),
+                    _ => self.prg_byte(if upper { last } else { bank }, addr),
+                }
+            }
+            Mapper::Mmc3 { bank_select, regs, .. } => {
+                let second_last = self.prg.len() / 0x2000 - 2;
+                let swap = bank_select & 0x40 != 0;
+                let bank_8k = match (addr >> 13) & 0x03 {
+                    0 if swap => second_last,
+                    0 => regs[6] as usize,
+                    1 => regs[7] as usize,
+                    2 if swap => regs[6] as usize,
+                    2 => second_last,
+                    _ => second_last + 1,
+                };
+                let offset = bank_8k * 0x2000 + (addr as usize & 0x1FFF);
+                self.prg[offset % self.prg.len()]
+            }
+        }
+    }
+
+    fn nes_cpu_wr(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            // MMC3 can deny writes with WRAM enabled.
+            let protected = matches!(self.mapper, Mapper::Mmc3 { wram_protect, .. } if wram_protect & 0x40 != 0);
+            if addr >= 0x6000 && !protected {
+                if let Some(offset) = self.wram_offset(addr) {
+                    self.wram[offset] = data;
+                }
+            }
+            return;
+        }
+        match &mut self.mapper {
+            Mapper::Nrom => {}
+            Mapper::Cnrom { chr_bank } => *chr_bank = data,
+            Mapper::Unrom { prg_bank } => *prg_bank = data,
+            Mapper::Mmc1 { .. } => self.mmc1_write(addr, data),
+            Mapper::Mmc3 { .. } => self.mmc3_write(addr, data),
+        }
+    }
+
+    fn nes_ppu_rd(&self, addr: u16) -> u8 {
+        if addr >= 0x2000 {
+            return 0xFF;
+        }
+        return self.chr[self.chr_offset(addr)];
+    }
+
+    fn nes_ppu_wr(&mut self, addr: u16, data: u8) {
+        if addr < 0x2000 && self.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.chr[offset] = data;
+        }
+    }
+
+    fn nes_ciram_a10(&self, ppu_addr: u16) -> bool {
+        let mirroring = match &self.mapper {
+            Mapper::Mmc1 { control, .. } => match control & 0x03 {
+                0 => Mirroring::SCNA,
+                1 => Mirroring::SCNB,
+                2 => Mirroring::VERT,
+                _ => Mirroring::HORZ,
+            },
+            Mapper::Mmc3 { mirroring, .. } => {
+                if *mirroring == 0 {
+                    Mirroring::VERT
+                } else {
+                    Mirroring::HORZ
+                }
+            }
+            _ => self.mirroring,
+        };
+        match mirroring {
+            Mirroring::VERT => ppu_addr & 0x0400 != 0,
+            Mirroring::HORZ => ppu_addr & 0x0800 != 0,
+            Mirroring::SCNA => false,
+            Mirroring::SCNB => true,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// SNES cartridge model for LoROM and HiROM boards with optional SRAM.
+use crate::emulator::Cartridge;
+use crate::error::{Error, Result};
+
+pub struct SnesCart {
+    rom: Vec<u8>,
+    sram: Vec<u8>,
+    hirom: bool,
+}
+
+impl SnesCart {
+    // Build a cart from a .sfc/.smc image, a 512 byte copier header is skipped.
+    pub fn from_rom(image: &[u8]) -> Result<SnesCart> {
+        let rom = if image.len() % 1024 == 512 { &image[512..] } else { image };
+        if rom.len() < 0x8000 {
+            return Err(Error::Header(String::from("SNES image is too small.")));
+        }
+
+        let hirom = header_score(rom, 0xFFC0) > header_score(rom, 0x7FC0);
+        let header = if hirom { 0xFFC0 } else { 0x7FC0 };
+
+        // 0x18 is the SRAM size, 1KB << n
+        let sram_size = match rom[header + 0x18] {
+            0 => 0,
+            n if n <= 7 => 1024 << n,
+            _ => 0,
+        };
+
+        return Ok(SnesCart { rom: rom.to_vec(), sram: vec![0; sram_size], hirom });
+    }
+}
+
+// How much the bytes at addr look like an internal header.
+fn header_score(rom: &[u8], addr: usize) -> u8 {
+    if rom.len() < addr + 0x20 {
+        return 0;
+    }
+    let mut score = 0;
+    let map_mode = rom[addr + 0x15] & 0x0F;
+    if (addr == 0x7FC0 && map_mode == 0x00) || (addr == 0xFFC0 && (map_mode == 0x01 || map_mode == 0x0A)) {
+        score += 2;
+    }
+    let complement = rom[addr + 0x1C] as u16 | (rom[addr + 0x1D] as u16) << 8;
+    let checksum = rom[addr + 0x1E] as u16 | (rom[addr + 0x1F] as u16) << 8;
+    if complement ^ checksum == 0xFFFF {
+        score += 4;
+    }
+    if rom[addr..addr + 21].iter().all(|c| (0x20..0x7F).contains(c)) {
+        score += 1;
+    }
+    return score;
+}
+
+impl Cartridge for SnesCart {
+    fn snes_rd(&self, bank: u8, addr: u16, romsel: bool) -> u8 {
+        let addr = addr as usize;
+        if self.hirom {
+            if romsel {
+                let offset = ((bank as usize & 0x3F) << 16) | addr;
+                return self.rom[offset % self.rom.len()];
+            }
+            // SRAM at $20-$3F:6000-7FFF
+            if !self.sram.is_empty() && (bank & 0x60) == 0x20 && (0x6000..0x8000).contains(&addr) {
+                let offset = (bank as usize & 0x0F) * 0x2000 + addr - 0x6000;
+                return self.sram[offset % self.sram.len()];
+            }
+        } else if romsel {
+            // SRAM at $70-$7D:0000-7FFF
+            if !self.sram.is_empty() && (bank & 0x70) == 0x70 && addr < 0x8000 {
+                let offset = (bank as usize & 0x0F) * 0x8000 + addr;
+                return self.sram[offset % self.sram.len()];
+            }
+            let offset = ((bank as usize & 0x7F) << 15) | (addr & 0x7FFF);
+            return self.rom[offset % self.rom.len()];
+        }
+        return 0xFF;
+    }
+}
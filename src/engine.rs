@@ -0,0 +1,208 @@
+// The one place that talks the buffer dump protocol with the firmware.
+// Callers describe what to read with a Region and where the bytes go with a
+// Sink, the engine does the setup, the polling and the cleanup.
+use std::io::prelude::*;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::buffer;
+use crate::error::{Error, Result};
+use crate::operation;
+use crate::progress::Tracker;
+use crate::transport::Transport;
+use crate::verify;
+
+// Size of each of the two firmware buffers used for dumping. The firmware
+// fills one buffer while the other is read out over USB.
+const DUMP_BUFF_SIZE: usize = 256;
+
+// mem is the memory part (NESCPU_4KB, SNESROM_PAGE, ...), map is the base
+// the firmware adds to the page number (its unit depends on mem) and size_kb
+// is how much to read from there.
+#[derive(Clone, Copy, Debug)]
+pub struct Region {
+    pub mem: u16,
+    pub map: u16,
+    pub size_kb: u16,
+}
+
+// Receives the dumped bytes in order, one buffer at a time.
+pub trait Sink: Send {
+    fn accept(&mut self, payload: &[u8]) -> Result<()>;
+}
+
+// Any writer is a sink, this includes &mut [u8] for dumping into a slice.
+impl<W: Write + Send> Sink for W {
+    fn accept(&mut self, payload: &[u8]) -> Result<()> {
+        self.write_all(payload)?;
+        return Ok(());
+    }
+}
+
+// Hands every buffer to a closure.
+pub struct Callback<F>(pub F);
+
+impl<F: FnMut(&[u8]) -> Result<()> + Send> Sink for Callback<F> {
+    fn accept(&mut self, payload: &[u8]) -> Result<()> {
+        (self.0)(payload)
+    }
+}
+
+// Dump region into sink. With verification the region is read once per pass
+// and only the merged result reaches the sink.
+pub fn run<T: Transport, S: Sink>(device_handle: &T, region: Region, mut sink: S, progress: &mut Tracker) -> Result<()> {
+    let verify = progress.verify();
+    if verify.passes <= 1 {
+        run_once(device_handle, region, sink, progress)?;
+        progress.add_dumped(region.size_kb as usize * 1024);
+        return Ok(());
+    }
+
+    let mut passes = Vec::new();
+    for _ in 0..verify.passes {
+        let mut pass = Vec::with_capacity(region.size_kb as usize * 1024);
+        run_once(device_handle, region, &mut pass, progress)?;
+        passes.push(pass);
+    }
+    let merged = verify::merge(&passes, verify.mode, progress.dumped(), progress.bank())?;
+    sink.accept(&merged)?;
+    progress.add_dumped(merged.len());
+    return Ok(());
+}
+
+// One read of region. The firmware buffers are reset again on every exit
+// path so a failed dump does not leave the programmer mid-operation.
+fn run_once<T: Transport, S: Sink>(device_handle: &T, region: Region, sink: S, progress: &mut Tracker) -> Result<()> {
+    let result = start(device_handle, region).and_then(|_| stream(device_handle, region, sink, progress));
+    let cleanup = stop(device_handle);
+    return result.and(cleanup);
+}
+
+fn start<T: Transport>(device_handle: &T, region: Region) -> Result<()> {
+    let buff0 = 0;
+    let buff1 = 1;
+
+    operation::set_operation(device_handle, 0x01)?;
+
+    buffer::raw_buffer_reset(device_handle)?;
+
+    allocate(device_handle, 2, DUMP_BUFF_SIZE as u16)?;
+
+    buffer::set_mem_n_part(device_handle, (region.mem << 8) | 0xDD, buff0)?;
+    buffer::set_mem_n_part(device_handle, (region.mem << 8) | 0xDD, buff1)?;
+
+    buffer::set_map_n_mapvar(device_handle, region.map << 8, buff0)?;
+    buffer::set_map_n_mapvar(device_handle, region.map << 8, buff1)?;
+
+    operation::set_operation(device_handle, 0xD2)?;
+    return Ok(());
+}
+
+fn stop<T: Transport>(device_handle: &T) -> Result<()> {
+    operation::set_operation(device_handle, 0x01)?;
+    buffer::raw_buffer_reset(device_handle)?;
+    return Ok(());
+}
+
+// The payloads are handed to a sink thread so the next buffer is polled and
+// read while the previous one is written out.
+fn stream<T: Transport, S: Sink>(
+    device_handle: &T,
+    region: Region,
+    mut sink: S,
+    progress: &mut Tracker,
+) -> Result<()> {
+    let num_buffers = (region.size_kb as usize) * 1024 / DUMP_BUFF_SIZE;
+    let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(16);
+    let (read_result, sink_result) = thread::scope(|scope| {
+        let consumer = scope.spawn(move || -> Result<()> {
+            for payload in receiver {
+                sink.accept(&payload)?;
+            }
+            return Ok(());
+        });
+
+        let mut read_result = Ok(());
+        for _ in 0..num_buffers {
+            let mut payload = vec![0; DUMP_BUFF_SIZE];
+            read_result = wait_for_dumped(device_handle)
+                .and_then(|_| buffer::buff_payload(device_handle, &mut payload));
+            // A failed send means the sink stopped, its error is reported below.
+            if read_result.is_err() || sender.send(payload).is_err() {
+                break;
+            }
+            progress.advance(DUMP_BUFF_SIZE);
+        }
+        drop(sender);
+        let sink_result = consumer.join().expect("dump sink thread panicked");
+        return (read_result, sink_result);
+    });
+
+    read_result?;
+    return sink_result;
+}
+
+// Poll until the current buffer has been dumped by the firmware.
+fn wait_for_dumped<T: Transport>(device_handle: &T) -> Result<()> {
+    let mut buff_status = 0;
+    for _ in 0..20 {
+        buff_status = buffer::get_cur_buff_status(device_handle)?;
+        // DUMPED = 0xD8
+        if buff_status == 0xD8 {
+            return Ok(());
+        }
+    }
+    return Err(Error::BufferTimeout { status: buff_status });
+}
+
+fn allocate<T: Transport>(
+    device_handle: &T,
+    num_buffers: u16,
+    buff_size: u16,
+) -> Result<()> {
+    let buff0basebank = 0;
+    // shared_dict_buffer.h:  #define RAW_BANK_SIZE   32
+    // local numbanks = buff_size/ (op_buffer["RAW_BANK_SIZE"])
+    let numbanks = buff_size / 32;
+    let buff1basebank = numbanks;
+
+    let buff0id;
+    let buff1id;
+    let reload;
+    let buff0_firstpage;
+    let buff1_firstpage;
+
+    if (num_buffers == 2) && (buff_size == 128) {
+        //buff0 dumps first half of page, buff1 dumps second half, repeat
+        //MSB tells buffer value of A7 when operating
+        buff0id = 0x00;
+        buff1id = 0x80;
+        //set reload (value added to page_num after each load/dump to sum of buffers
+        // 2 * 128 = 256 -> reload = 1
+        reload = 0x01;
+        //set first page
+        buff0_firstpage = 0x0000;
+        buff1_firstpage = 0x0000;
+    } else if (num_buffers == 2) && (buff_size == 256) {
+        //buff0 dumps even pages, buff1 dumps odd pages
+        //buffer id not used for addressing both id zero for now..
+        buff0id = 0x00;
+        buff1id = 0x00;
+        //set reload (value added to page_num after each load/dump to sum of buffers
+        // 2 * 256 = 512 -> reload = 2
+        reload = 0x02;
+        //set first page of each buffer
+        buff0_firstpage = 0x0000;
+        buff1_firstpage = 0x0001;
+    } else {
+        return Err(Error::Unsupported(format!(
+            "Not setup to handle {} buffers of {} bytes",
+            num_buffers, buff_size
+        )));
+    }
+    buffer::allocate_buffer0(device_handle, (buff0id << 8) | buff0basebank, numbanks)?;
+    buffer::allocate_buffer1(device_handle, (buff1id << 8) | buff1basebank, numbanks)?;
+    buffer::set_reload_pagenum0(device_handle, buff0_firstpage, reload)?;
+    buffer::set_reload_pagenum1(device_handle, buff1_firstpage, reload)?;
+    return Ok(());
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::io;
+
+use crate::opcodes::errors::*;
+
+#[derive(Debug)]
+pub enum Error {
+    // The USB transfer itself failed or came back short.
+    Usb(String),
+    // No usable programmer, or one we can't talk to.
+    Device(String),
+    // The firmware answered a request with a non-zero error code.
+    Firmware { dictionary: u8, opcode: u16, code: u8 },
+    // The firmware never reported a dumped buffer.
+    BufferTimeout { status: u8 },
+    // The cartridge header could not be parsed.
+    Header(String),
+    // The cart, mapper or option is not something we can handle.
+    Unsupported(String),
+    // The firmware is not one this host knows how to talk to.
+    Incompatible(String),
+    // A recorded trace could not be read or did not match.
+    Trace(String),
+    // A DAT file that could not be read as Logiqx XML.
+    Dat(String),
+    // The passes of a verified dump did not agree.
+    Verify(String),
+    // A partial dump could not be continued with --resume.
+    Resume(String),
+    // A file name could not be made from the cart header, see naming.
+    Name(String),
+    // The session was aborted, see Programmer::abort_flag.
+    Interrupted,
+    // The user said no when asked to confirm, nothing was done.
+    Cancelled(String),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usb(msg) => write!(f, "USB transfer failed: {}", msg),
+            Error::Device(msg) => write!(f, "{}", msg),
+            Error::Firmware { dictionary, opcode, code } => write!(
+                f,
+                "Firmware returned error {} ({}) for {} opcode 0x{:02X}",
+                code,
+                describe_firmware_error(*code),
+                dictionary_name(*dictionary),
+                opcode
+            ),
+            Error::BufferTimeout { status } => {
+                write!(f, "Timed out waiting for a dumped buffer, last status was 0x{:02X}", status)
+            }
+            Error::Header(msg) => write!(f, "Bad cartridge header: {}", msg),
+            Error::Unsupported(msg) => write!(f, "{}", msg),
+            Error::Incompatible(msg) => write!(f, "{}", msg),
+            Error::Trace(msg) => write!(f, "Trace: {}", msg),
+            Error::Dat(msg) => write!(f, "Bad DAT file: {}", msg),
+            Error::Verify(msg) => write!(f, "Verification failed: {}", msg),
+            Error::Resume(msg) => write!(f, "Can not resume: {}", msg),
+            Error::Name(msg) => write!(f, "Can not name the file: {}", msg),
+            Error::Interrupted => write!(f, "Interrupted"),
+            Error::Cancelled(msg) => write!(f, "{}", msg),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+// For the std::io traits implemented on top of the device.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Io(err) => err,
+            err => io::Error::other(err),
+        }
+    }
+}
+
+impl From<rusb::Error> for Error {
+    fn from(err: rusb::Error) -> Error {
+        Error::Usb(err.to_string())
+    }
+}
+
+// See shared_dictionaries.h
+fn dictionary_name(dictionary: u8) -> &'static str {
+    match dictionary {
+        1 => "PINPORT",
+        2 => "IO",
+        3 => "NES",
+        4 => "SNES",
+        5 => "BUFFER",
+        7 => "OPER",
+        10 => "BOOTLOAD",
+        12 => "GAMEBOY",
+        13 => "GBA",
+        14 => "SEGA",
+        _ => "unknown dictionary",
+    }
+}
+
+// See shared_errors.h
+fn describe_firmware_error(code: u8) -> &'static str {
+    match code {
+        ERR_UNKN_DICTIONARY => "unknown dictionary",
+        ERR_BAD_PINPORT_OP => "bad pinport operation",
+        ERR_CTL_PIN_NOT_PRESENT => "control pin not present",
+        ERR_UNKN_PINPORT_OPCODE | ERR_UNKN_IO_OPCODE | ERR_UNKN_NES_OPCODE | ERR_UNKN_SNES_OPCODE
+        | ERR_UNKN_BUFF_OPCODE | ERR_UNKN_OPER_OPCODE | ERR_UNKN_BOOTLOAD_OPCODE | ERR_UNKN_GAMEBOY_OPCODE
+        | ERR_UNKN_GBA_OPCODE | ERR_UNKN_SEGA_OPCODE => "unknown opcode, firmware may be too old",
+        ERR_BUFN_DOES_NOT_EXIST => "buffer does not exist",
+        ERR_BUFF_ALLOC_RANGE => "buffer allocation out of range",
+        ERR_BUFF_STATUS_ALREADY_ALLOC => "buffer status already allocated",
+        ERR_BUFF_ID_ALREADY_ALLOC => "buffer id already allocated",
+        ERR_BUFF_RAW_ALREADY_ALLOC => "raw buffer already allocated",
+        ERR_BUFF_ALLOC_SIZE_ZERO => "buffer allocation size is zero",
+        ERR_BUFF_UNSUP_MEM_TYPE => "unsupported memory type for buffer",
+        ERR_OUT_CURLDBUF_STATUS => "current load buffer in wrong state",
+        ERR_OUT_CURLDBUF_TO_SMALL => "current load buffer too small",
+        _ => "unknown error",
+    }
+}
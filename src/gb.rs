@@ -1,80 +1,146 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::{Error, Result};
+use crate::transport::Transport;
 use std::str;
-use std::fs::File;
-use std::io::BufWriter;
+use log::{debug, info};
+use serde::Serialize;
 
 use crate::io;
 use crate::util;
 
-use crate::util::{CommandLineOptions, dump};
+use crate::progress::{ProgressObserver, Tracker};
+use crate::verify::Verify;
+use crate::reader::{BankSource, CartReader};
+use crate::diagnose::{self, Bus, Pin, Report};
+use crate::naming::CartName;
+use crate::util::{OutputFile, dump, dump_to_array};
 use crate::opcodes::gb::*;
 use crate::opcodes::buffer as op_buffer;
 
 // TODO: Check header checksum and global checksum
 
-pub fn dump_gb<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions) {
-    io::reset(&device_handle);
-    io::gameboy_init(&device_handle);
-    io::gb_power_5v(&device_handle);
-
-    let header = get_header(&device_handle);
+// Dump the ROM to filename and/or the save RAM to savefile.
+pub fn dump_gb<T: Transport>(
+    device_handle: &T,
+    filename: Option<&str>,
+    savefile: Option<&str>,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    init(device_handle)?;
+
+    let header = read_header(device_handle)?;
     print_header(&header);
 
-    if cmd_options.savefile != "" {
-        println!("Dumping save RAM...");
-        dump_ram(&device_handle, &cmd_options, &header);
+    if let Some(savefile) = savefile {
+        info!("Dumping save RAM...");
+        dump_ram(device_handle, &header, savefile, verify, progress)?;
     }
 
-    if cmd_options.filename != "" {
-        println!("Dumping ROM...");
-        dump_rom(&device_handle, &cmd_options, &header);
+    if let Some(filename) = filename {
+        info!("Dumping ROM...");
+        dump_rom(device_handle, &header, filename, verify, progress)?;
     }
 
-    io::reset(&device_handle);
+    io::reset(device_handle)?;
+    return Ok(());
+}
+
+pub fn init<T: Transport>(device_handle: &T) -> Result<()> {
+    io::reset(device_handle)?;
+    io::gameboy_init(device_handle)?;
+    io::gb_power_5v(device_handle)?;
+    return Ok(());
 }
 
-fn dump_rom<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions, header: &GbHeader) {
-    let file = File::create(&cmd_options.filename).unwrap();
-    let mut f = BufWriter::new(file);
+pub fn dump_rom<T: Transport>(
+    device_handle: &T,
+    header: &GbHeader,
+    filename: &str,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let mut f = OutputFile::create(filename)?;
 
     let kb_per_read = 16; // 16 MBC or 32 rom only.
     let rom_size = 32 * (1 << header.rom_size);
     let mut addr_base = 0x00;
     let num_reads = rom_size / kb_per_read;
     let mut read_count = 0;
+    let mut progress = Tracker::new(progress, rom_size as u64 * 1024, verify);
 
     while read_count < num_reads {
-        if read_count % 8 == 0 {
-            println!("dumping ROM bank: {} of {}", read_count, num_reads-1);
-        }
+        progress.set_bank(read_count as u32);
         // TODO: Better check if MBC cart.
         if read_count >= 1 && header.cart_type > 0 { // For MBC carts, select current bank
             addr_base = 0x40;
-            rom_wr(&device_handle, 0x2100, read_count);
+            rom_wr(device_handle, 0x2100, read_count)?;
         }
-        dump(&device_handle, &mut f, kb_per_read, addr_base, op_buffer::GAMEBOY_PAGE);
+        dump(device_handle, &mut f, kb_per_read, addr_base, op_buffer::GAMEBOY_PAGE, &mut progress)?;
         read_count +=  1
     }
+    progress.finish();
+    f.commit()?;
+    return Ok(());
 }
 
-fn dump_ram<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions, header: &GbHeader) {
+// Bank 0 at $0000 and the switchable banks at $4000, see cart_reader.
+pub struct RomBanks<'a, T: Transport> {
+    device_handle: &'a T,
+    rom_size: u64,
+    mbc: bool,
+}
+
+// Random access to the ROM described by header.
+pub fn cart_reader<'a, T: Transport>(device_handle: &'a T, header: &GbHeader) -> CartReader<RomBanks<'a, T>> {
+    let rom_size = 32 * 1024 * (1 << header.rom_size);
+    // TODO: Better check if MBC cart.
+    return CartReader::new(RomBanks { device_handle, rom_size, mbc: header.cart_type > 0 });
+}
+
+impl<T: Transport> BankSource for RomBanks<'_, T> {
+    fn size(&self) -> u64 {
+        return self.rom_size;
+    }
+
+    fn block_size(&self) -> usize {
+        return 4 * 1024;
+    }
+
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let bank = (offset / 0x4000) as u16;
+        let page = ((offset % 0x4000) >> 8) as u16;
+        let mut addr_base = page;
+        if bank >= 1 {
+            if self.mbc {
+                rom_wr(self.device_handle, 0x2100, bank)?;
+            }
+            addr_base += 0x40;
+        }
+        return dump_to_array(self.device_handle, buf, (buf.len() / 1024) as u16, addr_base, op_buffer::GAMEBOY_PAGE, &mut Tracker::silent());
+    }
+}
+
+pub fn dump_ram<T: Transport>(
+    device_handle: &T,
+    header: &GbHeader,
+    filename: &str,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
     // MBC1, MBC3, MBC5 should work.
     // TODO: HuC1, MBC2
 
     if header.cart_type == 0xFF { // 0xFF is HuC1 with ram
-        println!("HuC1 with ram not supported yet!");
-        return;
+        return Err(Error::Unsupported(String::from("HuC1 with ram not supported yet!")));
     }
 
     // Header ram size
     let size = match_ram_size(header.ram_size).unwrap_or(0);
     if size == 0 {
-        println!("No RAM in this cart according to header.");
-        return;
+        return Err(Error::Unsupported(String::from("No RAM in this cart according to header.")));
     }
 
-    let file = File::create(&cmd_options.savefile).unwrap();
-    let mut f = BufWriter::new(file);
+    let mut f = OutputFile::create(filename)?;
 
     let kb_per_read = 8;
     let addr_base = 0xA0;
@@ -84,27 +150,32 @@ fn dump_ram<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &Comman
     // Enable RAM only for MBC
     // TODO: Detect MBC better
     if header.cart_type != 8 && header.cart_type != 9 {
-        println!("Enable RAM");
-        rom_wr(&device_handle, 0x0000, 0xA);
+        debug!("Enable RAM");
+        rom_wr(device_handle, 0x0000, 0xA)?;
     }
 
+    let mut progress = Tracker::new(progress, size as u64 * 1024, verify);
     for n in 0..banks {
-        println!("Dumping RAM bank: {} of {}", n+1, banks);
         // Switch ram bank.
-        rom_wr(&device_handle, 0x4100, n);
-        dump(&device_handle, &mut f, kb_per_read, addr_base, op_buffer::GAMEBOY_PAGE);
+        rom_wr(device_handle, 0x4100, n)?;
+        progress.set_bank(n as u32);
+        dump(device_handle, &mut f, kb_per_read, addr_base, op_buffer::GAMEBOY_PAGE, &mut progress)?;
     }
+    progress.finish();
 
     // Disable RAM
     if header.cart_type != 8 && header.cart_type != 9 {
-        println!("Disable RAM");
-        rom_wr(&device_handle, 0x0000, 0x0);
+        debug!("Disable RAM");
+        rom_wr(device_handle, 0x0000, 0x0)?;
     }
+    f.commit()?;
+    return Ok(());
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct GbHeader {
     pub rom_name: String,     // 0134 - 0143 Title (013F - 0142 Manufacturer code in newer carts)
+    pub cgb_flag: u8,         // 0143 (80h CGB support, C0h CGB only, else part of the title)
     pub developer_code: u8,   // 0144-0145 (newer carts), 014B older carts, 014B (old dev code) if 33h then use new 
     pub sgb_flag: u8,         // 0146 (00h No SGB, 03h SGB supported)
     pub cart_type: u8,        // 0147 Mapper/Cart type MBC1 ,MBC2 etc.
@@ -116,10 +187,11 @@ pub struct GbHeader {
     pub global_checksum: u16  // 014E-014F Global Checksum
 }
 
-fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GbHeader {
+pub fn read_header<T: Transport>(device_handle: &T) -> Result<GbHeader> {
 
     let mut gb_header = GbHeader{
         rom_name: "".to_string(),
+        cgb_flag: 0,
         developer_code:  0,
         sgb_flag: 0,
         cart_type: 0,
@@ -131,27 +203,28 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GbHeader {
         global_checksum: 0,
     };
 
-    gb_header.sgb_flag = rom_rd(&device_handle, 0x146);
-    gb_header.cart_type = rom_rd(&device_handle, 0x147);
-    gb_header.rom_size = rom_rd(&device_handle, 0x148);
-    gb_header.ram_size = rom_rd(&device_handle, 0x149);
-    gb_header.dest_code = rom_rd(&device_handle, 0x14A);
-    gb_header.version = rom_rd(&device_handle, 0x14C);
-    gb_header.header_checksum = rom_rd(&device_handle, 0x14D);
+    gb_header.cgb_flag = rom_rd(device_handle, 0x143)?;
+    gb_header.sgb_flag = rom_rd(device_handle, 0x146)?;
+    gb_header.cart_type = rom_rd(device_handle, 0x147)?;
+    gb_header.rom_size = rom_rd(device_handle, 0x148)?;
+    gb_header.ram_size = rom_rd(device_handle, 0x149)?;
+    gb_header.dest_code = rom_rd(device_handle, 0x14A)?;
+    gb_header.version = rom_rd(device_handle, 0x14C)?;
+    gb_header.header_checksum = rom_rd(device_handle, 0x14D)?;
     //  bytes
-    let upper = (rom_rd(&device_handle, 0x14E) as u16) << 8;
-    let lower = rom_rd(&device_handle, 0x14F) as u16;
+    let upper = (rom_rd(device_handle, 0x14E)? as u16) << 8;
+    let lower = rom_rd(device_handle, 0x14F)? as u16;
     gb_header.global_checksum = lower | upper;
 
     // Get old dev code
-    let old_dev = rom_rd(&device_handle, 0x14b);
+    let old_dev = rom_rd(device_handle, 0x14b)?;
     // If old dev is 0x33 then it is a newer type cart. Only newer types has the 16bytes names.
 
     let mut name_len = 11; // 16 is the full length, 11 for old type
     if old_dev == 0x33 { // new type
         name_len = 16;
-        let up = ((rom_rd(&device_handle, 0x144) as u16) & 0xF) << 8;
-        let low = rom_rd(&device_handle, 0x145) as u16 & 0xF;
+        let up = ((rom_rd(device_handle, 0x144)? as u16) & 0xF) << 8;
+        let low = rom_rd(device_handle, 0x145)? as u16 & 0xF;
         gb_header.developer_code = up as u8 | low as u8;
     } else {
         gb_header.developer_code = old_dev;
@@ -159,15 +232,15 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GbHeader {
 
     let mut rom_name = vec![0; name_len];
     for (index, item) in rom_name.iter_mut().enumerate() {
-        *item = rom_rd(&device_handle, 0x134 + index as u16);
+        *item = rom_rd(device_handle, 0x134 + index as u16)?;
     }
 
     gb_header.rom_name = String::from_utf8_lossy(&rom_name).to_string();
 
-    return gb_header;
+    return Ok(gb_header);
 }
 
-fn print_header(header: &GbHeader) {
+pub fn print_header(header: &GbHeader) {
     println!("------------ HEADER ------------");
     println!("Name: {}", header.rom_name);
     println!("Developer: {}", match_developer(header.developer_code).unwrap_or("Unknown"));
@@ -182,6 +255,51 @@ fn print_header(header: &GbHeader) {
     println!("--------------------------------");
 }
 
+// The header as print_header shows it, sizes in bytes and None for codes
+// that are not known.
+#[derive(Clone, Debug, Serialize)]
+pub struct GbDecoded {
+    pub title: String,
+    pub color: &'static str,
+    pub super_gameboy: bool,
+    pub cart_type: Option<&'static str>,
+    pub rom_size_bytes: Option<u32>,
+    pub ram_size_bytes: Option<u32>,
+    pub destination: &'static str,
+    pub developer: Option<&'static str>,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+pub fn decode_header(header: &GbHeader) -> GbDecoded {
+    return GbDecoded {
+        // The CGB flag is the last byte of a 16 byte title.
+        title: header.rom_name.trim_end_matches(['\0', ' ', '\u{FFFD}']).to_owned(),
+        color: match header.cgb_flag {
+            0x80 => "supported",
+            0xC0 => "only",
+            _ => "no",
+        },
+        super_gameboy: header.sgb_flag == 3,
+        cart_type: match_cart_type(header.cart_type),
+        rom_size_bytes: (header.rom_size <= 8).then(|| 0x8000 << header.rom_size),
+        ram_size_bytes: match_ram_size(header.ram_size).map(|kb| kb as u32 * 1024),
+        destination: if header.dest_code == 0 { "Japan" } else { "Overseas" },
+        developer: match_developer(header.developer_code),
+        version: header.version,
+        header_checksum: header.header_checksum,
+        global_checksum: header.global_checksum,
+    };
+}
+
+pub fn cart_name(header: &GbHeader) -> CartName {
+    let color = header.cgb_flag == 0x80 || header.cgb_flag == 0xC0;
+    let extension = if color { "gbc" } else { "gb" };
+    let region = if header.dest_code == 0 { "Japan" } else { "World" };
+    return CartName::new(extension, "sav").with_title(&header.rom_name).with_region(region);
+}
+
 fn match_ram_size(ram_size: u8) -> Option<u8> {
     match ram_size {
         0x00 => Some(0),
@@ -385,16 +503,54 @@ fn match_developer(dev_code: u8) -> Option<&'static str> {
     }
 }
 
+// The ROM side of the 32-pin edge, read at $0000-$7FFF where A15 is low.
+const ROM_BUS: Bus = Bus {
+    connector: "Game Boy 32-pin",
+    base: 0x0000,
+    address: &[
+        (0, Pin { signal: "A0", number: "6" }),
+        (1, Pin { signal: "A1", number: "7" }),
+        (2, Pin { signal: "A2", number: "8" }),
+        (3, Pin { signal: "A3", number: "9" }),
+        (4, Pin { signal: "A4", number: "10" }),
+        (5, Pin { signal: "A5", number: "11" }),
+        (6, Pin { signal: "A6", number: "12" }),
+        (7, Pin { signal: "A7", number: "13" }),
+        (8, Pin { signal: "A8", number: "14" }),
+        (9, Pin { signal: "A9", number: "15" }),
+        (10, Pin { signal: "A10", number: "16" }),
+        (11, Pin { signal: "A11", number: "17" }),
+        (12, Pin { signal: "A12", number: "18" }),
+        (13, Pin { signal: "A13", number: "19" }),
+        (14, Pin { signal: "A14", number: "20" }),
+    ],
+    data: &[
+        Pin { signal: "D0", number: "22" },
+        Pin { signal: "D1", number: "23" },
+        Pin { signal: "D2", number: "24" },
+        Pin { signal: "D3", number: "25" },
+        Pin { signal: "D4", number: "26" },
+        Pin { signal: "D5", number: "27" },
+        Pin { signal: "D6", number: "28" },
+        Pin { signal: "D7", number: "29" },
+    ],
+};
+
+// Look for bad ROM lines, only bank 0 and the bank the MBC starts in are read.
+pub fn diagnose<T: Transport>(device_handle: &T) -> Result<Report> {
+    return diagnose::probe(&ROM_BUS, |address| Ok(rom_rd(device_handle, address as u16)? as u16));
+}
+
 // Device functions
-pub fn rom_rd<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) -> u8 {
+pub fn rom_rd<T: Transport>(device_handle: &T, operand: u16) -> Result<u8> {
     let request = 12; // 12 is for GB
     let mut buf: [u8; 3] = [0; 3];
-    util::read_device(device_handle, &mut buf, request, GAMEBOY_RD, operand, 0);
-    return buf[2];
+    util::read_device(device_handle, &mut buf, request, GAMEBOY_RD, operand, 0)?;
+    return Ok(buf[2]);
 }
 
-pub fn rom_wr<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16, misc: u16) {
+pub fn rom_wr<T: Transport>(device_handle: &T, operand: u16, misc: u16) -> Result<()> {
     let request = 12; // 12 is for GB
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, GAMEBOY_WR, operand, misc);
+    util::read_device(device_handle, &mut buf, request, GAMEBOY_WR, operand, misc)
 }
\ No newline at end of file
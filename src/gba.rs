@@ -1,64 +1,123 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
+use log::info;
+use serde::Serialize;
 
 use std::io::prelude::*;
-use std::fs::File;
-use std::io::BufWriter;
 
 use crate::io;
 use crate::util;
 
-use crate::util::{CommandLineOptions, dump_to_array};
+use crate::progress::{ProgressObserver, Tracker};
+use crate::verify::Verify;
+use crate::reader::{BankSource, CartReader};
+use crate::diagnose::{self, Bus, Pin, Report};
+use crate::naming::CartName;
+use crate::util::{OutputFile, dump_to_array};
 use crate::opcodes::gba::*;
 use crate::opcodes::buffer as op_buffer;
 
 // TODO: Check header checksum
-pub fn dump_gba<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions) {
-    io::reset(&device_handle);
-    io::gba_init(&device_handle);
-    io::gb_power_3v(&device_handle);
+// Dump the ROM to filename, with resume it continues from where an earlier
+// dump was interrupted.
+pub fn dump_gba<T: Transport>(
+    device_handle: &T,
+    filename: Option<&str>,
+    resume: bool,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    init(device_handle)?;
 
-    let header = get_header(&device_handle);
+    let header = read_header(device_handle)?;
     print_header(&header);
 
-    if cmd_options.filename != "" {
-        println!("Dumping ROM...");
-        dump_rom(&device_handle, &cmd_options);
+    if let Some(filename) = filename {
+        info!("Dumping ROM...");
+        dump_rom(device_handle, &header, filename, resume, verify, progress)?;
     }
 
-    io::reset(&device_handle);
+    io::reset(device_handle)?;
+    return Ok(());
 }
 
-fn dump_rom<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions) {
-    let file = File::create(&cmd_options.filename).unwrap();
-    let mut f = BufWriter::new(file);
+pub fn init<T: Transport>(device_handle: &T) -> Result<()> {
+    io::reset(device_handle)?;
+    io::gba_init(device_handle)?;
+    io::gb_power_3v(device_handle)?;
+    return Ok(());
+}
+
+pub fn dump_rom<T: Transport>(
+    device_handle: &T,
+    header: &GbaHeader,
+    filename: &str,
+    resume: bool,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let cart = format!("{} {} {:02X}", header.rom_name.trim(), header.game_code, header.header_checksum);
+    let (mut f, done) = OutputFile::create_resumable(filename, &cart, resume)?;
 
 
     let kb_per_read = 128; // 16 MBC or 32 rom only.
     let rom_size = 32 * 1024;
     let num_reads = rom_size / kb_per_read;
-    let mut read_count = 0;
+    let mut read_count = (done / (kb_per_read as u64 * 1024)) as u16;
     // TODO Change to regular array?
     let mut dump_array = vec![0; (kb_per_read as usize) * 1024];
 
+    let mut progress = Tracker::new(progress, rom_size as u64 * 1024, verify);
+    progress.resume(done);
+
     while read_count < num_reads {
-        if read_count % 8 == 0 {
-            println!("dumping ROM bank: {} of {}", read_count, num_reads-1);
-        }
-        latch_addr(device_handle, 0x0000, read_count);
-        
-        dump_to_array(&device_handle, &mut dump_array, kb_per_read, 0x00, op_buffer::GBA_ROM_PAGE);
-        release_bus(device_handle);
+        latch_addr(device_handle, 0x0000, read_count)?;
+        progress.set_bank(read_count as u32);
+
+        dump_to_array(device_handle, &mut dump_array, kb_per_read, 0x00, op_buffer::GBA_ROM_PAGE, &mut progress)?;
+        release_bus(device_handle)?;
         
         match read_count {
-            32  => if check_empty(&dump_array) { break; },
-            64  => if check_empty(&dump_array) { break; },
-            128 => if check_empty(&dump_array) { break; },
+            32 | 64 | 128 if check_empty(&dump_array) => break,
             _ => {},
         }
 
-        f.write_all(&dump_array).unwrap();
+        f.write_all(&dump_array)?;
+        f.checkpoint()?;
         read_count +=  1;   
     }
+    progress.finish();
+    f.commit()?;
+    return Ok(());
+}
+
+// The ROM reached by latching the word address, see cart_reader.
+pub struct RomBanks<'a, T: Transport> {
+    device_handle: &'a T,
+}
+
+// Random access to the ROM. There is no size in the header so the whole
+// 32MB address space is readable, a smaller ROM repeats or reads open bus.
+pub fn cart_reader<T: Transport>(device_handle: &T) -> CartReader<RomBanks<'_, T>> {
+    return CartReader::new(RomBanks { device_handle });
+}
+
+impl<T: Transport> BankSource for RomBanks<'_, T> {
+    fn size(&self) -> u64 {
+        return 32 * 1024 * 1024;
+    }
+
+    fn block_size(&self) -> usize {
+        return 4 * 1024;
+    }
+
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let word_addr = offset / 2;
+        latch_addr(self.device_handle, word_addr as u16, (word_addr >> 16) as u16)?;
+        let result = dump_to_array(self.device_handle, buf, (buf.len() / 1024) as u16, 0x00, op_buffer::GBA_ROM_PAGE, &mut Tracker::silent());
+        release_bus(self.device_handle)?;
+        return result;
+    }
 }
 
 // check if the whole array is 0xFF
@@ -71,7 +130,7 @@ fn check_empty(dump_array: &[u8]) -> bool {
     return true;
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct GbaHeader {
     pub rom_name: String,     // 0x0A0 - 0x0AB Title
     pub game_code: String,   // 0x0AC - 0x0AF
@@ -79,7 +138,7 @@ pub struct GbaHeader {
     pub header_checksum: u8,  // 0x0BD
 }
 
-fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GbaHeader {
+pub fn read_header<T: Transport>(device_handle: &T) -> Result<GbaHeader> {
 
     let mut header = GbaHeader{
         rom_name: "".to_string(),
@@ -89,40 +148,41 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GbaHeader {
     };
 
     // latch addr first        A0-15  A16-23
-    latch_addr(&device_handle, 0x00A0 >> 1, 0x0000);
-    header.rom_name = get_string_from_header(&device_handle, 12);
-    release_bus(&device_handle);
+    latch_addr(device_handle, 0x00A0 >> 1, 0x0000)?;
+    header.rom_name = get_string_from_header(device_handle, 12)?;
+    release_bus(device_handle)?;
 
     // latch addr first        A0-15  A16-23
-    latch_addr(&device_handle, 0x00AC >> 1, 0x0000);
-    header.game_code = get_string_from_header(&device_handle, 4);
-    release_bus(&device_handle);
+    latch_addr(device_handle, 0x00AC >> 1, 0x0000)?;
+    header.game_code = get_string_from_header(device_handle, 4)?;
+    release_bus(device_handle)?;
 
-    latch_addr(&device_handle, 0x00BC >> 1, 0x0000);
-    let tmp = rom_rd(device_handle); 
+    latch_addr(device_handle, 0x00BC >> 1, 0x0000)?;
+    let tmp = rom_rd(device_handle)?; 
     header.version = (tmp & 0xFF) as u8;
     header.header_checksum = ((tmp >> 8) & 0xFF) as u8;
-    release_bus(&device_handle);
+    release_bus(device_handle)?;
 
-    return header;
+    return Ok(header);
 }
 
-fn get_string_from_header<T: UsbContext>(device_handle: &DeviceHandle<T>, size: usize) -> String {
+fn get_string_from_header<T: Transport>(device_handle: &T, size: usize) -> Result<String> {
     let mut tmp_array = vec![0; size];
-    get_byte_array(device_handle, &mut tmp_array);
+    get_byte_array(device_handle, &mut tmp_array)?;
     let rom_name = String::from_utf8_lossy(&tmp_array).to_string();
-    return rom_name
+    return Ok(rom_name);
 }
 
-fn get_byte_array<T: UsbContext>(device_handle: &DeviceHandle<T>, tmp_array: &mut [u8]) {
+fn get_byte_array<T: Transport>(device_handle: &T, tmp_array: &mut [u8]) -> Result<()> {
     for i in (0..tmp_array.len()).step_by(2) {
-        let two = rom_rd(device_handle); 
+        let two = rom_rd(device_handle)?; 
         tmp_array[i] = (two & 0xFF) as u8;
         tmp_array[i + 1] = ((two >> 8) & 0xFF) as u8;
     }
+    return Ok(());
 }
 
-fn print_header(header: &GbaHeader) {
+pub fn print_header(header: &GbaHeader) {
     println!("------------ HEADER ------------");
     println!("Name: {}", header.rom_name);
     println!("Game code: {}", header.game_code);
@@ -131,24 +191,129 @@ fn print_header(header: &GbaHeader) {
     println!("--------------------------------");
 }
 
+// The header as print_header shows it.
+#[derive(Clone, Debug, Serialize)]
+pub struct GbaDecoded {
+    pub title: String,
+    pub game_code: String,
+    pub region: Option<&'static str>,
+    pub version: u8,
+    pub header_checksum: u8,
+}
+
+pub fn decode_header(header: &GbaHeader) -> GbaDecoded {
+    return GbaDecoded {
+        title: header.rom_name.trim_end_matches(['\0', ' ']).to_owned(),
+        game_code: header.game_code.clone(),
+        region: match_region(&header.game_code),
+        version: header.version,
+        header_checksum: header.header_checksum,
+    };
+}
+
+pub fn cart_name(header: &GbaHeader) -> CartName {
+    let region = match_region(&header.game_code).unwrap_or("Unknown");
+    return CartName::new("gba", "sav").with_title(&header.rom_name).with_region(region).with_code(&header.game_code);
+}
+
+// The last letter of the game code is the region.
+fn match_region(game_code: &str) -> Option<&'static str> {
+    match game_code.chars().nth(3) {
+        Some('J') => Some("Japan"),
+        Some('E') => Some("USA"),
+        Some('P') | Some('X') | Some('Y') => Some("Europe"),
+        Some('D') => Some("Germany"),
+        Some('F') => Some("France"),
+        Some('I') => Some("Italy"),
+        Some('S') => Some("Spain"),
+        Some('H') => Some("Netherlands"),
+        Some('U') => Some("Australia"),
+        Some('K') => Some("Korea"),
+        Some('C') => Some("China"),
+        _ => None
+    }
+}
+
+// The GBA edge has the same 32 pins as the Game Boy, the lower address half
+// and the data share AD0-AD15. Addresses are in 16 bit words.
+const ROM_BUS: Bus = Bus {
+    connector: "GBA 32-pin",
+    base: 0x00_0000,
+    address: &[
+        (0, Pin { signal: "AD0", number: "6" }),
+        (1, Pin { signal: "AD1", number: "7" }),
+        (2, Pin { signal: "AD2", number: "8" }),
+        (3, Pin { signal: "AD3", number: "9" }),
+        (4, Pin { signal: "AD4", number: "10" }),
+        (5, Pin { signal: "AD5", number: "11" }),
+        (6, Pin { signal: "AD6", number: "12" }),
+        (7, Pin { signal: "AD7", number: "13" }),
+        (8, Pin { signal: "AD8", number: "14" }),
+        (9, Pin { signal: "AD9", number: "15" }),
+        (10, Pin { signal: "AD10", number: "16" }),
+        (11, Pin { signal: "AD11", number: "17" }),
+        (12, Pin { signal: "AD12", number: "18" }),
+        (13, Pin { signal: "AD13", number: "19" }),
+        (14, Pin { signal: "AD14", number: "20" }),
+        (15, Pin { signal: "AD15", number: "21" }),
+        (16, Pin { signal: "A16", number: "22" }),
+        (17, Pin { signal: "A17", number: "23" }),
+        (18, Pin { signal: "A18", number: "24" }),
+        (19, Pin { signal: "A19", number: "25" }),
+        (20, Pin { signal: "A20", number: "26" }),
+        (21, Pin { signal: "A21", number: "27" }),
+        (22, Pin { signal: "A22", number: "28" }),
+        (23, Pin { signal: "A23", number: "29" }),
+    ],
+    data: &[
+        Pin { signal: "AD0", number: "6" },
+        Pin { signal: "AD1", number: "7" },
+        Pin { signal: "AD2", number: "8" },
+        Pin { signal: "AD3", number: "9" },
+        Pin { signal: "AD4", number: "10" },
+        Pin { signal: "AD5", number: "11" },
+        Pin { signal: "AD6", number: "12" },
+        Pin { signal: "AD7", number: "13" },
+        Pin { signal: "AD8", number: "14" },
+        Pin { signal: "AD9", number: "15" },
+        Pin { signal: "AD10", number: "16" },
+        Pin { signal: "AD11", number: "17" },
+        Pin { signal: "AD12", number: "18" },
+        Pin { signal: "AD13", number: "19" },
+        Pin { signal: "AD14", number: "20" },
+        Pin { signal: "AD15", number: "21" },
+    ],
+};
+
+// Look for bad ROM lines. A line shorted on AD0-AD15 shows up as a data and
+// an address fault.
+pub fn diagnose<T: Transport>(device_handle: &T) -> Result<Report> {
+    return diagnose::probe(&ROM_BUS, |address| {
+        latch_addr(device_handle, address as u16, (address >> 16) as u16)?;
+        let word = rom_rd(device_handle)?;
+        release_bus(device_handle)?;
+        return Ok(word);
+    });
+}
+
 // Device functions
-fn rom_rd<T: UsbContext>(device_handle: &DeviceHandle<T>) -> u16 {
+fn rom_rd<T: Transport>(device_handle: &T) -> Result<u16> {
     let request = 13; // 13 is for gba
     let mut buf: [u8; 4] = [0; 4];
-    util::read_device(device_handle, &mut buf, request, GBA_RD, 0, 0);
+    util::read_device(device_handle, &mut buf, request, GBA_RD, 0, 0)?;
     let upper = (buf[3] as u16) << 8;
     let lower = buf[2] as u16; 
-    return upper | lower;
+    return Ok(upper | lower);
 }
 
-pub fn latch_addr<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16, misc: u16) {
+pub fn latch_addr<T: Transport>(device_handle: &T, operand: u16, misc: u16) -> Result<()> {
     let request = 13; // 13 is for GBA
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, GBA_LATCH_ADDR, operand, misc);
+    util::read_device(device_handle, &mut buf, request, GBA_LATCH_ADDR, operand, misc)
 }
 
-pub fn release_bus<T: UsbContext>(device_handle: &DeviceHandle<T>) {
+pub fn release_bus<T: Transport>(device_handle: &T) -> Result<()> {
     let request = 13; // 13 is for GBA
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, GBA_RELEASE_BUS, 0, 0);
+    util::read_device(device_handle, &mut buf, request, GBA_RELEASE_BUS, 0, 0)
 }
\ No newline at end of file
@@ -1,102 +1,168 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::{Error, Result};
+use crate::transport::Transport;
 use std::io::Write;
 use std::str;
-use std::fs;
-use std::io::prelude::*;
-use std::io::BufWriter;
+use log::info;
+use serde::Serialize;
 
 use crate::io;
 use crate::util;
 
-use crate::util::{CommandLineOptions, dump};
+use crate::engine::{self, Callback, Region};
+use crate::progress::{ProgressObserver, Tracker};
+use crate::verify::Verify;
+use crate::reader::{BankSource, CartReader};
+use crate::diagnose::{self, Bus, Pin, Report};
+use crate::naming::CartName;
+use crate::util::{OutputFile, dump, dump_to_array};
 use crate::opcodes::genesis::*;
 use crate::opcodes::buffer as op_buffer;
 
-pub fn dump_genesis<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions) {
-    io::reset(&device_handle);
-    io::genesis_init(&device_handle);
-
-    let header = get_header(&device_handle);
+// Dump the ROM to filename and/or the save RAM to savefile. With resume the
+// ROM dump continues from where an earlier one was interrupted.
+pub fn dump_genesis<T: Transport>(
+    device_handle: &T,
+    filename: Option<&str>,
+    savefile: Option<&str>,
+    resume: bool,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    init(device_handle)?;
+
+    let header = read_header(device_handle)?;
     print_header(&header);
 
-    if cmd_options.savefile != "" {
-        println!("Dumping save RAM...");
-        dump_ram(&device_handle, &cmd_options, &header);
+    if let Some(savefile) = savefile {
+        info!("Dumping save RAM...");
+        dump_ram(device_handle, &header, savefile, verify, progress)?;
     }
  
-    if cmd_options.filename != "" {
-        println!("Dumping ROM...");
-        dump_rom(&device_handle, &cmd_options, &header);
+    if let Some(filename) = filename {
+        info!("Dumping ROM...");
+        dump_rom(device_handle, &header, filename, resume, verify, progress)?;
     }
 
-    io::reset(&device_handle);
+    io::reset(device_handle)?;
+    return Ok(());
+}
+
+pub fn init<T: Transport>(device_handle: &T) -> Result<()> {
+    io::reset(device_handle)?;
+    io::genesis_init(device_handle)?;
+    return Ok(());
 }
 
 // TODO: Detect and warn for larger roms with sram because can't disable sram with firmware.
-fn dump_rom<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions, header: &GenesisHeader) {
-    let file = fs::File::create(&cmd_options.filename).unwrap();
-    let mut f = BufWriter::new(file);
+pub fn dump_rom<T: Transport>(
+    device_handle: &T,
+    header: &GenesisHeader,
+    filename: &str,
+    resume: bool,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let cart = format!(
+        "{} {} {} KB {:04X}",
+        header.serial_number.trim(),
+        header.rom_name_overseas.trim(),
+        header.rom_size,
+        header.checksum
+    );
+    let (mut f, done) = OutputFile::create_resumable(filename, &cart, resume)?;
 
     let kb_per_read = 128;
     let rom_size = header.rom_size;
     let addr_base = 0x0000;
     let num_reads = rom_size / kb_per_read;
-    let mut read_count = 0;
+    let mut read_count = (done / (kb_per_read as u64 * 1024)) as u32;
+    let mut progress = Tracker::new(progress, rom_size as u64 * 1024, verify);
+    progress.resume(done);
 
     while read_count < num_reads {
-        if read_count % 8 == 0 {
-            println!("dumping ROM bank: {} of {}", read_count, num_reads-1);
-        }
-
-        set_bank(device_handle, read_count as u16);
-        dump(&device_handle, &mut f, (kb_per_read/2) as u16, addr_base, op_buffer::GENESIS_ROM_PAGE0);
-        dump(&device_handle, &mut f, (kb_per_read/2) as u16, addr_base, op_buffer::GENESIS_ROM_PAGE1);
+        set_bank(device_handle, read_count as u16)?;
+        progress.set_bank(read_count);
+        dump(device_handle, &mut f, (kb_per_read/2) as u16, addr_base, op_buffer::GENESIS_ROM_PAGE0, &mut progress)?;
+        dump(device_handle, &mut f, (kb_per_read/2) as u16, addr_base, op_buffer::GENESIS_ROM_PAGE1, &mut progress)?;
+        f.checkpoint()?;
         read_count +=  1
     }
+    progress.finish();
+    f.commit()?;
+    return Ok(());
 }
 
-fn dump_ram<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions, header: &GenesisHeader) {
+// 128KB banks read as two 64KB pages, see cart_reader.
+pub struct RomBanks<'a, T: Transport> {
+    device_handle: &'a T,
+    rom_size: u64,
+}
 
-    if header.extra_memory_type != 0xF8 {
-        println!("Dumping save RAM for {} is not supported.", match_extra_memory_type(header.extra_memory_type).unwrap_or("Unknown"));
+// Random access to the ROM described by header.
+pub fn cart_reader<'a, T: Transport>(device_handle: &'a T, header: &GenesisHeader) -> CartReader<RomBanks<'a, T>> {
+    return CartReader::new(RomBanks { device_handle, rom_size: header.rom_size as u64 * 1024 });
+}
+
+impl<T: Transport> BankSource for RomBanks<'_, T> {
+    fn size(&self) -> u64 {
+        return self.rom_size;
     }
-    {
-        let file = fs::File::create(&cmd_options.savefile).unwrap();
-        let mut f = BufWriter::new(file);
 
-        let kb_per_read = 8;
-        let addr_base = 0x00;
+    fn block_size(&self) -> usize {
+        return 64 * 1024;
+    }
 
-        let size = header.extra_memory_size / 2;
-        let banks = size as u16 / kb_per_read;
-        let start_bank = 0x20 >> 1;
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let bank = (offset / 0x20000) as u16;
+        let mem = if offset % 0x20000 < 0x10000 { op_buffer::GENESIS_ROM_PAGE0 } else { op_buffer::GENESIS_ROM_PAGE1 };
+        set_bank(self.device_handle, bank)?;
+        return dump_to_array(self.device_handle, buf, (buf.len() / 1024) as u16, 0x0000, mem, &mut Tracker::silent());
+    }
+}
 
-        for n in 0..banks {
-            println!("Dumping RAM bank: {} of {}", n+1, banks);
+pub fn dump_ram<T: Transport>(
+    device_handle: &T,
+    header: &GenesisHeader,
+    filename: &str,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
 
-            set_bank(device_handle, start_bank + (n as u16));
-            dump(&device_handle, &mut f, kb_per_read, addr_base, op_buffer::GENESIS_RAM_PAGE); // Reads the lower byte
-        }
-        f.flush().unwrap();
+    if header.extra_memory_type != 0xF8 {
+        return Err(Error::Unsupported(format!(
+            "Dumping save RAM for {} is not supported.",
+            match_extra_memory_type(header.extra_memory_type).unwrap_or("Unknown")
+        )));
     }
-
-    // Pad file with 0xff since we only got the lower byte.
-    fs::rename(&cmd_options.savefile, "tmp.srm").unwrap();
-    // Writer for file again.
-    let file = fs::File::create(&cmd_options.savefile).unwrap();
-    let mut f = BufWriter::new(file);
-    // Reader for old file.
-    let file_old = fs::File::open("tmp.srm").unwrap();
-    let mut b:  [u8; 2] = [0xFF, 0];
-    for byte in file_old.bytes() {
-        b[1] = byte.unwrap();
-        f.write(&b).unwrap();
+    let mut f = OutputFile::create(filename)?;
+
+    let kb_per_read = 8;
+    let addr_base = 0x00;
+
+    let size = header.extra_memory_size / 2;
+    let banks = size as u16 / kb_per_read;
+    let start_bank = 0x20 >> 1;
+
+    let mut progress = Tracker::new(progress, size as u64 * 1024, verify);
+    for n in 0..banks {
+        set_bank(device_handle, start_bank + n)?;
+        progress.set_bank((start_bank + n) as u32);
+        // Reads the lower byte, pad with 0xff since we only got that one.
+        let region = Region { mem: op_buffer::GENESIS_RAM_PAGE, map: addr_base, size_kb: kb_per_read };
+        let pad = Callback(|payload: &[u8]| -> Result<()> {
+            for byte in payload {
+                f.write_all(&[0xFF, *byte])?;
+            }
+            return Ok(());
+        });
+        engine::run(device_handle, region, pad, &mut progress)?;
     }
-    fs::remove_file("tmp.srm").unwrap();
-
+    progress.finish();
+    f.commit()?;
+    return Ok(());
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct GenesisHeader {
     pub rom_name_domestic: String,
     pub rom_name_overseas: String,
@@ -113,9 +179,9 @@ pub struct GenesisHeader {
     pub region_support: [u8; 3]
 }
 
-fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GenesisHeader {
+pub fn read_header<T: Transport>(device_handle: &T) -> Result<GenesisHeader> {
 
-    set_bank(device_handle, 0);
+    set_bank(device_handle, 0)?;
     
     let mut genesis_header = GenesisHeader{
         rom_name_domestic: "".to_string(),
@@ -133,27 +199,27 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GenesisHeader {
         region_support: [0; 3]
     };
 
-    genesis_header.system_type = get_string_from_header(device_handle, 16, 0x100);
-    genesis_header.copyright = get_string_from_header(device_handle, 16, 0x110);
-    genesis_header.rom_name_domestic = get_string_from_header(device_handle, 48, 0x120);
-    genesis_header.rom_name_overseas = get_string_from_header(device_handle, 48, 0x150);
-    genesis_header.serial_number = get_string_from_header(device_handle, 14, 0x180);
-    genesis_header.checksum = rom_rd(device_handle, (0x18E) >> 1);
+    genesis_header.system_type = get_string_from_header(device_handle, 16, 0x100)?;
+    genesis_header.copyright = get_string_from_header(device_handle, 16, 0x110)?;
+    genesis_header.rom_name_domestic = get_string_from_header(device_handle, 48, 0x120)?;
+    genesis_header.rom_name_overseas = get_string_from_header(device_handle, 48, 0x150)?;
+    genesis_header.serial_number = get_string_from_header(device_handle, 14, 0x180)?;
+    genesis_header.checksum = get_u16(device_handle, 0x18E)?;
     let mut tmp_array: [u8; 16] = [0; 16];
-    get_byte_array(device_handle, &mut tmp_array, 0x190);
+    get_byte_array(device_handle, &mut tmp_array, 0x190)?;
     genesis_header.devices_supported = tmp_array;
 
-    let lower_addr = get_u32(device_handle, 0x1A0);
-    let upper_addr = get_u32(device_handle, 0x1A4);
-    genesis_header.rom_size = (upper_addr - lower_addr + 1) / 1024;
+    let lower_addr = get_u32(device_handle, 0x1A0)?;
+    let upper_addr = get_u32(device_handle, 0x1A4)?;
+    genesis_header.rom_size = range_size(lower_addr, upper_addr, "ROM")? / 1024;
 
     // Always 64KiB. Does not indicate if there is save ram.
-    let lower_addr = get_u32(device_handle, 0x1A8);
-    let upper_addr = get_u32(device_handle, 0x1AC);
-    genesis_header.ram_size = (upper_addr - lower_addr + 1) / 1024;
+    let lower_addr = get_u32(device_handle, 0x1A8)?;
+    let upper_addr = get_u32(device_handle, 0x1AC)?;
+    genesis_header.ram_size = range_size(lower_addr, upper_addr, "RAM")? / 1024;
 
     let mut tmp_array: [u8; 12] = [0; 12];
-    get_byte_array(device_handle, &mut tmp_array, 0x1B0);
+    get_byte_array(device_handle, &mut tmp_array, 0x1B0)?;
 
     if tmp_array[0] as char == 'R' && tmp_array[1] as char == 'A' {
         genesis_header.extra_memory = true;
@@ -162,43 +228,60 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> GenesisHeader {
         let start = (tmp_array[4] as u32) << 24 | (tmp_array[5] as u32) << 16 | (tmp_array[6] as u32) << 8 | tmp_array[7] as u32;
         let end = (tmp_array[8] as u32) << 24 | (tmp_array[9] as u32) << 16 | (tmp_array[10] as u32) << 8 | tmp_array[11] as u32;
 
-        if start == 0x200001 { // ODD then +2 , low byte, base >> 1
-            genesis_header.extra_memory_size = (end - start + 2) / 1024;
-        } else if start == 0x200000 { // Even then + 1
-            genesis_header.extra_memory_size = (end - start + 1) / 2;
+        // KB of address space from the even address below start, the save
+        // itself is on one byte lane and half of that.
+        if start == 0x200001 || start == 0x200000 {
+            genesis_header.extra_memory_size = range_size(start & !1, end, "extra memory")?.div_ceil(1024);
         }
     }
     
     let mut tmp_array: [u8; 4] = [0; 4];
-    get_byte_array(device_handle, &mut tmp_array, 0x1F0);
+    get_byte_array(device_handle, &mut tmp_array, 0x1F0)?;
     genesis_header.region_support.copy_from_slice(&tmp_array[..3]);
 
-    return genesis_header;
+    return Ok(genesis_header);
 }
 
-fn get_u32<T: UsbContext>(device_handle: &DeviceHandle<T>, addr: u16) -> u32 {
-    let hi = rom_rd(device_handle, (addr) >> 1);
-    let lo = rom_rd(device_handle, (addr + 2) >> 1);
-    let total: u32 = ((hi as u32) << 8) | lo as u32;
-    return total;
+// rom_rd has the byte at the even address in its low half, swap it back to
+// the big-endian order of the 68000.
+fn get_u16<T: Transport>(device_handle: &T, addr: u16) -> Result<u16> {
+    let word = rom_rd(device_handle, addr >> 1)?;
+    return Ok(word.swap_bytes());
 }
 
-fn get_string_from_header<T: UsbContext>(device_handle: &DeviceHandle<T>, size: usize, addr: u16) -> String {
+fn get_u32<T: Transport>(device_handle: &T, addr: u16) -> Result<u32> {
+    let hi = get_u16(device_handle, addr)?;
+    let lo = get_u16(device_handle, addr + 2)?;
+    let total: u32 = ((hi as u32) << 16) | lo as u32;
+    return Ok(total);
+}
+
+// Bytes from start to end inclusive, a header with the end first or the
+// whole 4 GB address space is broken.
+fn range_size(start: u32, end: u32, what: &str) -> Result<u32> {
+    return end
+        .checked_sub(start)
+        .and_then(|size| size.checked_add(1))
+        .ok_or_else(|| Error::Header(format!("The {} range 0x{:X}-0x{:X} in the header is not valid", what, start, end)));
+}
+
+fn get_string_from_header<T: Transport>(device_handle: &T, size: usize, addr: u16) -> Result<String> {
     let mut tmp_array = vec![0; size];
-    get_byte_array(device_handle, &mut tmp_array, addr);
+    get_byte_array(device_handle, &mut tmp_array, addr)?;
     let rom_name = String::from_utf8_lossy(&tmp_array).to_string();
-    return rom_name
+    return Ok(rom_name);
 }
 
-fn get_byte_array<T: UsbContext>(device_handle: &DeviceHandle<T>, tmp_array: &mut [u8], addr: u16) {
+fn get_byte_array<T: Transport>(device_handle: &T, tmp_array: &mut [u8], addr: u16) -> Result<()> {
     for i in (0..tmp_array.len()).step_by(2) {
-        let two = rom_rd(device_handle, (addr + i as u16) >> 1); 
+        let two = rom_rd(device_handle, (addr + i as u16) >> 1)?; 
         tmp_array[i] = (two & 0xFF) as u8;
         tmp_array[i + 1] = ((two >> 8) & 0xFF) as u8;
     }
+    return Ok(());
 }
 
-fn print_header(header: &GenesisHeader) {
+pub fn print_header(header: &GenesisHeader) {
     println!("------------ HEADER ------------");
     println!("Name (Domestic): {}", header.rom_name_domestic);
     println!("Name (Overseas): {}", header.rom_name_overseas);
@@ -214,20 +297,151 @@ fn print_header(header: &GenesisHeader) {
     println!("--------------------------------");
 }
 
+// The header as print_header shows it, sizes in bytes.
+#[derive(Clone, Debug, Serialize)]
+pub struct GenesisDecoded {
+    pub system_type: String,
+    pub copyright: String,
+    pub title_domestic: String,
+    pub title_overseas: String,
+    pub serial_number: String,
+    pub checksum: u16,
+    pub devices_supported: String,
+    pub rom_size_bytes: u32,
+    pub ram_size_bytes: u32,
+    // None without extra memory or when the type is not known.
+    pub extra_memory_type: Option<&'static str>,
+    pub extra_memory_size_bytes: u32,
+    pub regions: Vec<&'static str>,
+}
+
+pub fn decode_header(header: &GenesisHeader) -> GenesisDecoded {
+    let text = |text: &str| text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    return GenesisDecoded {
+        system_type: text(&header.system_type),
+        copyright: text(&header.copyright),
+        title_domestic: text(&header.rom_name_domestic),
+        title_overseas: text(&header.rom_name_overseas),
+        serial_number: text(&header.serial_number),
+        checksum: header.checksum,
+        devices_supported: text(&String::from_utf8_lossy(&header.devices_supported)),
+        rom_size_bytes: header.rom_size * 1024,
+        ram_size_bytes: header.ram_size * 1024,
+        extra_memory_type: if header.extra_memory { match_extra_memory_type(header.extra_memory_type) } else { None },
+        extra_memory_size_bytes: header.extra_memory_size * 1024,
+        regions: match_regions(&header.region_support),
+    };
+}
+
+pub fn cart_name(header: &GenesisHeader) -> CartName {
+    let regions = match_regions(&header.region_support);
+    let region = match regions.len() {
+        0 => "Unknown".to_owned(),
+        3 => "World".to_owned(),
+        _ => regions.join(", "),
+    };
+    return CartName::new("md", "srm")
+        .with_title(&header.rom_name_overseas)
+        .with_region(&region)
+        .with_code(&header.serial_number);
+}
+
+// The 64-pin edge with sides A and B. The bus is 16 bits wide so there is
+// no A0, the word address is A1 up and the bank sets A17-A23.
+const ROM_BUS: Bus = Bus {
+    connector: "Genesis 64-pin",
+    base: 0x00_0000,
+    address: &[
+        (0, Pin { signal: "A1", number: "A17" }),
+        (1, Pin { signal: "A2", number: "A15" }),
+        (2, Pin { signal: "A3", number: "A13" }),
+        (3, Pin { signal: "A4", number: "A11" }),
+        (4, Pin { signal: "A5", number: "A9" }),
+        (5, Pin { signal: "A6", number: "A7" }),
+        (6, Pin { signal: "A7", number: "A5" }),
+        (7, Pin { signal: "A8", number: "A3" }),
+        (8, Pin { signal: "A9", number: "B4" }),
+        (9, Pin { signal: "A10", number: "B5" }),
+        (10, Pin { signal: "A11", number: "A4" }),
+        (11, Pin { signal: "A12", number: "A6" }),
+        (12, Pin { signal: "A13", number: "A8" }),
+        (13, Pin { signal: "A14", number: "A10" }),
+        (14, Pin { signal: "A15", number: "A12" }),
+        (15, Pin { signal: "A16", number: "A14" }),
+        (16, Pin { signal: "A17", number: "A16" }),
+        (17, Pin { signal: "A18", number: "B6" }),
+        (18, Pin { signal: "A19", number: "B7" }),
+        (19, Pin { signal: "A20", number: "B8" }),
+        (20, Pin { signal: "A21", number: "B9" }),
+        (21, Pin { signal: "A22", number: "B10" }),
+        (22, Pin { signal: "A23", number: "B11" }),
+    ],
+    data: &[
+        Pin { signal: "D0", number: "A20" },
+        Pin { signal: "D1", number: "A23" },
+        Pin { signal: "D2", number: "A26" },
+        Pin { signal: "D3", number: "A29" },
+        Pin { signal: "D4", number: "A28" },
+        Pin { signal: "D5", number: "A25" },
+        Pin { signal: "D6", number: "A22" },
+        Pin { signal: "D7", number: "A19" },
+        Pin { signal: "D8", number: "A21" },
+        Pin { signal: "D9", number: "A24" },
+        Pin { signal: "D10", number: "A27" },
+        Pin { signal: "D11", number: "A30" },
+        Pin { signal: "D12", number: "B25" },
+        Pin { signal: "D13", number: "B24" },
+        Pin { signal: "D14", number: "B23" },
+        Pin { signal: "D15", number: "B22" },
+    ],
+};
+
+// Look for bad ROM lines.
+pub fn diagnose<T: Transport>(device_handle: &T) -> Result<Report> {
+    let mut current_bank = None;
+    return diagnose::probe(&ROM_BUS, |address| {
+        let bank = (address >> 16) as u16;
+        if current_bank != Some(bank) {
+            set_bank(device_handle, bank)?;
+            current_bank = Some(bank);
+        }
+        return rom_rd(device_handle, address as u16);
+    });
+}
+
 // Device functions
-fn rom_rd<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) -> u16 {
+fn rom_rd<T: Transport>(device_handle: &T, operand: u16) -> Result<u16> {
     let request = 14; // 14 is for Sega
     let mut buf: [u8; 4] = [0; 4];
-    util::read_device(device_handle, &mut buf, request, GEN_ROM_RD, operand, 0);
+    util::read_device(device_handle, &mut buf, request, GEN_ROM_RD, operand, 0)?;
     let upper = (buf[2] as u16) << 8;
     let lower = buf[3] as u16; 
-    return upper | lower;
+    return Ok(upper | lower);
 }
 
-fn set_bank<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) {
+fn set_bank<T: Transport>(device_handle: &T, operand: u16) -> Result<()> {
     let request = 14; // 14 is for Sega
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, GEN_SET_BANK, operand, 0);
+    util::read_device(device_handle, &mut buf, request, GEN_SET_BANK, operand, 0)
+}
+
+fn match_regions(support: &[u8; 3]) -> Vec<&'static str> {
+    // Later carts have one hex digit instead, bit 0 Japan, bit 2 USA and bit 3 Europe.
+    let bits = match str::from_utf8(&support[..1]).ok().and_then(|digit| u8::from_str_radix(digit, 16).ok()) {
+        Some(bits) if !support.contains(&b'J') && !support.contains(&b'U') && !support.contains(&b'E') => bits,
+        _ => 0,
+    };
+    let mut regions = Vec::new();
+    if support.contains(&b'J') || bits & 0x1 != 0 {
+        regions.push("Japan");
+    }
+    if support.contains(&b'U') || bits & 0x4 != 0 {
+        regions.push("USA");
+    }
+    if support.contains(&b'E') || bits & 0x8 != 0 {
+        regions.push("Europe");
+    }
+    return regions;
 }
 
 fn match_extra_memory_type(mem_type: u8) -> Option<&'static str> {
@@ -0,0 +1,41 @@
+#![allow(clippy::needless_return, clippy::upper_case_acronyms)]
+
+// Host side of the INL Retro-Prog. Open a Programmer and hand it to the
+// console modules to read headers and dump ROMs and saves.
+pub mod dat;
+pub mod diagnose;
+pub mod emulator;
+pub mod error;
+pub mod gb;
+pub mod gba;
+pub mod genesis;
+pub mod naming;
+pub mod nes;
+pub mod programmer;
+pub mod progress;
+pub mod reader;
+pub mod snes;
+pub mod trace;
+pub mod transport;
+pub mod verify;
+pub mod version;
+
+mod bootload;
+mod buffer;
+mod engine;
+mod io;
+mod nes_mappers;
+mod opcodes;
+mod operation;
+mod pinport;
+mod util;
+
+pub use error::{Error, Result};
+pub use gb::GbHeader;
+pub use gba::GbaHeader;
+pub use genesis::GenesisHeader;
+pub use programmer::Programmer;
+pub use reader::CartReader;
+pub use snes::SnesHeader;
+pub use transport::Transport;
+pub use verify::Verify;
@@ -0,0 +1,31 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+// Diagnostics go to stderr, stdout is kept for results.
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() == Level::Info {
+            eprintln!("{}", record.args());
+        } else {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+pub fn init(level: LevelFilter) {
+    // Only fails if a logger is already set, which would then be used instead.
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
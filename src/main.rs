@@ -1,125 +1,329 @@
-use rusb::{Context, DeviceHandle, UsbContext, Version};
+#![allow(clippy::needless_return)]
+
 use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
-mod bootload;
-mod buffer;
-mod io;
-mod nes;
-mod snes;
-mod gb;
-mod gba;
-mod genesis;
-mod operation;
-mod pinport;
-mod opcodes;
-mod util;
-mod nes_mappers;
-
-const VENDOR_ID: u16 = 0x16C0;
-const PRODUCT_ID: u16 = 0x05DC;
-
-const INL_MANUFACTURER: &str = "InfiniteNesLives.com";
-const INL_PRODUCT: &str = "INL Retro-Prog";
-const MIN_MAJOR_FW_VERSION: u8 = 2;
+
+use inlrust::dat::{self, Dat, Hashes};
+use inlrust::naming::{self, CartName};
+use inlrust::progress::{ProgressObserver, Silent};
+use inlrust::version::Capability;
+use inlrust::{gb, gba, genesis, nes, programmer, snes, Error, Programmer, Result};
+
+use cli::Command;
+use log::LevelFilter;
+use serde::Serialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use progress_bar::ProgressBar;
+
+mod cli;
+mod logger;
+mod progress_bar;
+
+// WRAM size in kilobytes when -w is not given, what all but SOROM and SXROM have.
+const NES_WRAM_SIZE: u16 = 8;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let cmd_options = util::parse_command_line(&args).unwrap_or_else(|err| {
-        println!("{}", err);
-        util::help();
+    let cmd_options = cli::parse_command_line(&args).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        cli::help();
         process::exit(1);
     });
-    println!("{:?}", cmd_options);
+    logger::init(cmd_options.log_level);
+    log::debug!("{:?}", cmd_options);
 
-    let context = Context::new().unwrap();
-    let device_handle = get_device_handle(&context).unwrap();
-    // get device version from firmware
-    println!("Get app version");
-    bootload::get_app_ver(&device_handle);
-    
-    if cmd_options.console.to_lowercase() == "nes" {
-        nes::dump_nes(&device_handle, &cmd_options);
-    }
-    else if cmd_options.console.to_lowercase() == "snes" {
-        snes::dump_snes(&device_handle, &cmd_options);
+    match run(&cmd_options) {
+        Ok(()) => {}
+        // Declining is not a failure, but scripts still learn nothing was done.
+        Err(err @ Error::Cancelled(_)) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            process::exit(1);
+        }
     }
-    else if cmd_options.console.to_lowercase() == "gb" {
-        gb::dump_gb(&device_handle, &cmd_options);
+}
+
+fn run(cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    if cmd_options.command == Command::ListDevices {
+        return list_devices();
     }
-    else if cmd_options.console.to_lowercase() == "gba" {
-        gba::dump_gba(&device_handle, &cmd_options);
+    if cmd_options.command == Command::ListMappers {
+        return list_mappers();
     }
-    else if cmd_options.console.to_lowercase() == "genesis" {
-            genesis::dump_genesis(&device_handle, &cmd_options);
+
+    let mut programmer = if !cmd_options.replay.is_empty() {
+        Programmer::replay(&cmd_options.replay)?
+    } else if !cmd_options.emulate.is_empty() {
+        Programmer::emulate(&cmd_options.console, &cmd_options.emulate)?
     } else {
-        println!("Console {} is not supported!", cmd_options.console);
+        Programmer::open_with(&cmd_options.device)?
+    };
+    if !cmd_options.record.is_empty() {
+        programmer = programmer.record(&cmd_options.record)?;
+    }
+    // Ctrl-C stops at the next device call and the session resets the cart.
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, programmer.abort_flag())?;
+    }
+    let session = programmer.session();
+    programmer.check_compatibility()?;
+    if let Some(capability) = Capability::for_console(&cmd_options.console) {
+        programmer.require(capability)?;
+    }
+
+    match cmd_options.command {
+        Command::Info => info(&programmer, cmd_options)?,
+        Command::DumpRom => dump_rom(&programmer, cmd_options)?,
+        Command::DumpSave => dump_save(&programmer, cmd_options)?,
+        Command::TestCart => test_cart(&programmer, cmd_options)?,
+        Command::Diagnose => diagnose(&programmer, cmd_options)?,
+        Command::WriteSave => write_save(&programmer, cmd_options)?,
+        Command::ListDevices | Command::ListMappers => {}
+    }
+    session.close()?;
+    return Ok(());
+}
+
+fn list_devices() -> Result<()> {
+    let devices = programmer::list_devices()?;
+    if devices.is_empty() {
+        println!("No INL Retro-Prog found.");
     }
-    io::reset(&device_handle);
+    for device in devices {
+        println!("{}", device);
+    }
+    return Ok(());
 }
 
+fn list_mappers() -> Result<()> {
+    for (name, number) in nes::mappers() {
+        println!("{:<8} {}", name, number);
+    }
+    return Ok(());
+}
 
-fn get_device_handle<T: UsbContext>(context: &T) -> Option<DeviceHandle<T>> {
-    println!("Checking");
-    for device in context.devices().unwrap().iter() {
-        let device_desc = device.device_descriptor().unwrap();
-        if device_desc.vendor_id() == VENDOR_ID && device_desc.product_id() == PRODUCT_ID {
-            println!("Found device");
-            println!(
-                "Bus {:03} Device {:03} ID {:04x}:{:04x}",
-                device.bus_number(),
-                device.address(),
-                device_desc.vendor_id(),
-                device_desc.product_id()
-            );
-            println!("Open device");
-            let device_handle = device.open().unwrap_or_else(|err| {
-                println!("{}", err);
-                process::exit(1);
-            });
-            println!("Got device handle");
-            let manufacturer = device_handle
-            .read_manufacturer_string_ascii(&device_desc)
-            .unwrap();
-            println!("Manufacturer string: {}", manufacturer);
-            let product = device_handle
-                .read_product_string_ascii(&device_desc)
-                .unwrap_or_else(|err| {
-                    println!("{}", err);
-                    process::exit(1);
-                });
-            println!("Product string: {}", product);
-
-            if manufacturer == INL_MANUFACTURER && product == INL_PRODUCT {
-                let firmware_version = device_desc.device_version();
-                if check_version(firmware_version) {
-                    println!(
-                        "INL retro-prog was found with firmware version {}.{}.{}",
-                        firmware_version.major(),
-                        firmware_version.minor(),
-                        firmware_version.sub_minor()
-                    );
-                    return Some(device_handle);
-                } else {
-                    println!("INL Retro-Prog found, but firmware is too old!");
-                }
+fn info(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    let json = cmd_options.json;
+    match cmd_options.console.as_str() {
+        "nes" => {
+            // NES carts have no header, mirroring is all we can tell without a mapper.
+            nes::init(programmer)?;
+            let mirroring = nes::detect_mapper_mirroring(programmer)?;
+            if json {
+                print_json("nes", &(), &serde_json::json!({ "mirroring": mirroring }))?;
+            } else {
+                println!("Mirroring: {:?}", mirroring);
+            }
+        }
+        "snes" => {
+            snes::init(programmer)?;
+            let header = snes::read_header(programmer)?;
+            if json {
+                print_json("snes", &header, &snes::decode_header(&header))?;
+            } else {
+                snes::print_header(&header);
+            }
+        }
+        "gb" => {
+            gb::init(programmer)?;
+            let header = gb::read_header(programmer)?;
+            if json {
+                print_json("gb", &header, &gb::decode_header(&header))?;
+            } else {
+                gb::print_header(&header);
+            }
+        }
+        "gba" => {
+            gba::init(programmer)?;
+            let header = gba::read_header(programmer)?;
+            if json {
+                print_json("gba", &header, &gba::decode_header(&header))?;
+            } else {
+                gba::print_header(&header);
+            }
+        }
+        _ => {
+            genesis::init(programmer)?;
+            let header = genesis::read_header(programmer)?;
+            if json {
+                print_json("genesis", &header, &genesis::decode_header(&header))?;
+            } else {
+                genesis::print_header(&header);
             }
-            break;
         }
     }
-    return None;
+    return Ok(());
+}
+
+// The header fields as read under "raw" and what they mean under "decoded",
+// NES has no header so raw is null.
+fn print_json<R: Serialize, D: Serialize>(console: &str, raw: &R, decoded: &D) -> Result<()> {
+    let info = serde_json::json!({ "console": console, "raw": raw, "decoded": decoded });
+    println!("{}", serde_json::to_string_pretty(&info).map_err(io::Error::from)?);
+    return Ok(());
 }
 
-// Since partial ord is not implemented for Version we do this ugly comparsion.
-// TODO: Support minor and sub_minor minimum version
-fn check_version(device_version: Version) -> bool {
-    if device_version.major() >= MIN_MAJOR_FW_VERSION {
-        if device_version.minor() > 0 {
-            return true;
+fn dump_rom(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    // Read the DAT first so a bad one does not fail after the whole dump.
+    let dat = if cmd_options.dat.is_empty() { None } else { Some(Dat::load(&cmd_options.dat)?) };
+    // Named from the header the ROM is dumped under a stand-in for the CRC32,
+    // and renamed once it is known.
+    let cart = if cmd_options.file.is_empty() { Some(cart_name(programmer, cmd_options)?) } else { None };
+    let file = &match &cart {
+        Some(cart) => naming::pending_rom_name(&cmd_options.name, cart)?,
+        None => cmd_options.file.clone(),
+    };
+    let mut bar = ProgressBar::default();
+    let bar: &mut dyn ProgressObserver = if cmd_options.log_level == LevelFilter::Error { &mut Silent } else { &mut bar };
+    match cmd_options.console.as_str() {
+        "nes" => nes::dump_nes(
+            programmer,
+            &cmd_options.mapper,
+            cmd_options.prg_size,
+            cmd_options.chr_size,
+            &cmd_options.board,
+            file,
+            cmd_options.verify,
+            bar,
+        )?,
+        "snes" => snes::dump_snes(programmer, Some(file), None, cmd_options.resume, cmd_options.verify, bar)?,
+        "gb" => gb::dump_gb(programmer, Some(file), None, cmd_options.verify, bar)?,
+        "gba" => gba::dump_gba(programmer, Some(file), cmd_options.resume, cmd_options.verify, bar)?,
+        _ => genesis::dump_genesis(programmer, Some(file), None, cmd_options.resume, cmd_options.verify, bar)?,
+    }
+
+    let hashes = Hashes::of_dump(&cmd_options.console, file)?;
+    let mut file = file.clone();
+    if let Some(cart) = cart {
+        let named = naming::rom_name(&cmd_options.name, &cart, hashes.crc32)?;
+        if named != file {
+            fs::rename(&file, &named)?;
+            println!("Renamed to {}", named);
+            file = named;
+        }
+    }
+    // The save goes next to the ROM under the same name, as emulators look for it there.
+    if let Some(wram_size) = cmd_options.wram_size {
+        let savefile = Path::new(&file).with_extension("sav");
+        let savefile = savefile.to_string_lossy();
+        nes::dump_nes_save(programmer, &cmd_options.mapper, wram_size, &savefile, cmd_options.verify, bar)?;
+    }
+    dat::print_hashes(&hashes);
+    if let Some(dat) = dat {
+        println!("{}", dat.lookup(&hashes));
+    }
+    return Ok(());
+}
+
+fn dump_save(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    let file = &if cmd_options.file.is_empty() {
+        naming::save_name(&cmd_options.name, &cart_name(programmer, cmd_options)?)?
+    } else {
+        cmd_options.file.clone()
+    };
+    let mut bar = ProgressBar::default();
+    let bar: &mut dyn ProgressObserver = if cmd_options.log_level == LevelFilter::Error { &mut Silent } else { &mut bar };
+    match cmd_options.console.as_str() {
+        "snes" => snes::dump_snes(programmer, None, Some(file), false, cmd_options.verify, bar)?,
+        "gb" => gb::dump_gb(programmer, None, Some(file), cmd_options.verify, bar)?,
+        "genesis" => genesis::dump_genesis(programmer, None, Some(file), false, cmd_options.verify, bar)?,
+        "nes" => {
+            let wram_size = cmd_options.wram_size.unwrap_or(NES_WRAM_SIZE);
+            nes::dump_nes_save(programmer, &cmd_options.mapper, wram_size, file, cmd_options.verify, bar)?;
         }
-        if device_version.sub_minor() > 0 {
-            return true;
+        _ => {
+            return Err(Error::Unsupported(format!("Dumping save RAM for {} is not supported yet.", cmd_options.console)));
         }
     }
-    return false;
+    return Ok(());
+}
+
+fn write_save(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    if cmd_options.console != "nes" {
+        return Err(Error::Unsupported(format!("Writing save RAM for {} is not supported yet.", cmd_options.console)));
+    }
+    if !cmd_options.yes && !confirm(&format!("This overwrites the save on the cart with {}.", cmd_options.file))? {
+        return Err(Error::Cancelled(String::from("Not written, the save on the cart is unchanged.")));
+    }
+    let mut bar = ProgressBar::default();
+    let bar: &mut dyn ProgressObserver = if cmd_options.log_level == LevelFilter::Error { &mut Silent } else { &mut bar };
+    return nes::write_nes_save(programmer, &cmd_options.mapper, &cmd_options.file, bar);
+}
+
+// Ask on stderr, only an answer of yes goes on.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} Type yes to continue: ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        // No answer to end the line with.
+        eprintln!();
+    }
+    return Ok(answer.trim().eq_ignore_ascii_case("yes"));
+}
+
+// What the cart header says to name its dumps after.
+fn cart_name(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<CartName> {
+    let cart = match cmd_options.console.as_str() {
+        "nes" => nes::cart_name(),
+        "snes" => {
+            snes::init(programmer)?;
+            snes::cart_name(&snes::read_header(programmer)?)
+        }
+        "gb" => {
+            gb::init(programmer)?;
+            gb::cart_name(&gb::read_header(programmer)?)
+        }
+        "gba" => {
+            gba::init(programmer)?;
+            gba::cart_name(&gba::read_header(programmer)?)
+        }
+        _ => {
+            genesis::init(programmer)?;
+            genesis::cart_name(&genesis::read_header(programmer)?)
+        }
+    };
+    return Ok(cart);
+}
+
+fn test_cart(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    if cmd_options.console == "nes" {
+        return nes::test_cart(programmer, &cmd_options.mapper);
+    }
+    // The other consoles have no flash ID test yet, a readable header is the test.
+    return info(programmer, cmd_options);
+}
+
+fn diagnose(programmer: &Programmer, cmd_options: &cli::CommandLineOptions) -> Result<()> {
+    let report = match cmd_options.console.as_str() {
+        "nes" => {
+            nes::init(programmer)?;
+            nes::diagnose(programmer)?
+        }
+        "snes" => {
+            snes::init(programmer)?;
+            snes::diagnose(programmer)?
+        }
+        "gb" => {
+            gb::init(programmer)?;
+            gb::diagnose(programmer)?
+        }
+        "gba" => {
+            gba::init(programmer)?;
+            gba::diagnose(programmer)?
+        }
+        _ => {
+            genesis::init(programmer)?;
+            genesis::diagnose(programmer)?
+        }
+    };
+    inlrust::diagnose::print_report(&report);
+    return Ok(());
 }
@@ -0,0 +1,246 @@
+// Naming dump files from the cart header with a template like
+// "{title} ({region}) [{crc32}].{ext}". Each console module builds a CartName
+// from its header, see snes::cart_name.
+use std::fs;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+pub const DEFAULT_TEMPLATE: &str = "{title} ({region}) [{crc32}].{ext}";
+
+// NES carts have no header to take a title or region from.
+pub const NES_TEMPLATE: &str = "{crc32}.{ext}";
+
+const FIELDS: [&str; 5] = ["title", "region", "code", "crc32", "ext"];
+
+// Stands in for the CRC32 while the ROM is dumped, it is only known after.
+const PENDING_CRC32: &str = "dumping";
+
+// What the header tells about the cart, None where the console has no such field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartName {
+    pub title: Option<String>,
+    pub region: Option<String>,
+    // Game code or serial number.
+    pub code: Option<String>,
+    pub rom_extension: &'static str,
+    pub save_extension: &'static str,
+}
+
+impl CartName {
+    pub fn new(rom_extension: &'static str, save_extension: &'static str) -> CartName {
+        return CartName { title: None, region: None, code: None, rom_extension, save_extension };
+    }
+
+    // Header strings are padded with spaces or zeros and may hold characters
+    // that are not allowed in file names, those are left out.
+    pub fn with_title(mut self, title: &str) -> CartName {
+        self.title = sanitize(title);
+        return self;
+    }
+
+    pub fn with_region(mut self, region: &str) -> CartName {
+        self.region = sanitize(region);
+        return self;
+    }
+
+    pub fn with_code(mut self, code: &str) -> CartName {
+        self.code = sanitize(code);
+        return self;
+    }
+}
+
+pub fn default_template(console: &str) -> &'static str {
+    return if console == "nes" { NES_TEMPLATE } else { DEFAULT_TEMPLATE };
+}
+
+// Fails on an unknown field or a { without its }.
+pub fn check(template: &str) -> Result<()> {
+    return fill(template, |_| Ok(String::new())).map(|_| ());
+}
+
+pub fn uses_crc32(template: &str) -> bool {
+    return template.contains("{crc32}");
+}
+
+pub fn rom_name(template: &str, cart: &CartName, crc32: u32) -> Result<String> {
+    return render(template, cart, cart.rom_extension, &format!("{:08X}", crc32));
+}
+
+// The name to dump the ROM to until its CRC32 is known, the same for every
+// try so an interrupted dump can be resumed.
+pub fn pending_rom_name(template: &str, cart: &CartName) -> Result<String> {
+    return render(template, cart, cart.rom_extension, PENDING_CRC32);
+}
+
+// The save is named like the ROM of the same cart so emulators pair them up.
+// Its CRC32 is taken from that ROM dump, which then has to be there already.
+pub fn save_name(template: &str, cart: &CartName) -> Result<String> {
+    if !uses_crc32(template) {
+        return render(template, cart, cart.save_extension, "");
+    }
+    let crc32 = find_rom(template, cart)?.ok_or_else(|| {
+        Error::Name(format!(
+            "no ROM dump named like {} to pair the save with, dump the ROM first or leave {{crc32}} out of --name",
+            render(template, cart, cart.rom_extension, "XXXXXXXX").unwrap_or_default()
+        ))
+    })?;
+    return render(template, cart, cart.save_extension, &crc32);
+}
+
+// The CRC32 in the name of an earlier ROM dump of this cart.
+fn find_rom(template: &str, cart: &CartName) -> Result<Option<String>> {
+    // Control characters never make it into a field, so this can only be the CRC32.
+    let marked = render(template, cart, cart.rom_extension, "\0")?;
+    let pieces: Vec<&str> = marked.split('\0').collect();
+    let directory = match Path::new(pieces[0]).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(None),
+    };
+
+    let mut found = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let path = path.to_string_lossy();
+        // Compare without a leading ./ when the template has none.
+        let name = if directory == Path::new(".") { path.trim_start_matches("./") } else { &path };
+        if let Some(crc32) = match_crc32(name, &pieces) {
+            found.push(crc32);
+        }
+    }
+    found.sort();
+    found.dedup();
+    if found.len() > 1 {
+        return Err(Error::Name(format!("ROM dumps with different CRC32s ({}) match, name the save file", found.join(", "))));
+    }
+    return Ok(found.pop());
+}
+
+// The CRC32 in name if it is the template with an 8 digit hex value between each piece.
+fn match_crc32(name: &str, pieces: &[&str]) -> Option<String> {
+    let mut rest = name.strip_prefix(pieces[0])?;
+    let mut crc32: Option<&str> = None;
+    for piece in &pieces[1..] {
+        let value = rest.get(..8)?;
+        if !value.chars().all(|c| c.is_ascii_hexdigit()) || crc32.is_some_and(|crc32| crc32 != value) {
+            return None;
+        }
+        crc32 = Some(value);
+        rest = rest[8..].strip_prefix(piece)?;
+    }
+    if !rest.is_empty() {
+        return None;
+    }
+    return crc32.map(|crc32| crc32.to_uppercase());
+}
+
+fn render(template: &str, cart: &CartName, extension: &str, crc32: &str) -> Result<String> {
+    let known = |value: &Option<String>, field: &str| {
+        value.clone().ok_or_else(|| {
+            Error::Name(format!("the cart header has no {{{}}}, give a file name or a --name without it", field))
+        })
+    };
+    return fill(template, |field| match field {
+        "title" => known(&cart.title, field),
+        "region" => known(&cart.region, field),
+        "code" => known(&cart.code, field),
+        "crc32" => Ok(crc32.to_owned()),
+        _ => Ok(extension.to_owned()),
+    });
+}
+
+// Replace every {field} with what value returns for it.
+fn fill<F: Fn(&str) -> Result<String>>(template: &str, value: F) -> Result<String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| Error::Name(format!("{{ without }} in \"{}\"", template)))?;
+        let field = &rest[start + 1..start + end];
+        if !FIELDS.contains(&field) {
+            return Err(Error::Name(format!(
+                "unknown field {{{}}} in \"{}\", use {{{}}}",
+                field,
+                template,
+                FIELDS.join("}, {")
+            )));
+        }
+        name.push_str(&value(field)?);
+        rest = &rest[start + end + 1..];
+    }
+    name.push_str(rest);
+    return Ok(name);
+}
+
+fn sanitize(text: &str) -> Option<String> {
+    let allowed: String = text
+        .chars()
+        .map(|c| if c.is_control() || "<>:\"/\\|?*\u{FFFD}".contains(c) { ' ' } else { c })
+        .collect();
+    // Windows drops dots at the end of a name.
+    let name = allowed.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let name = name.trim_end_matches('.');
+    return if name.is_empty() { None } else { Some(name.to_owned()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cart() -> CartName {
+        return CartName::new("sfc", "srm").with_title("ZELDA").with_region("USA");
+    }
+
+    #[test]
+    fn fill_replaces_every_field() {
+        let name = fill("{title}-{title} [{crc32}]", |field| Ok(field.to_uppercase())).unwrap();
+        assert_eq!(name, "TITLE-TITLE [CRC32]");
+        assert_eq!(fill("no fields", |_| Ok(String::new())).unwrap(), "no fields");
+    }
+
+    #[test]
+    fn fill_rejects_bad_templates() {
+        assert!(fill("{title", |_| Ok(String::new())).is_err());
+        assert!(fill("{size}", |_| Ok(String::new())).is_err());
+        assert!(check(DEFAULT_TEMPLATE).is_ok());
+        assert!(check(NES_TEMPLATE).is_ok());
+    }
+
+    #[test]
+    fn names_from_the_cart() {
+        assert_eq!(rom_name(DEFAULT_TEMPLATE, &cart(), 0xAB12).unwrap(), "ZELDA (USA) [0000AB12].sfc");
+        assert_eq!(pending_rom_name(DEFAULT_TEMPLATE, &cart()).unwrap(), "ZELDA (USA) [dumping].sfc");
+        assert_eq!(save_name("{title}.{ext}", &cart()).unwrap(), "ZELDA.srm");
+        // NES carts have no title.
+        assert!(rom_name(DEFAULT_TEMPLATE, &CartName::new("nes", "sav"), 0).is_err());
+    }
+
+    #[test]
+    fn sanitize_header_text() {
+        assert_eq!(sanitize("  SUPER  MARIO\0\0\0"), Some(String::from("SUPER MARIO")));
+        assert_eq!(sanitize("A/B: C?"), Some(String::from("A B C")));
+        assert_eq!(sanitize("GAME..."), Some(String::from("GAME")));
+        assert_eq!(sanitize(" \0\u{FFFD} "), None);
+    }
+
+    #[test]
+    fn crc32_from_a_rom_name() {
+        let pieces = ["ZELDA (USA) [", "].sfc"];
+        assert_eq!(match_crc32("ZELDA (USA) [0000ab12].sfc", &pieces), Some(String::from("0000AB12")));
+        assert_eq!(match_crc32("ZELDA (USA) [dumping].sfc", &pieces), None);
+        assert_eq!(match_crc32("ZELDA (USA) [0000AB12].sfc.bak", &pieces), None);
+        assert_eq!(match_crc32("ZELDA (JPN) [0000AB12].sfc", &pieces), None);
+        // The same CRC32 everywhere it is used.
+        let pieces = ["", "-", ".sfc"];
+        assert_eq!(match_crc32("0000AB12-0000AB12.sfc", &pieces), Some(String::from("0000AB12")));
+        assert_eq!(match_crc32("0000AB12-0000AB13.sfc", &pieces), None);
+        // No {crc32} in the template.
+        assert_eq!(match_crc32("ZELDA.sfc", &["ZELDA.sfc"]), None);
+    }
+}
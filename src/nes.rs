@@ -1,131 +1,481 @@
-use rusb::{DeviceHandle, UsbContext};
-use std::fs::File;
+use crate::error::{Error, Result};
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{debug, info, trace, warn};
+use serde::Serialize;
 
 use crate::io;
 use crate::pinport;
 use crate::util;
-use crate::util::CommandLineOptions;
+use crate::util::OutputFile;
 use crate::opcodes::nes::*;
-use crate::nes_mappers::{nrom, mmc1, unrom, cnrom, mmc3};
+use crate::nes_mappers::{self, NesMapper};
+use crate::opcodes::buffer as op_buffer;
+use crate::progress::{ProgressObserver, Tracker};
+use crate::verify::Verify;
+use crate::reader::{BankSource, CartReader};
+use crate::diagnose::{self, Bus, Pin, Report};
+use crate::naming::CartName;
+
+// Name and iNES number of every mapper that can be dumped.
+pub fn mappers() -> Vec<(&'static str, u16)> {
+    return nes_mappers::all().iter().map(|mapper| (mapper.name(), mapper.number())).collect();
+}
+
+// The name of a mapper given by name or iNES number, None if it can't be dumped.
+pub fn mapper_name(mapper: &str) -> Option<&'static str> {
+    return nes_mappers::find(mapper).ok().map(|mapper| mapper.name());
+}
 
-pub fn dump_nes<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions) {
-    println!("IO_RESET");
-    io::reset(&device_handle);
+pub fn init<T: Transport>(device_handle: &T) -> Result<()> {
+    debug!("IO_RESET");
+    io::reset(device_handle)?;
     // NES INIT
-    println!("NES_INIT");
-    io::nes_init(&device_handle);
-
-    if cmd_options.mapper.to_lowercase() == "nrom" {
-        nrom::test_nrom(&device_handle);
-        // MIRROR
-        //   detect_mapper_mirroring
-        //   ciccom
-        // READ
-        let file = File::create(&cmd_options.filename).unwrap();
-        let mut f = BufWriter::new(file);
-
-        //   create_header
-        let mirroring = detect_mapper_mirroring(&device_handle).unwrap();
-        create_header(&mut f, cmd_options.prg_size, cmd_options.chr_size, 0, mirroring);
-        nrom::dump_prgrom(&device_handle, &mut f, cmd_options.prg_size);
-        nrom::dump_chrrom(&device_handle, &mut f, cmd_options.chr_size);
-
-        f.flush().unwrap();
-    } else if cmd_options.mapper.to_lowercase() == "mmc1" {
-        mmc1::test_mmc1(&device_handle);
-
-        mmc1::init_mapper_mmc1(&device_handle);
-        let file = File::create(&cmd_options.filename).unwrap();
-        let mut f = BufWriter::new(file);
-        create_header(&mut f, cmd_options.prg_size, cmd_options.chr_size, 1, Mirroring::HORZ);
-        mmc1::dump_prgrom_mmc1(&device_handle, &mut f, cmd_options.prg_size);
-        mmc1::dump_chrrom_mmc1(&device_handle, &mut f, cmd_options.chr_size);
-
-        f.flush().unwrap();
-    } else if cmd_options.mapper.to_lowercase() == "unrom" {
-        unrom::test_unrom(&device_handle);
-
-        // find bank table to avoid bus conflicts
-        let kb_per_bank = 16;
-        // Size is one byte smaller because table doesn't need fixed bank.
-        let banktable_size = cmd_options.prg_size / kb_per_bank - 1;
-        let banktable_base = unrom::find_banktable(&device_handle, banktable_size as u8);
-        println!("Found banktable addr = {}", banktable_base);
-
-        let file = File::create(&cmd_options.filename).unwrap();
-        let mut f = BufWriter::new(file);
-        let mirroring = detect_mapper_mirroring(&device_handle).unwrap();
-        create_header(&mut f, cmd_options.prg_size, cmd_options.chr_size, 2, mirroring);
-        unrom::dump_prgrom_unrom(&device_handle, &mut f, cmd_options.prg_size, banktable_base);
-
-        f.flush().unwrap();
-    } else if cmd_options.mapper.to_lowercase() == "cnrom" {
-        cnrom::test_cnrom(&device_handle);
-
-        let file = File::create(&cmd_options.filename).unwrap();
-        let mut f = BufWriter::new(file);
-
-        let mirroring = detect_mapper_mirroring(&device_handle).unwrap();
-        create_header(&mut f, cmd_options.prg_size, cmd_options.chr_size, 3, mirroring);
-        cnrom::dump_prgrom(&device_handle, &mut f, cmd_options.prg_size);
-        cnrom::dump_chrrom(&device_handle, &mut f, cmd_options.chr_size);
-
-        f.flush().unwrap();
-    } else if cmd_options.mapper.to_lowercase() == "mmc3" {
-        mmc3::test_mmc3(&device_handle);
-
-        let file = File::create(&cmd_options.filename).unwrap();
-        let mut f = BufWriter::new(file);
-
-        mmc3::init_mapper(&device_handle);
-        let mirroring = detect_mapper_mirroring(&device_handle).unwrap();
-        create_header(&mut f, cmd_options.prg_size, cmd_options.chr_size, 4, mirroring);
-        mmc3::dump_prgrom(&device_handle, &mut f, cmd_options.prg_size);
-        mmc3::dump_chrrom(&device_handle, &mut f, cmd_options.chr_size);
-
-        f.flush().unwrap();
+    debug!("NES_INIT");
+    io::nes_init(device_handle)?;
+    return Ok(());
+}
+
+// Run the mapper test, it prints mirroring and the flash IDs of PRG and CHR.
+pub fn test_cart<T: Transport>(device_handle: &T, mapper: &str) -> Result<()> {
+    let mapper = nes_mappers::find(mapper)?;
+    init(device_handle)?;
+    mapper.test(device_handle)?;
+    io::reset(device_handle)?;
+    return Ok(());
+}
+
+// How the image header is written, NES 2.0 unless an old emulator needs iNES 1.0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderFormat {
+    Nes2,
+    Ines,
+}
+
+// CPU/PPU timing of the console the cart is made for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // Works on both.
+    Multi,
+    Dendy,
+}
+
+// What the header tells about the board that can not be read from the cart.
+// Sizes are in bytes, a power of two from 128 bytes to 2 MB or 0 for none.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Board {
+    pub format: HeaderFormat,
+    pub submapper: u8,
+    pub prg_ram: u32,
+    // Battery backed PRG-RAM, it sets the battery flag.
+    pub prg_nvram: u32,
+    // None senses 8KB of CHR-RAM on carts without CHR-ROM.
+    pub chr_ram: Option<u32>,
+    pub battery: bool,
+    // Four screen VRAM on the cart instead of mirroring.
+    pub four_screen: bool,
+    pub timing: Timing,
+}
+
+impl Default for Board {
+    fn default() -> Board {
+        return Board {
+            format: HeaderFormat::Nes2,
+            submapper: 0,
+            prg_ram: 0,
+            prg_nvram: 0,
+            chr_ram: None,
+            battery: false,
+            four_screen: false,
+            timing: Timing::Ntsc,
+        };
+    }
+}
+
+// Dump PRG-ROM and CHR-ROM of the given sizes in kilobytes to filename as an
+// NES 2.0 or iNES image, board fills in the rest of the header. Sizes that
+// are None are detected, see detect_sizes.
+#[allow(clippy::too_many_arguments)]
+pub fn dump_nes<T: Transport>(
+    device_handle: &T,
+    mapper: &str,
+    prg_size: Option<u16>,
+    chr_size: Option<u16>,
+    board: &Board,
+    filename: &str,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    check_rom_sizes(mapper, prg_size, chr_size)?;
+    let mapper = nes_mappers::find(mapper)?;
+    init(device_handle)?;
+    let (prg_size, chr_size) = match (prg_size, chr_size) {
+        (Some(prg_size), Some(chr_size)) => (prg_size, chr_size),
+        _ => {
+            let (prg_detected, chr_detected) = detect_sizes(device_handle, mapper.name())?;
+            (prg_size.unwrap_or(prg_detected), chr_size.unwrap_or(chr_detected))
+        }
+    };
+    let mut progress = Tracker::new(progress, (prg_size as u64 + chr_size as u64) * 1024, verify);
+
+    let mut board = board.clone();
+    if board.chr_ram.is_none() {
+        let sensed = chr_size == 0 && ppu_ram_sense(device_handle, 0x0000)?;
+        board.chr_ram = Some(if sensed { 8 * 1024 } else { 0 });
+    }
+
+    mapper.test(device_handle)?;
+    mapper.init(device_handle)?;
+    let mirroring = mapper.mirroring(device_handle)?;
+    let bank_table = mapper.bank_table(device_handle, prg_size)?;
+
+    let mut f = OutputFile::create(filename)?;
+    create_header(&mut f, prg_size, chr_size, mapper.number(), mirroring, &board)?;
+    mapper.dump_prg(device_handle, &mut f, prg_size, bank_table, &mut progress)?;
+    mapper.dump_chr(device_handle, &mut f, chr_size, &mut progress)?;
+
+    progress.finish();
+    f.commit()?;
+    return Ok(());
+}
+
+// Dump the battery backed WRAM at CPU $6000-$7FFF of size_kb kilobytes to
+// filename. The mapper enables it only for the dump.
+pub fn dump_nes_save<T: Transport>(
+    device_handle: &T,
+    mapper: &str,
+    size_kb: u16,
+    filename: &str,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let mapper = nes_mappers::find(mapper)?;
+    check_wram_size(mapper, size_kb)?;
+    init(device_handle)?;
+    mapper.init(device_handle)?;
+    let mut progress = Tracker::new(progress, size_kb as u64 * 1024, verify);
+
+    info!("Dumping {} KB WRAM...", size_kb);
+    let mut f = OutputFile::create(filename)?;
+    let result = dump_wram(device_handle, mapper, size_kb, &mut f, &mut progress);
+    mapper.disable_wram(device_handle)?;
+    result?;
+    progress.finish();
+    f.commit()?;
+    return Ok(());
+}
+
+fn dump_wram<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    mapper: &dyn NesMapper,
+    size_kb: u16,
+    file: &mut W,
+    progress: &mut Tracker,
+) -> Result<()> {
+    for bank in 0..size_kb / 8 {
+        mapper.select_wram(device_handle, bank, size_kb, false)?;
+        progress.set_bank(bank as u32);
+        util::dump(device_handle, file, 8, 0x06, op_buffer::NESCPU_4KB, progress)?;
+    }
+    return Ok(());
+}
+
+// Overwrite the battery backed WRAM with the save in filename and read it back
+// to check it. WRAM is writable only while the save is written.
+pub fn write_nes_save<T: Transport>(
+    device_handle: &T,
+    mapper: &str,
+    filename: &str,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let mapper = nes_mappers::find(mapper)?;
+    let save = std::fs::read(filename)?;
+    if save.len() % 1024 != 0 {
+        return Err(Error::Unsupported(format!("{} is {} bytes, not a whole number of KB.", filename, save.len())));
+    }
+    let size_kb = (save.len() / 1024) as u16;
+    check_wram_size(mapper, size_kb)?;
+    init(device_handle)?;
+    mapper.init(device_handle)?;
+    // Every byte is written and then read back.
+    let mut progress = Tracker::new(progress, save.len() as u64 * 2, Verify::OFF);
+
+    info!("Writing {} KB WRAM...", size_kb);
+    let result = write_wram(device_handle, mapper, &save, &mut progress);
+    mapper.disable_wram(device_handle)?;
+    result?;
+    progress.finish();
+    info!("Wrote {} KB from {} to the cart and read it back", size_kb, filename);
+    return Ok(());
+}
+
+fn write_wram<T: Transport>(
+    device_handle: &T,
+    mapper: &dyn NesMapper,
+    save: &[u8],
+    progress: &mut Tracker,
+) -> Result<()> {
+    let size_kb = (save.len() / 1024) as u16;
+    for (bank, data) in save.chunks(8 * 1024).enumerate() {
+        mapper.select_wram(device_handle, bank as u16, size_kb, true)?;
+        progress.set_bank(bank as u32);
+        for (page, bytes) in data.chunks(256).enumerate() {
+            for (i, byte) in bytes.iter().enumerate() {
+                cpu_wr(device_handle, 0x6000 + (page * 256 + i) as u16, *byte as u16)?;
+            }
+            progress.advance(bytes.len());
+        }
+
+        let mut read = vec![0; data.len()];
+        util::dump_to_array(device_handle, &mut read, 8, 0x06, op_buffer::NESCPU_4KB, progress)?;
+        let differing: Vec<usize> = (0..data.len()).filter(|i| read[*i] != data[*i]).collect();
+        if let Some(first) = differing.first() {
+            return Err(Error::Verify(format!(
+                "{} bytes in bank {} read back different from what was written, the first at offset 0x{:06X}",
+                differing.len(),
+                bank,
+                bank * 8 * 1024 + first
+            )));
+        }
+    }
+    return Ok(());
+}
+
+// PRG-ROM and CHR-ROM sizes as given with -x and -y, None is detected. They
+// are powers of two the mapper can switch between, CHR-ROM is 0 on CHR-RAM carts.
+pub fn check_rom_sizes(mapper: &str, prg_size: Option<u16>, chr_size: Option<u16>) -> Result<()> {
+    let mapper = nes_mappers::find(mapper)?;
+    let (min_prg, max_prg) = mapper.prg_sizes();
+    if let Some(prg_size) = prg_size {
+        if !prg_size.is_power_of_two() || prg_size < min_prg || prg_size > max_prg {
+            return Err(Error::Unsupported(format!(
+                "PRG-ROM of {} carts is a power of two from {} to {} KB, not {} KB.",
+                mapper.name(),
+                min_prg,
+                max_prg,
+                prg_size
+            )));
+        }
+    }
+    let max_chr = mapper.max_chr_size();
+    if let Some(chr_size) = chr_size {
+        if max_chr == 0 && chr_size != 0 {
+            return Err(Error::Unsupported(format!("{} carts have CHR-RAM, CHR-ROM size is 0 KB.", mapper.name())));
+        }
+        if chr_size != 0 && (!chr_size.is_power_of_two() || chr_size < 8 || chr_size > max_chr) {
+            return Err(Error::Unsupported(format!(
+                "CHR-ROM of {} carts is 0 KB for CHR-RAM or a power of two from 8 to {} KB, not {} KB.",
+                mapper.name(),
+                max_chr,
+                chr_size
+            )));
+        }
+    }
+    return Ok(());
+}
+
+fn check_wram_size(mapper: &dyn NesMapper, size_kb: u16) -> Result<()> {
+    let (sizes, listed) = mapper.wram_sizes();
+    if !sizes.contains(&size_kb) {
+        return Err(Error::Unsupported(format!("WRAM of {} carts is {} KB, not {} KB.", mapper.name(), listed, size_kb)));
+    }
+    return Ok(());
+}
+
+// PRG-ROM and CHR-ROM sizes in kilobytes read from the cart. A ROM smaller
+// than the mapper can address shows up again past its end, so its size is
+// where its content first repeats. CHR is 0 on carts with CHR-RAM.
+pub fn detect_sizes<T: Transport>(device_handle: &T, mapper: &str) -> Result<(u16, u16)> {
+    let mapper = nes_mappers::find(mapper)?;
+    let (min_prg, max_prg) = mapper.prg_sizes();
+    init(device_handle)?;
+    mapper.init(device_handle)?;
+
+    let chr_ram = mapper.max_chr_size() == 0 || ppu_ram_sense(device_handle, 0x0000)?;
+    let max_chr = if chr_ram { 0 } else { mapper.max_chr_size() };
+    let mut banks = RomBanks { device_handle, mapper, prg_size: max_prg, chr_size: max_chr, bank_table: 0 };
+    let prg_size = match mapper.detect_prg_size(device_handle)? {
+        Some(prg_size) => prg_size,
+        None => repeat_size(&mut banks, 0, min_prg, max_prg)?,
+    };
+    let chr_size = if chr_ram { 0 } else { repeat_size(&mut banks, max_prg as u64 * 1024, 8, max_chr)? };
+
+    if chr_ram {
+        info!("Detected {} KB PRG-ROM and 8 KB CHR-RAM", prg_size);
     } else {
-        println!("Mapper {} is not supported!", cmd_options.mapper);
+        info!("Detected {} KB PRG-ROM and {} KB CHR-ROM", prg_size, chr_size);
     }
+    return Ok((prg_size, chr_size));
 }
 
-// General NES functions
+// The smallest size from min_kb up where the ROM starting at base repeats,
+// max_kb if it does not. A few blocks are compared so two banks that happen
+// to start the same are not taken for a repeat.
+fn repeat_size<S: BankSource>(source: &mut S, base: u64, min_kb: u16, max_kb: u16) -> Result<u16> {
+    let block = source.block_size();
+    let mut read = |offset: u64| -> Result<Vec<u8>> {
+        let mut buf = vec![0; block];
+        source.read_block(base + offset, &mut buf)?;
+        return Ok(buf);
+    };
 
-fn create_header<W: Write>(
-    file: &mut BufWriter<W>,
+    let mut kb = min_kb;
+    while kb < max_kb {
+        let size = kb as u64 * 1024;
+        let mut offsets = vec![0, size / 2, size - block as u64];
+        offsets.dedup();
+        let mut repeats = true;
+        for offset in offsets {
+            if read(size + offset)? != read(offset)? {
+                repeats = false;
+                break;
+            }
+        }
+        if repeats {
+            return Ok(kb);
+        }
+        kb *= 2;
+    }
+    return Ok(max_kb);
+}
+
+// PRG-ROM followed by CHR-ROM, as in an iNES image without header. See cart_reader.
+pub struct RomBanks<'a, T: Transport> {
+    device_handle: &'a T,
+    mapper: &'static dyn NesMapper,
+    prg_size: u16,
+    chr_size: u16,
+    bank_table: u16,
+}
+
+// Random access to PRG-ROM and CHR-ROM of the given sizes in kilobytes.
+pub fn cart_reader<'a, T: Transport>(
+    device_handle: &'a T,
+    mapper: &str,
     prg_size: u16,
     chr_size: u16,
-    mapper: u8,
-    mirroring: Mirroring,
-) {
-    file.write(b"NES").unwrap();
+) -> Result<CartReader<RomBanks<'a, T>>> {
+    let mapper = nes_mappers::find(mapper)?;
+    init(device_handle)?;
+    mapper.init(device_handle)?;
+    let bank_table = mapper.bank_table(device_handle, prg_size)?;
+    return Ok(CartReader::new(RomBanks { device_handle, mapper, prg_size, chr_size, bank_table }));
+}
 
-    file.write_all(&[0x1A]).unwrap();
-    // byte 4
-    file.write_all(&[(prg_size / 16) as u8]).unwrap();
-    // byte 5
-    file.write_all(&[(chr_size / 8) as u8]).unwrap();
+impl<T: Transport> BankSource for RomBanks<'_, T> {
+    fn size(&self) -> u64 {
+        return (self.prg_size as u64 + self.chr_size as u64) * 1024;
+    }
 
-    // byte 6
-    let mut temp = mapper & 0x0F;
-    temp = temp << 4;
+    fn block_size(&self) -> usize {
+        return 4 * 1024;
+    }
 
-    if mirroring == Mirroring::VERT {
-        temp = temp | 0x01;
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let device_handle = self.device_handle;
+        let prg_bytes = self.prg_size as u64 * 1024;
+        let size_kb = (buf.len() / 1024) as u16;
+        if offset < prg_bytes {
+            let offset = offset as u32;
+            let map = self.mapper.select_prg(device_handle, offset, self.prg_size, self.bank_table)?;
+            return util::dump_to_array(device_handle, buf, size_kb, map, op_buffer::NESCPU_4KB, &mut Tracker::silent());
+        }
+
+        let offset = (offset - prg_bytes) as u32;
+        let map = self.mapper.select_chr(device_handle, offset)?;
+        return util::dump_to_array(device_handle, buf, size_kb, map, op_buffer::NESPPU_1KB, &mut Tracker::silent());
     }
-    file.write_all(&[temp]).unwrap();
+}
 
+// General NES functions
+
+fn create_header<W: Write>(
+    file: &mut W,
+    prg_size: u16,
+    chr_size: u16,
+    mapper: u16,
+    mirroring: Option<Mirroring>,
+    board: &Board,
+) -> Result<()> {
+    let prg_units = prg_size / 16;
+    let chr_units = chr_size / 8;
+    let battery = board.battery || board.prg_nvram > 0;
+
+    // byte 6, the mirroring bit stays 0 where the mapper controls mirroring
+    let mut flags6 = ((mapper & 0x0F) as u8) << 4;
+    if mirroring == Some(Mirroring::VERT) {
+        flags6 |= 0x01;
+    }
+    if battery {
+        flags6 |= 0x02;
+    }
+    if board.four_screen {
+        flags6 |= 0x08;
+    }
     // byte 7
-    let temp = mapper & 0xF0;
-    file.write_all(&[temp]).unwrap();
+    let mut flags7 = (mapper & 0xF0) as u8;
+
+    if board.format == HeaderFormat::Ines {
+        let chr_ram = board.chr_ram.unwrap_or(0);
+        let default_chr_ram = if chr_size == 0 { 8 * 1024 } else { 0 };
+        if board.submapper != 0 || board.prg_ram != 0 || board.prg_nvram != 0 || chr_ram != default_chr_ram || board.timing != Timing::Ntsc {
+            warn!("iNES 1.0 has no room for the submapper, RAM sizes and timing, they are left out");
+        }
+        if mapper > 0xFF || prg_units > 0xFF || chr_units > 0xFF {
+            return Err(Error::Unsupported(String::from("The mapper or ROM sizes do not fit in an iNES 1.0 header.")));
+        }
+        file.write_all(b"NES\x1A")?;
+        file.write_all(&[prg_units as u8, chr_units as u8, flags6, flags7])?;
+        // byte 8-15
+        file.write_all(&[0; 8])?;
+        return Ok(());
+    }
+
+    // NES 2.0 identifier
+    flags7 |= 0x08;
+    // byte 8
+    let mapper_msb = (board.submapper << 4) | ((mapper >> 8) & 0x0F) as u8;
+    // byte 9
+    if prg_units > 0xEFF || chr_units > 0xEFF {
+        return Err(Error::Unsupported(String::from("The ROM sizes do not fit in a NES 2.0 header.")));
+    }
+    let size_msb = (((chr_units >> 8) as u8) << 4) | (prg_units >> 8) as u8;
+    // byte 10 and 11
+    let prg_ram = (ram_shift(board.prg_nvram)? << 4) | ram_shift(board.prg_ram)?;
+    let chr_ram = ram_shift(board.chr_ram.unwrap_or(0))?;
+    // byte 12
+    let timing = match board.timing {
+        Timing::Ntsc => 0,
+        Timing::Pal => 1,
+        Timing::Multi => 2,
+        Timing::Dendy => 3,
+    };
+
+    file.write_all(b"NES\x1A")?;
+    file.write_all(&[prg_units as u8, chr_units as u8, flags6, flags7])?;
+    file.write_all(&[mapper_msb, size_msb, prg_ram, chr_ram, timing])?;
+    // byte 13-15, no Vs. System, misc ROMs or default expansion device
+    file.write_all(&[0; 3])?;
+    return Ok(());
+}
 
-    // byte 8-15
-    file.write_all(&[0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+// NES 2.0 stores RAM sizes as 64 << shift.
+fn ram_shift(bytes: u32) -> Result<u8> {
+    if bytes == 0 {
+        return Ok(0);
+    }
+    if !bytes.is_power_of_two() || !(128..=2 * 1024 * 1024).contains(&bytes) {
+        return Err(Error::Unsupported(format!(
+            "{} bytes of RAM can not be put in a NES 2.0 header, use a power of two from 128 bytes to 2 MB",
+            bytes
+        )));
+    }
+    return Ok((bytes.trailing_zeros() - 6) as u8);
 }
 
-#[derive(Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
 pub enum Mirroring {
     VERT,
     HORZ,
@@ -133,66 +483,110 @@ pub enum Mirroring {
     SCNB,
 }
 
-pub fn detect_mapper_mirroring<T: UsbContext>(device_handle: &DeviceHandle<T>) -> Result<Mirroring, String> {
+// NES carts have no header, only {crc32} and {ext} can be used to name a dump.
+pub fn cart_name() -> CartName {
+    return CartName::new("nes", "sav");
+}
+
+pub fn detect_mapper_mirroring<T: Transport>(device_handle: &T) -> Result<Mirroring> {
     // TODO: call mmc3 detection function
     // TODO: call mmc1 detection function
     // TODO: fme7 and other ASIC mappers
 
     // PINPORT ADDR_SET, 0x0800
     //   1       17      0x0800
-    println!("PINPORT_ADDR_SET 0x0800");
-    pinport::addr_set(&device_handle, 0x0800);
+    trace!("PINPORT_ADDR_SET 0x0800");
+    pinport::addr_set(device_handle, 0x0800)?;
     // readH = PINPORT CTL_RD, CIA10         RL=4 (err_code, data_len, LSB, MSB)
     //           1       6       11
-    let read_h = pinport::ctl_rd(&device_handle, 11).unwrap();
-    println!("Read h: {}", read_h);
+    let read_h = pinport::ctl_rd(device_handle, 11)?;
+    trace!("Read h: {}", read_h);
 
     // PINPORT ADDR_SET, 0x0400
-    println!("PINPORT_ADDR_SET 0x0400");
-    pinport::addr_set(&device_handle, 0x0400);
+    trace!("PINPORT_ADDR_SET 0x0400");
+    pinport::addr_set(device_handle, 0x0400)?;
     // readH = PINPORT CTL_RD, CIA10         RL=4 (err_code, data_len, LSB, MSB)
     //           1       6       11
-    let read_v = pinport::ctl_rd(&device_handle, 11).unwrap();
+    let read_v = pinport::ctl_rd(device_handle, 11)?;
 
     if read_v == 0 && read_h == 0 {
-        println!("1SCNA - 1screen A mirroring");
+        info!("1SCNA - 1screen A mirroring");
         return Ok(Mirroring::SCNA);
     } else if read_v != 0 && read_h == 0 {
-        println!("VERT - Vertical mirroring");
+        info!("VERT - Vertical mirroring");
         return Ok(Mirroring::VERT);
     } else if read_v == 0 && read_h != 0 {
-        println!("HORZ - Horizontal mirroring");
+        info!("HORZ - Horizontal mirroring");
         return Ok(Mirroring::HORZ);
     } else {
-        println!("1SCNB - 1screen B mirroring");
+        info!("1SCNB - 1screen B mirroring");
         return Ok(Mirroring::SCNB);
     };
 }
 
-pub fn ppu_ram_sense<T: UsbContext>(device_handle: &DeviceHandle<T>, addr: u16) -> bool {
-    ppu_wr(&device_handle, addr, 0xAA);
-    if ppu_rd(&device_handle, addr) != 0xAA {
-        println!("Could not write 0xAA to PPU {:X}", addr);
-        return false;
+// The PRG-ROM side of the NES 72-pin edge, read at $8000-$FFFF.
+const PRG_BUS: Bus = Bus {
+    connector: "NES 72-pin, CPU bus",
+    base: 0x8000,
+    address: &[
+        (0, Pin { signal: "CPU A0", number: "13" }),
+        (1, Pin { signal: "CPU A1", number: "12" }),
+        (2, Pin { signal: "CPU A2", number: "11" }),
+        (3, Pin { signal: "CPU A3", number: "10" }),
+        (4, Pin { signal: "CPU A4", number: "9" }),
+        (5, Pin { signal: "CPU A5", number: "8" }),
+        (6, Pin { signal: "CPU A6", number: "7" }),
+        (7, Pin { signal: "CPU A7", number: "6" }),
+        (8, Pin { signal: "CPU A8", number: "5" }),
+        (9, Pin { signal: "CPU A9", number: "4" }),
+        (10, Pin { signal: "CPU A10", number: "3" }),
+        (11, Pin { signal: "CPU A11", number: "2" }),
+        (12, Pin { signal: "CPU A12", number: "39" }),
+        (13, Pin { signal: "CPU A13", number: "40" }),
+        (14, Pin { signal: "CPU A14", number: "41" }),
+    ],
+    data: &[
+        Pin { signal: "CPU D0", number: "49" },
+        Pin { signal: "CPU D1", number: "48" },
+        Pin { signal: "CPU D2", number: "47" },
+        Pin { signal: "CPU D3", number: "46" },
+        Pin { signal: "CPU D4", number: "45" },
+        Pin { signal: "CPU D5", number: "44" },
+        Pin { signal: "CPU D6", number: "43" },
+        Pin { signal: "CPU D7", number: "42" },
+    ],
+};
+
+// Look for bad PRG-ROM lines. Only the bank the mapper starts in is read so
+// no mapper is needed, a small PRG-ROM shows as unused upper lines.
+pub fn diagnose<T: Transport>(device_handle: &T) -> Result<Report> {
+    return diagnose::probe(&PRG_BUS, |address| Ok(cpu_rd(device_handle, address as u16)? as u16));
+}
+
+pub fn ppu_ram_sense<T: Transport>(device_handle: &T, addr: u16) -> Result<bool> {
+    ppu_wr(device_handle, addr, 0xAA)?;
+    if ppu_rd(device_handle, addr)? != 0xAA {
+        debug!("Could not write 0xAA to PPU {:X}", addr);
+        return Ok(false);
     }
 
-    ppu_wr(&device_handle, addr, 0x55);
-    if ppu_rd(&device_handle, addr) != 0x55 {
-        println!("Could not write 0x55 to PPU {:X}", addr);
-        return false;
+    ppu_wr(device_handle, addr, 0x55)?;
+    if ppu_rd(device_handle, addr)? != 0x55 {
+        debug!("Could not write 0x55 to PPU {:X}", addr);
+        return Ok(false);
     }
 
-    println!("Detected RAM @ PPU {:X}", addr);
-    return true;
+    info!("Detected RAM @ PPU {:X}", addr);
+    return Ok(true);
 }
 
 // Device functions
 
-pub fn discrete_exp0_prgrom_wr<T: UsbContext>(
-    device_handle: &DeviceHandle<T>,
+pub fn discrete_exp0_prgrom_wr<T: Transport>(
+    device_handle: &T,
     operand: u16,
     misc: u16,
-) {
+) -> Result<()> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 1] = [0; 1];
     util::read_device(
@@ -202,37 +596,89 @@ pub fn discrete_exp0_prgrom_wr<T: UsbContext>(
         DISCRETE_EXP0_PRGROM_WR,
         operand,
         misc,
-    );
+    )
 }
 
-pub fn cpu_rd<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) -> u8 {
+pub fn cpu_rd<T: Transport>(device_handle: &T, operand: u16) -> Result<u8> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 3] = [0; 3];
-    util::read_device(device_handle, &mut buf, request, NES_CPU_RD, operand, 0);
-    return buf[2];
+    util::read_device(device_handle, &mut buf, request, NES_CPU_RD, operand, 0)?;
+    return Ok(buf[2]);
 }
 
-pub fn cpu_wr<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16, misc: u16) {
+pub fn cpu_wr<T: Transport>(device_handle: &T, operand: u16, misc: u16) -> Result<()> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, NES_CPU_WR, operand, misc);
+    util::read_device(device_handle, &mut buf, request, NES_CPU_WR, operand, misc)
 }
 
-pub fn mmc1_wr<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16, misc: u16) {
+pub fn mmc1_wr<T: Transport>(device_handle: &T, operand: u16, misc: u16) -> Result<()> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, NES_MMC1_WR, operand, misc);
+    util::read_device(device_handle, &mut buf, request, NES_MMC1_WR, operand, misc)
 }
 
-pub fn ppu_rd<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) -> u8 {
+pub fn ppu_rd<T: Transport>(device_handle: &T, operand: u16) -> Result<u8> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 3] = [0; 3];
-    util::read_device(device_handle, &mut buf, request, NES_PPU_RD, operand, 0);
-    return buf[2];
+    util::read_device(device_handle, &mut buf, request, NES_PPU_RD, operand, 0)?;
+    return Ok(buf[2]);
 }
 
-pub fn ppu_wr<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16, misc: u16) {
+pub fn ppu_wr<T: Transport>(device_handle: &T, operand: u16, misc: u16) -> Result<()> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, NES_PPU_WR, operand, misc);
+    util::read_device(device_handle, &mut buf, request, NES_PPU_WR, operand, misc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(prg_size: u16, chr_size: u16, mapper: u16, mirroring: Option<Mirroring>, board: &Board) -> Result<Vec<u8>> {
+        let mut header = Vec::new();
+        create_header(&mut header, prg_size, chr_size, mapper, mirroring, board)?;
+        return Ok(header);
+    }
+
+    #[test]
+    fn nes2_header() {
+        let board = Board { submapper: 2, prg_ram: 8 * 1024, prg_nvram: 32 * 1024, timing: Timing::Pal, ..Board::default() };
+        let header = header(256, 128, 0x104, Some(Mirroring::VERT), &board).unwrap();
+        assert_eq!(header, [b'N', b'E', b'S', 0x1A, 16, 16, 0x43, 0x08, 0x21, 0x00, 0x97, 0x00, 0x01, 0, 0, 0]);
+    }
+
+    #[test]
+    fn nes2_header_sizes_above_a_byte() {
+        let header = header(16 * 0x123, 8 * 0x456, 0, None, &Board::default()).unwrap();
+        assert_eq!((header[4], header[5], header[9]), (0x23, 0x56, 0x41));
+        assert!(create_header(&mut Vec::new(), 16 * 0xF00, 0, 0, None, &Board::default()).is_err());
+    }
+
+    #[test]
+    fn ines_header() {
+        let board = Board { format: HeaderFormat::Ines, battery: true, four_screen: true, ..Board::default() };
+        let header = header(128, 0, 1, Some(Mirroring::HORZ), &board).unwrap();
+        assert_eq!(header, [b'N', b'E', b'S', 0x1A, 8, 0, 0x1A, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // The NES 2.0 mapper range does not fit.
+        assert!(create_header(&mut Vec::new(), 32, 8, 0x100, None, &board).is_err());
+    }
+
+    #[test]
+    fn mirroring_bit_only_for_fixed_vertical() {
+        for (mirroring, bit) in [(Some(Mirroring::VERT), 1), (Some(Mirroring::HORZ), 0), (None, 0)] {
+            assert_eq!(header(32, 8, 0, mirroring, &Board::default()).unwrap()[6] & 1, bit, "{:?}", mirroring);
+        }
+    }
+
+    #[test]
+    fn ram_shift_is_a_power_of_two_from_128_bytes() {
+        assert_eq!(ram_shift(0).unwrap(), 0);
+        assert_eq!(ram_shift(128).unwrap(), 1);
+        assert_eq!(ram_shift(8 * 1024).unwrap(), 7);
+        assert_eq!(ram_shift(2 * 1024 * 1024).unwrap(), 15);
+        assert!(ram_shift(64).is_err());
+        assert!(ram_shift(4 * 1024 * 1024).is_err());
+        assert!(ram_shift(24 * 1024).is_err());
+    }
 }
@@ -1,56 +1,61 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{debug, info};
 
 use crate::io;
 use crate::util::dump;
 use crate::nes::{discrete_exp0_prgrom_wr, detect_mapper_mirroring, ppu_wr, ppu_rd, cpu_rd, cpu_wr};
 use crate::opcodes::buffer as op_buffer;
+use crate::progress::Tracker;
+use crate::nes_mappers::NesMapper;
 
-pub fn test_cnrom<T: UsbContext>(device_handle: &DeviceHandle<T>) {
-    println!("Testing CNROM");
-    println!("Detect mapper mirroring");
-    detect_mapper_mirroring(&device_handle).unwrap();
+pub fn test_cnrom<T: Transport>(device_handle: &T) -> Result<()> {
+    info!("Testing CNROM");
+    debug!("Detect mapper mirroring");
+    detect_mapper_mirroring(device_handle)?;
     //    IO EXP0_PULLUP_TEST
-    io::exp0_pullup_test(&device_handle);
+    io::exp0_pullup_test(device_handle)?;
     //    read PRG-ROM manf ID
-    discrete_exp0_prgrom_wr(&device_handle, 0x5555, 0xAA);
-    discrete_exp0_prgrom_wr(&device_handle, 0x2AAA, 0x55);
-    discrete_exp0_prgrom_wr(&device_handle, 0x5555, 0x90);
+    discrete_exp0_prgrom_wr(device_handle, 0x5555, 0xAA)?;
+    discrete_exp0_prgrom_wr(device_handle, 0x2AAA, 0x55)?;
+    discrete_exp0_prgrom_wr(device_handle, 0x5555, 0x90)?;
 
-    let rv = cpu_rd(&device_handle, 0x8000);
-    println!("PRG-ROM manf ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8000)?;
+    info!("PRG-ROM manf ID: 0x{:x}", rv);
 
-    let rv = cpu_rd(&device_handle, 0x8001);
-    println!("PRG-ROM prod ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8001)?;
+    info!("PRG-ROM prod ID: 0x{:x}", rv);
 
-    discrete_exp0_prgrom_wr(&device_handle, 0x8000, 0xF0);
+    discrete_exp0_prgrom_wr(device_handle, 0x8000, 0xF0)?;
 
     //    read CHR-ROM manf ID
-    cpu_wr(&device_handle, 0x8002, 0x02);
-    ppu_wr(&device_handle, 0x1555, 0xAA);
+    cpu_wr(device_handle, 0x8002, 0x02)?;
+    ppu_wr(device_handle, 0x1555, 0xAA)?;
 
-    cpu_wr(&device_handle, 0x8001, 0x01);
-    ppu_wr(&device_handle, 0x0AAA, 0x55);
+    cpu_wr(device_handle, 0x8001, 0x01)?;
+    ppu_wr(device_handle, 0x0AAA, 0x55)?;
 
-    cpu_wr(&device_handle, 0x8002, 0x02);
-    ppu_wr(&device_handle, 0x1555, 0x90);
+    cpu_wr(device_handle, 0x8002, 0x02)?;
+    ppu_wr(device_handle, 0x1555, 0x90)?;
 
-    let rv = ppu_rd(&device_handle, 0x0000);
-    println!("CHR-ROM manf ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0000)?;
+    info!("CHR-ROM manf ID: 0x{:x}", rv);
 
-    let rv = ppu_rd(&device_handle, 0x0001);
-    println!("CHR-ROM prod ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0001)?;
+    info!("CHR-ROM prod ID: 0x{:x}", rv);
 
-    ppu_wr(&device_handle, 0x0000, 0xF0);
+    ppu_wr(device_handle, 0x0000, 0xF0)?;
+    return Ok(());
 }
 
 // SAME AS NROM
-pub fn dump_prgrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_prgrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let mut kb_per_read = 32;
 
     // Handle 16KB nroms.
@@ -63,16 +68,19 @@ pub fn dump_prgrom<T: UsbContext, W: Write>(
 
     // NESCPU_4KB = 0x20
     while read_count < num_reads {
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
 }
 
-pub fn dump_chrrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_chrrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
 
     let kb_per_read = 8;
     let num_reads = rom_size_kb / kb_per_read;
@@ -80,9 +88,77 @@ pub fn dump_chrrom<T: UsbContext, W: Write>(
     let addr_base = 0x00;
 
     while read_count < num_reads {
-        cpu_wr(&device_handle, 0x8000 + read_count, read_count);
-        cpu_wr(&device_handle, 0x8003, read_count);
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB);
+        cpu_wr(device_handle, 0x8000 + read_count, read_count)?;
+        cpu_wr(device_handle, 0x8003, read_count)?;
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
+}
+
+// Map base of the 4KB PRG-ROM block at offset, PRG-ROM is not banked.
+pub fn select_prg<T: Transport>(_device_handle: &T, offset: u32) -> Result<u16> {
+    return Ok(0x08 + (offset >> 12) as u16);
+}
+
+// Switch in the 8KB CHR bank holding offset and return the map base of its 4KB block.
+pub fn select_chr<T: Transport>(device_handle: &T, offset: u32) -> Result<u16> {
+    let bank = (offset / 0x2000) as u16;
+    cpu_wr(device_handle, 0x8000 + bank, bank)?;
+    cpu_wr(device_handle, 0x8003, bank)?;
+    return Ok(((offset % 0x2000) >> 10) as u16);
+}
+
+pub struct Cnrom;
+
+impl NesMapper for Cnrom {
+    fn name(&self) -> &'static str {
+        return "cnrom";
+    }
+
+    fn number(&self) -> u16 {
+        return 3;
+    }
+
+    fn prg_sizes(&self) -> (u16, u16) {
+        return (16, 32);
+    }
+
+    fn max_chr_size(&self) -> u16 {
+        return 32;
+    }
+
+    fn test(&self, device_handle: &dyn Transport) -> Result<()> {
+        return test_cnrom(&device_handle);
+    }
+
+    fn dump_prg(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        prg_size: u16,
+        _bank_table: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_prgrom(&device_handle, &mut file, prg_size, progress);
+    }
+
+    fn dump_chr(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        chr_size: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_chrrom(&device_handle, &mut file, chr_size, progress);
+    }
+
+    fn select_prg(&self, device_handle: &dyn Transport, offset: u32, _prg_size: u16, _bank_table: u16) -> Result<u16> {
+        return select_prg(&device_handle, offset);
+    }
+
+    fn select_chr(&self, device_handle: &dyn Transport, offset: u32) -> Result<u16> {
+        return select_chr(&device_handle, offset);
+    }
 }
@@ -1,112 +1,229 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{info, warn};
 
 use crate::io;
 use crate::util::dump;
 use crate::nes::{detect_mapper_mirroring, ppu_wr, ppu_rd, cpu_rd, cpu_wr, mmc1_wr, Mirroring, ppu_ram_sense};
 use crate::opcodes::buffer as op_buffer;
+use crate::progress::Tracker;
+use crate::nes_mappers::NesMapper;
 
-pub fn test_mmc1<T: UsbContext>(device_handle: &DeviceHandle<T>) {
-    println!("Testing MMC1");
+pub fn test_mmc1<T: Transport>(device_handle: &T) -> Result<()> {
+    info!("Testing MMC1");
     //mirror_test
-    init_mapper_mmc1(&device_handle);
+    init_mapper(device_handle)?;
 
     // Screen A
-    mmc1_wr(&device_handle, 0x8000, 0x00);
-    if detect_mapper_mirroring(&device_handle).unwrap() != Mirroring::SCNA {
-        println!("MMC1 mirror test fail (1 screen A)");
+    mmc1_wr(device_handle, 0x8000, 0x00)?;
+    if detect_mapper_mirroring(device_handle)? != Mirroring::SCNA {
+        warn!("MMC1 mirror test fail (1 screen A)");
     }
 
-    mmc1_wr(&device_handle, 0x8000, 0x01);
-    if detect_mapper_mirroring(&device_handle).unwrap() != Mirroring::SCNB {
-        println!("MMC1 mirror test fail (1 screen B)");
+    mmc1_wr(device_handle, 0x8000, 0x01)?;
+    if detect_mapper_mirroring(device_handle)? != Mirroring::SCNB {
+        warn!("MMC1 mirror test fail (1 screen B)");
     }
 
-    mmc1_wr(&device_handle, 0x8000, 0x02);
-    if detect_mapper_mirroring(&device_handle).unwrap() != Mirroring::VERT {
-        println!("MMC1 mirror test fail (Vertical)");
+    mmc1_wr(device_handle, 0x8000, 0x02)?;
+    if detect_mapper_mirroring(device_handle)? != Mirroring::VERT {
+        warn!("MMC1 mirror test fail (Vertical)");
     }
 
-    mmc1_wr(&device_handle, 0x8000, 0x03);
-    if detect_mapper_mirroring(&device_handle).unwrap() != Mirroring::HORZ {
-        println!("MMC1 mirror test fail (Horizontal)");
+    mmc1_wr(device_handle, 0x8000, 0x03)?;
+    if detect_mapper_mirroring(device_handle)? != Mirroring::HORZ {
+        warn!("MMC1 mirror test fail (Horizontal)");
     }
 
-    ppu_ram_sense(&device_handle, 0x1000);
-    println!("EXP0 pull-up test: {}", io::exp0_pullup_test(&device_handle));
+    ppu_ram_sense(device_handle, 0x1000)?;
+    info!("EXP0 pull-up test: {}", io::exp0_pullup_test(device_handle)?);
 
     // prgrom manf id
-    init_mapper_mmc1(&device_handle);
-    cpu_wr(&device_handle, 0xD555, 0xAA);
-    cpu_wr(&device_handle, 0xAAAA, 0x55);
-    cpu_wr(&device_handle, 0xD555, 0x90);
+    init_mapper(device_handle)?;
+    cpu_wr(device_handle, 0xD555, 0xAA)?;
+    cpu_wr(device_handle, 0xAAAA, 0x55)?;
+    cpu_wr(device_handle, 0xD555, 0x90)?;
 
-    let rv = cpu_rd(&device_handle, 0x8000);
-    println!("PRG-ROM manf ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8000)?;
+    info!("PRG-ROM manf ID: 0x{:x}", rv);
 
-    let rv = cpu_rd(&device_handle, 0x8001);
-    println!("PRG-ROM prod ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8001)?;
+    info!("PRG-ROM prod ID: 0x{:x}", rv);
 
     // Exit
-    cpu_wr(&device_handle, 0x8000, 0xF0);
+    cpu_wr(device_handle, 0x8000, 0xF0)?;
 
     //    read CHR-ROM manf ID
-    init_mapper_mmc1(&device_handle);
-    ppu_wr(&device_handle, 0x1555, 0xAA);
-    ppu_wr(&device_handle, 0x0AAA, 0x55);
-    ppu_wr(&device_handle, 0x1555, 0x90);
+    init_mapper(device_handle)?;
+    ppu_wr(device_handle, 0x1555, 0xAA)?;
+    ppu_wr(device_handle, 0x0AAA, 0x55)?;
+    ppu_wr(device_handle, 0x1555, 0x90)?;
 
-    let rv = ppu_rd(&device_handle, 0x0000);
-    println!("CHR-ROM manf ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0000)?;
+    info!("CHR-ROM manf ID: 0x{:x}", rv);
 
-    let rv = ppu_rd(&device_handle, 0x0001);
-    println!("CHR-ROM prod ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0001)?;
+    info!("CHR-ROM prod ID: 0x{:x}", rv);
     // EXIT
-    ppu_wr(&device_handle, 0x0000, 0xF0);
+    ppu_wr(device_handle, 0x0000, 0xF0)?;
+    return Ok(());
 }
 
-pub fn init_mapper_mmc1<T: UsbContext>(device_handle: &DeviceHandle<T>) {
-    cpu_rd(&device_handle, 0x8000);
-    cpu_wr(&device_handle, 0x8000, 0x80);
-    mmc1_wr(&device_handle, 0x8000, 0x10);
-    mmc1_wr(&device_handle, 0xE000, 0x10);
-    mmc1_wr(&device_handle, 0xA000, 0x12);
-    mmc1_wr(&device_handle, 0xC000, 0x15);
+pub fn init_mapper<T: Transport>(device_handle: &T) -> Result<()> {
+    cpu_rd(device_handle, 0x8000)?;
+    cpu_wr(device_handle, 0x8000, 0x80)?;
+    mmc1_wr(device_handle, 0x8000, 0x10)?;
+    mmc1_wr(device_handle, 0xE000, 0x10)?;
+    mmc1_wr(device_handle, 0xA000, 0x12)?;
+    mmc1_wr(device_handle, 0xC000, 0x15)?;
+    return Ok(());
 }
 
-pub fn dump_prgrom_mmc1<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_prgrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let kb_per_read = 32;
     let num_reads = rom_size_kb / kb_per_read;
     let mut read_count = 0;
     let addr_base = 0x08;
 
     while read_count < num_reads {
-        mmc1_wr(&device_handle, 0xE000, read_count<<1);
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB);
+        mmc1_wr(device_handle, 0xE000, read_count<<1)?;
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
 }
 
-pub fn dump_chrrom_mmc1<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_chrrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let kb_per_read = 8;
     let num_reads = rom_size_kb / kb_per_read;
     let mut read_count = 0;
     let addr_base = 0x00;
 
     while read_count < num_reads {
-        mmc1_wr(&device_handle, 0xA000, read_count*2);
-        mmc1_wr(&device_handle, 0xC000, read_count*2+1);
+        mmc1_wr(device_handle, 0xA000, read_count*2)?;
+        mmc1_wr(device_handle, 0xC000, read_count*2+1)?;
         // NESPPU_1KB = 0x21
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
+}
+
+// Enable WRAM at CPU $6000-$7FFF by clearing bit 4 of the PRG register and
+// switch in its 8KB bank. SOROM has 16KB and SXROM 32KB of it, the bank is
+// picked with bit 3 and bits 2-3 of the CHR registers. Bit 4 of those
+// disables it on SNROM.
+pub fn select_wram<T: Transport>(device_handle: &T, bank: u16, ram_size_kb: u16) -> Result<()> {
+    let bank_shift = if ram_size_kb > 16 { 2 } else { 3 };
+    mmc1_wr(device_handle, 0xE000, 0x00)?;
+    // PPU A12 decides which of the two registers is used, set both.
+    mmc1_wr(device_handle, 0xA000, bank << bank_shift)?;
+    mmc1_wr(device_handle, 0xC000, bank << bank_shift)?;
+    return Ok(());
+}
+
+// Disable WRAM again so the save can't be written to by accident.
+pub fn disable_wram<T: Transport>(device_handle: &T) -> Result<()> {
+    mmc1_wr(device_handle, 0xE000, 0x10)?;
+    return Ok(());
+}
+
+// Switch in the 32KB PRG bank holding offset and return the map base of its
+// 4KB block, init_mapper sets 32KB PRG mode.
+pub fn select_prg<T: Transport>(device_handle: &T, offset: u32) -> Result<u16> {
+    mmc1_wr(device_handle, 0xE000, ((offset / 0x8000) as u16) << 1)?;
+    return Ok(0x08 + ((offset % 0x8000) >> 12) as u16);
+}
+
+// Switch in the 4KB CHR bank holding offset at PPU $0000.
+pub fn select_chr<T: Transport>(device_handle: &T, offset: u32) -> Result<u16> {
+    mmc1_wr(device_handle, 0xA000, (offset / 0x1000) as u16)?;
+    return Ok(0x00);
+}
+
+pub struct Mmc1;
+
+impl NesMapper for Mmc1 {
+    fn name(&self) -> &'static str {
+        return "mmc1";
+    }
+
+    fn number(&self) -> u16 {
+        return 1;
+    }
+
+    fn prg_sizes(&self) -> (u16, u16) {
+        return (32, 256);
+    }
+
+    fn max_chr_size(&self) -> u16 {
+        return 128;
+    }
+
+    fn test(&self, device_handle: &dyn Transport) -> Result<()> {
+        return test_mmc1(&device_handle);
+    }
+
+    fn init(&self, device_handle: &dyn Transport) -> Result<()> {
+        return init_mapper(&device_handle);
+    }
+
+    // Set by the game through the mapper.
+    fn mirroring(&self, _device_handle: &dyn Transport) -> Result<Option<Mirroring>> {
+        return Ok(None);
+    }
+
+    fn dump_prg(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        prg_size: u16,
+        _bank_table: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_prgrom(&device_handle, &mut file, prg_size, progress);
+    }
+
+    fn dump_chr(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        chr_size: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_chrrom(&device_handle, &mut file, chr_size, progress);
+    }
+
+    fn select_prg(&self, device_handle: &dyn Transport, offset: u32, _prg_size: u16, _bank_table: u16) -> Result<u16> {
+        return select_prg(&device_handle, offset);
+    }
+
+    fn select_chr(&self, device_handle: &dyn Transport, offset: u32) -> Result<u16> {
+        return select_chr(&device_handle, offset);
+    }
+
+    fn wram_sizes(&self) -> (&'static [u16], &'static str) {
+        return (&[8, 16, 32], "8, 16 or 32");
+    }
+
+    fn select_wram(&self, device_handle: &dyn Transport, bank: u16, wram_size: u16, _writable: bool) -> Result<()> {
+        return select_wram(&device_handle, bank, wram_size);
+    }
+
+    fn disable_wram(&self, device_handle: &dyn Transport) -> Result<()> {
+        return disable_wram(&device_handle);
+    }
 }
@@ -1,68 +1,72 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{info, warn};
 
 use crate::io;
 use crate::util::dump;
 use crate::nes::{detect_mapper_mirroring, ppu_wr, ppu_rd, cpu_rd, cpu_wr, Mirroring, ppu_ram_sense};
 use crate::opcodes::buffer as op_buffer;
+use crate::progress::Tracker;
+use crate::nes_mappers::NesMapper;
 
-pub fn test_mmc3<T: UsbContext>(device_handle: &DeviceHandle<T>) {
-    println!("Testing MMC3");
+pub fn test_mmc3<T: Transport>(device_handle: &T) -> Result<()> {
+    info!("Testing MMC3");
     //mirror_test
-    init_mapper(&device_handle);
+    init_mapper(device_handle)?;
 
-    cpu_wr(&device_handle, 0xA000, 0x00);
-    if detect_mapper_mirroring(&device_handle).unwrap() != Mirroring::VERT {
-        println!("MMC3 mirror test fail (Vertical)");
+    cpu_wr(device_handle, 0xA000, 0x00)?;
+    if detect_mapper_mirroring(device_handle)? != Mirroring::VERT {
+        warn!("MMC3 mirror test fail (Vertical)");
     }
 
-    cpu_wr(&device_handle, 0xA000, 0x01);
-    if detect_mapper_mirroring(&device_handle).unwrap() != Mirroring::HORZ {
-        println!("MMC3 mirror test fail (Horizontal)");
+    cpu_wr(device_handle, 0xA000, 0x01)?;
+    if detect_mapper_mirroring(device_handle)? != Mirroring::HORZ {
+        warn!("MMC3 mirror test fail (Horizontal)");
     }
 
-    ppu_ram_sense(&device_handle, 0x1000);
-    println!("EXP0 pull-up test: {}", io::exp0_pullup_test(&device_handle));
+    ppu_ram_sense(device_handle, 0x1000)?;
+    info!("EXP0 pull-up test: {}", io::exp0_pullup_test(device_handle)?);
 
     // prgrom manf id
     // Same as mmc1 except init
-    init_mapper(&device_handle);
-    cpu_wr(&device_handle, 0xD555, 0xAA);
-    cpu_wr(&device_handle, 0xAAAA, 0x55);
-    cpu_wr(&device_handle, 0xD555, 0x90);
+    init_mapper(device_handle)?;
+    cpu_wr(device_handle, 0xD555, 0xAA)?;
+    cpu_wr(device_handle, 0xAAAA, 0x55)?;
+    cpu_wr(device_handle, 0xD555, 0x90)?;
 
-    let rv = cpu_rd(&device_handle, 0x8000);
-    println!("PRG-ROM manf ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8000)?;
+    info!("PRG-ROM manf ID: 0x{:x}", rv);
 
-    let rv = cpu_rd(&device_handle, 0x8001);
-    println!("PRG-ROM prod ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8001)?;
+    info!("PRG-ROM prod ID: 0x{:x}", rv);
 
     // Exit
-    cpu_wr(&device_handle, 0x8000, 0xF0);
+    cpu_wr(device_handle, 0x8000, 0xF0)?;
 
     //    read CHR-ROM manf ID
-    init_mapper(&device_handle);
-    ppu_wr(&device_handle, 0x1555, 0xAA);
-    ppu_wr(&device_handle, 0x1AAA, 0x55);
-    ppu_wr(&device_handle, 0x1555, 0x90);
+    init_mapper(device_handle)?;
+    ppu_wr(device_handle, 0x1555, 0xAA)?;
+    ppu_wr(device_handle, 0x1AAA, 0x55)?;
+    ppu_wr(device_handle, 0x1555, 0x90)?;
 
-    let rv = ppu_rd(&device_handle, 0x0000);
-    println!("CHR-ROM manf ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0000)?;
+    info!("CHR-ROM manf ID: 0x{:x}", rv);
 
-    let rv = ppu_rd(&device_handle, 0x0001);
-    println!("CHR-ROM prod ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0001)?;
+    info!("CHR-ROM prod ID: 0x{:x}", rv);
     // EXIT
-    ppu_wr(&device_handle, 0x0000, 0xF0);
+    ppu_wr(device_handle, 0x0000, 0xF0)?;
+    return Ok(());
 }
 
-pub fn init_mapper<T: UsbContext>(device_handle: &DeviceHandle<T>) {
+pub fn init_mapper<T: Transport>(device_handle: &T) -> Result<()> {
 
     // for save data safety start by disabling WRAM, and deny writes
-    cpu_wr(&device_handle, 0xA001, 0x40);
+    cpu_wr(device_handle, 0xA001, 0x40)?;
 
     // set mirroring
-    cpu_wr(&device_handle, 0xA000, 0x00);
+    cpu_wr(device_handle, 0xA000, 0x00)?;
 	
     // $8000-9FFE even
     // MMC3 bank select:
@@ -90,25 +94,25 @@ pub fn init_mapper<T: UsbContext>(device_handle: &DeviceHandle<T>) {
     //                                  four 1 KB banks at $0000-$0FFF)
 
     // For CHR-ROM flash writes, use lower 4KB (PT0) for writting data & upper 4KB (PT1) for commands
-    cpu_wr(&device_handle, 0x8000, 0x00);
-    cpu_wr(&device_handle, 0x8001, 0x00); // 2KB @ PPU $0000
+    cpu_wr(device_handle, 0x8000, 0x00)?;
+    cpu_wr(device_handle, 0x8001, 0x00)?; // 2KB @ PPU $0000
 
-    cpu_wr(&device_handle, 0x8000, 0x01);
-    cpu_wr(&device_handle, 0x8001, 0x02); // 2KB @ PPU $0800
+    cpu_wr(device_handle, 0x8000, 0x01)?;
+    cpu_wr(device_handle, 0x8001, 0x02)?; // 2KB @ PPU $0800
 
     // use lower half of PT1 for $5555 commands
-    cpu_wr(&device_handle, 0x8000, 0x02);
-    cpu_wr(&device_handle, 0x8001, 0x15); // 1KB @ PPU $1000
+    cpu_wr(device_handle, 0x8000, 0x02)?;
+    cpu_wr(device_handle, 0x8001, 0x15)?; // 1KB @ PPU $1000
 
-    cpu_wr(&device_handle, 0x8000, 0x03);
-    cpu_wr(&device_handle, 0x8001, 0x15); // 1KB @ PPU $1400
+    cpu_wr(device_handle, 0x8000, 0x03)?;
+    cpu_wr(device_handle, 0x8001, 0x15)?; // 1KB @ PPU $1400
     
     // use upper half of PT1 for $2AAA commands
-    cpu_wr(&device_handle, 0x8000, 0x04);
-    cpu_wr(&device_handle, 0x8001, 0x0A); // 1KB @ PPU $1800
+    cpu_wr(device_handle, 0x8000, 0x04)?;
+    cpu_wr(device_handle, 0x8001, 0x0A)?; // 1KB @ PPU $1800
 
-    cpu_wr(&device_handle, 0x8000, 0x05);
-    cpu_wr(&device_handle, 0x8001, 0x0A); // 1KB @ PPU $1C00
+    cpu_wr(device_handle, 0x8000, 0x05)?;
+    cpu_wr(device_handle, 0x8001, 0x0A)?; // 1KB @ PPU $1C00
 
     // For PRG-ROM flash writes:
     // mode 0: $C000-FFFF fixed to last 16KByte
@@ -131,44 +135,49 @@ pub fn init_mapper<T: UsbContext>(device_handle: &DeviceHandle<T>) {
     // $5555 commands written to $D555 (default due to mode 0)
     // $2AAA commands written to $AAAA
 
-    cpu_wr(&device_handle, 0x8000, 0x07);
-    cpu_wr(&device_handle, 0x8001, 0x01); // 8KB @ CPU $A000
+    cpu_wr(device_handle, 0x8000, 0x07)?;
+    cpu_wr(device_handle, 0x8001, 0x01)?; // 8KB @ CPU $A000
 
     // DATA writes written to $8000-9FFF
-    cpu_wr(&device_handle, 0x8000, 0x06);
-    cpu_wr(&device_handle, 0x8001, 0x00); // 8KB @ CPU $8000
+    cpu_wr(device_handle, 0x8000, 0x06)?;
+    cpu_wr(device_handle, 0x8001, 0x00)?; // 8KB @ CPU $8000
 
     // set $8000 bank select register to a CHR reg so $8000/1 writes don't change the PRG bank
-    cpu_wr(&device_handle, 0x8000, 0x00);
+    cpu_wr(device_handle, 0x8000, 0x00)?;
+    return Ok(());
 }
 
-pub fn dump_prgrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_prgrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let kb_per_read = 16;
     let num_reads = rom_size_kb / kb_per_read;
     let mut read_count = 0;
     let addr_base = 0x08;
 
     while read_count < num_reads {
-        cpu_wr(&device_handle, 0x8000, 0x06);
-        cpu_wr(&device_handle, 0x8001, read_count * 2); // 8KB @ CPU $8000
+        cpu_wr(device_handle, 0x8000, 0x06)?;
+        cpu_wr(device_handle, 0x8001, read_count * 2)?; // 8KB @ CPU $8000
 
-        cpu_wr(&device_handle, 0x8000, 0x07);
-        cpu_wr(&device_handle, 0x8001, read_count*2 + 1); // 8KB @ CPU $A000
+        cpu_wr(device_handle, 0x8000, 0x07)?;
+        cpu_wr(device_handle, 0x8001, read_count*2 + 1)?; // 8KB @ CPU $A000
 
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
 }
 
-pub fn dump_chrrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_chrrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let kb_per_read = 4;
     let num_reads = rom_size_kb / kb_per_read;
     let mut read_count = 0;
@@ -176,13 +185,116 @@ pub fn dump_chrrom<T: UsbContext, W: Write>(
 
     while read_count < num_reads {
 
-        cpu_wr(&device_handle, 0x8000, 0x00);
-        cpu_wr(&device_handle, 0x8001, (read_count*2)<<1); // 2KB @ PPU $0000
+        cpu_wr(device_handle, 0x8000, 0x00)?;
+        cpu_wr(device_handle, 0x8001, (read_count*2)<<1)?; // 2KB @ PPU $0000
 
-        cpu_wr(&device_handle, 0x8000, 0x01);
-        cpu_wr(&device_handle, 0x8001, (read_count*2 + 1)<<1); // 2KB @ PPU $0800
+        cpu_wr(device_handle, 0x8000, 0x01)?;
+        cpu_wr(device_handle, 0x8001, (read_count*2 + 1)<<1)?; // 2KB @ PPU $0800
 
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
+}
+
+// Enable the 8KB WRAM at CPU $6000-$7FFF, init_mapper leaves it disabled.
+// Writes stay denied unless writable.
+pub fn enable_wram<T: Transport>(device_handle: &T, writable: bool) -> Result<()> {
+    cpu_wr(device_handle, 0xA001, if writable { 0x80 } else { 0xC0 })?;
+    return Ok(());
+}
+
+// Disable WRAM and deny writes again, like init_mapper does.
+pub fn disable_wram<T: Transport>(device_handle: &T) -> Result<()> {
+    cpu_wr(device_handle, 0xA001, 0x40)?;
+    return Ok(());
+}
+
+// Switch in the 8KB PRG bank holding offset at CPU $8000 and return the map
+// base of its 4KB block.
+pub fn select_prg<T: Transport>(device_handle: &T, offset: u32) -> Result<u16> {
+    cpu_wr(device_handle, 0x8000, 0x06)?;
+    cpu_wr(device_handle, 0x8001, (offset / 0x2000) as u16)?; // 8KB @ CPU $8000
+    return Ok(0x08 + ((offset % 0x2000) >> 12) as u16);
+}
+
+// Switch the 4KB CHR block at offset in as two 2KB banks at PPU $0000.
+pub fn select_chr<T: Transport>(device_handle: &T, offset: u32) -> Result<u16> {
+    let bank = (offset / 0x800) as u16;
+    cpu_wr(device_handle, 0x8000, 0x00)?;
+    cpu_wr(device_handle, 0x8001, bank << 1)?; // 2KB @ PPU $0000
+    cpu_wr(device_handle, 0x8000, 0x01)?;
+    cpu_wr(device_handle, 0x8001, (bank + 1) << 1)?; // 2KB @ PPU $0800
+    return Ok(0x00);
+}
+
+pub struct Mmc3;
+
+impl NesMapper for Mmc3 {
+    fn name(&self) -> &'static str {
+        return "mmc3";
+    }
+
+    fn number(&self) -> u16 {
+        return 4;
+    }
+
+    fn prg_sizes(&self) -> (u16, u16) {
+        return (32, 512);
+    }
+
+    fn max_chr_size(&self) -> u16 {
+        return 256;
+    }
+
+    fn test(&self, device_handle: &dyn Transport) -> Result<()> {
+        return test_mmc3(&device_handle);
+    }
+
+    fn init(&self, device_handle: &dyn Transport) -> Result<()> {
+        return init_mapper(&device_handle);
+    }
+
+    // Set by the game through the mapper.
+    fn mirroring(&self, _device_handle: &dyn Transport) -> Result<Option<Mirroring>> {
+        return Ok(None);
+    }
+
+    fn dump_prg(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        prg_size: u16,
+        _bank_table: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_prgrom(&device_handle, &mut file, prg_size, progress);
+    }
+
+    fn dump_chr(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        chr_size: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_chrrom(&device_handle, &mut file, chr_size, progress);
+    }
+
+    fn select_prg(&self, device_handle: &dyn Transport, offset: u32, _prg_size: u16, _bank_table: u16) -> Result<u16> {
+        return select_prg(&device_handle, offset);
+    }
+
+    fn select_chr(&self, device_handle: &dyn Transport, offset: u32) -> Result<u16> {
+        return select_chr(&device_handle, offset);
+    }
+
+    fn select_wram(&self, device_handle: &dyn Transport, _bank: u16, _wram_size: u16, writable: bool) -> Result<()> {
+        return enable_wram(&device_handle, writable);
+    }
+
+    fn disable_wram(&self, device_handle: &dyn Transport) -> Result<()> {
+        return disable_wram(&device_handle);
+    }
 }
@@ -1,5 +1,110 @@
+use std::io::prelude::*;
+
+use crate::error::{Error, Result};
+use crate::nes::{detect_mapper_mirroring, Mirroring};
+use crate::progress::Tracker;
+use crate::transport::Transport;
+
 pub mod nrom;
 pub mod mmc1;
 pub mod unrom;
 pub mod cnrom;
 pub mod mmc3;
+
+// Every board that can be dumped, a new one only needs its module and a line here.
+static MAPPERS: [&dyn NesMapper; 5] = [&nrom::Nrom, &cnrom::Cnrom, &unrom::Unrom, &mmc1::Mmc1, &mmc3::Mmc3];
+
+// How to dump the ROM and WRAM of one mapper. Sizes are in kilobytes, offsets
+// count from the start of PRG-ROM or CHR-ROM.
+pub(crate) trait NesMapper: Sync {
+    // What -m takes.
+    fn name(&self) -> &'static str;
+
+    // Mapper number in the iNES header.
+    fn number(&self) -> u16;
+
+    // Smallest and largest PRG-ROM the mapper can switch between, see nes::detect_sizes.
+    fn prg_sizes(&self) -> (u16, u16);
+
+    // 0 on boards that only come with CHR-RAM.
+    fn max_chr_size(&self) -> u16;
+
+    // Print mirroring and the flash IDs of PRG and CHR.
+    fn test(&self, device_handle: &dyn Transport) -> Result<()>;
+
+    // Put the registers in the state dumping starts from.
+    fn init(&self, _device_handle: &dyn Transport) -> Result<()> {
+        return Ok(());
+    }
+
+    // Mirroring for the header, as the cart has it after init. None where the
+    // game switches it through the mapper, what init left is no hint then.
+    fn mirroring(&self, device_handle: &dyn Transport) -> Result<Option<Mirroring>> {
+        return detect_mapper_mirroring(&device_handle).map(Some);
+    }
+
+    // Where bank numbers can be written without a bus conflict, on boards
+    // that have them. It is handed to dump_prg and select_prg.
+    fn bank_table(&self, _device_handle: &dyn Transport, _prg_size: u16) -> Result<u16> {
+        return Ok(0);
+    }
+
+    fn dump_prg(
+        &self,
+        device_handle: &dyn Transport,
+        file: &mut (dyn Write + Send),
+        prg_size: u16,
+        bank_table: u16,
+        progress: &mut Tracker,
+    ) -> Result<()>;
+
+    fn dump_chr(
+        &self,
+        device_handle: &dyn Transport,
+        file: &mut (dyn Write + Send),
+        chr_size: u16,
+        progress: &mut Tracker,
+    ) -> Result<()>;
+
+    // Switch in the 4KB block of PRG-ROM at offset and return its map base.
+    fn select_prg(&self, device_handle: &dyn Transport, offset: u32, prg_size: u16, bank_table: u16) -> Result<u16>;
+
+    // Switch in the 4KB block of CHR-ROM at offset and return its map base.
+    fn select_chr(&self, device_handle: &dyn Transport, offset: u32) -> Result<u16>;
+
+    // PRG-ROM size found by switching banks, for boards where reading until
+    // the ROM repeats does not work. None leaves it to nes::detect_sizes.
+    fn detect_prg_size(&self, _device_handle: &dyn Transport) -> Result<Option<u16>> {
+        return Ok(None);
+    }
+
+    // WRAM sizes the boards come with, and how to list them in a message.
+    // Discrete boards with WRAM, like Family BASIC, have 8KB.
+    fn wram_sizes(&self) -> (&'static [u16], &'static str) {
+        return (&[8], "8");
+    }
+
+    // Enable WRAM with its 8KB bank at $6000, writable only where the mapper
+    // can deny writes. Discrete boards have it always enabled.
+    fn select_wram(&self, _device_handle: &dyn Transport, _bank: u16, _wram_size: u16, _writable: bool) -> Result<()> {
+        return Ok(());
+    }
+
+    fn disable_wram(&self, _device_handle: &dyn Transport) -> Result<()> {
+        return Ok(());
+    }
+}
+
+// The mapper with this name or iNES number.
+pub(crate) fn find(mapper: &str) -> Result<&'static dyn NesMapper> {
+    let mapper = mapper.to_lowercase();
+    return MAPPERS
+        .iter()
+        .find(|known| known.name() == mapper || known.number().to_string() == mapper)
+        .copied()
+        .ok_or_else(|| Error::Unsupported(format!("Mapper {} is not supported!", mapper)));
+}
+
+pub(crate) fn all() -> &'static [&'static dyn NesMapper] {
+    return &MAPPERS;
+}
@@ -1,51 +1,56 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{debug, info};
 
 use crate::io;
 use crate::util::dump;
 use crate::nes::{discrete_exp0_prgrom_wr, detect_mapper_mirroring, ppu_wr, ppu_rd, cpu_rd};
 use crate::opcodes::buffer as op_buffer;
+use crate::progress::Tracker;
+use crate::nes_mappers::NesMapper;
 
-pub fn test_nrom<T: UsbContext>(device_handle: &DeviceHandle<T>) {
-    println!("Testing NROM");
-    println!("Detect mapper mirroring");
-    detect_mapper_mirroring(&device_handle).unwrap();
+pub fn test_nrom<T: Transport>(device_handle: &T) -> Result<()> {
+    info!("Testing NROM");
+    debug!("Detect mapper mirroring");
+    detect_mapper_mirroring(device_handle)?;
     //    IO EXP0_PULLUP_TEST
-    io::exp0_pullup_test(&device_handle);
+    io::exp0_pullup_test(device_handle)?;
     //    read PRG-ROM manf ID
-    discrete_exp0_prgrom_wr(&device_handle, 0x5555, 0xAA);
-    discrete_exp0_prgrom_wr(&device_handle, 0x2AAA, 0x55);
-    discrete_exp0_prgrom_wr(&device_handle, 0x5555, 0x90);
+    discrete_exp0_prgrom_wr(device_handle, 0x5555, 0xAA)?;
+    discrete_exp0_prgrom_wr(device_handle, 0x2AAA, 0x55)?;
+    discrete_exp0_prgrom_wr(device_handle, 0x5555, 0x90)?;
 
-    let rv = cpu_rd(&device_handle, 0x8000);
-    println!("PRG-ROM manf ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8000)?;
+    info!("PRG-ROM manf ID: 0x{:x}", rv);
 
-    let rv = cpu_rd(&device_handle, 0x8001);
-    println!("PRG-ROM prod ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8001)?;
+    info!("PRG-ROM prod ID: 0x{:x}", rv);
 
     // Exit
-    discrete_exp0_prgrom_wr(&device_handle, 0x8000, 0xF0);
+    discrete_exp0_prgrom_wr(device_handle, 0x8000, 0xF0)?;
 
     //    read CHR-ROM manf ID
-    ppu_wr(&device_handle, 0x1555, 0xAA);
-    ppu_wr(&device_handle, 0x0AAA, 0x55);
-    ppu_wr(&device_handle, 0x1555, 0x90);
+    ppu_wr(device_handle, 0x1555, 0xAA)?;
+    ppu_wr(device_handle, 0x0AAA, 0x55)?;
+    ppu_wr(device_handle, 0x1555, 0x90)?;
 
-    let rv = ppu_rd(&device_handle, 0x0000);
-    println!("CHR-ROM manf ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0000)?;
+    info!("CHR-ROM manf ID: 0x{:x}", rv);
 
-    let rv = ppu_rd(&device_handle, 0x0001);
-    println!("CHR-ROM prod ID: 0x{:x}", rv);
+    let rv = ppu_rd(device_handle, 0x0001)?;
+    info!("CHR-ROM prod ID: 0x{:x}", rv);
     // EXIT
-    ppu_wr(&device_handle, 0x0000, 0xF0);
+    ppu_wr(device_handle, 0x0000, 0xF0)?;
+    return Ok(());
 }
 
-pub fn dump_prgrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_prgrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let mut kb_per_read = 32;
 
     // Handle 16KB nroms.
@@ -58,16 +63,19 @@ pub fn dump_prgrom<T: UsbContext, W: Write>(
 
     // NESCPU_4KB = 0x20
     while read_count < num_reads {
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
 }
 
-pub fn dump_chrrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump_chrrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-) {
+    progress: &mut Tracker,
+) -> Result<()> {
     let kb_per_read = 8;
     let num_reads = rom_size_kb / kb_per_read;
     let mut read_count = 0;
@@ -75,7 +83,72 @@ pub fn dump_chrrom<T: UsbContext, W: Write>(
 
     // NESPPU_1KB = 0x21
     while read_count < num_reads {
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESPPU_1KB, progress)?;
         read_count += 1;
     }
+    return Ok(());
+}
+
+// Map base of the 4KB PRG-ROM block at offset, NROM has no banks.
+pub fn select_prg<T: Transport>(_device_handle: &T, offset: u32) -> Result<u16> {
+    return Ok(0x08 + (offset >> 12) as u16);
+}
+
+// Map base of the 4KB CHR-ROM block at offset.
+pub fn select_chr<T: Transport>(_device_handle: &T, offset: u32) -> Result<u16> {
+    return Ok((offset >> 10) as u16);
+}
+
+pub struct Nrom;
+
+impl NesMapper for Nrom {
+    fn name(&self) -> &'static str {
+        return "nrom";
+    }
+
+    fn number(&self) -> u16 {
+        return 0;
+    }
+
+    fn prg_sizes(&self) -> (u16, u16) {
+        return (16, 32);
+    }
+
+    fn max_chr_size(&self) -> u16 {
+        return 8;
+    }
+
+    fn test(&self, device_handle: &dyn Transport) -> Result<()> {
+        return test_nrom(&device_handle);
+    }
+
+    fn dump_prg(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        prg_size: u16,
+        _bank_table: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_prgrom(&device_handle, &mut file, prg_size, progress);
+    }
+
+    fn dump_chr(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        chr_size: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_chrrom(&device_handle, &mut file, chr_size, progress);
+    }
+
+    fn select_prg(&self, device_handle: &dyn Transport, offset: u32, _prg_size: u16, _bank_table: u16) -> Result<u16> {
+        return select_prg(&device_handle, offset);
+    }
+
+    fn select_chr(&self, device_handle: &dyn Transport, offset: u32) -> Result<u16> {
+        return select_chr(&device_handle, offset);
+    }
 }
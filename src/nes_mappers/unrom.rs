@@ -1,92 +1,47 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{debug, info, warn};
 
 use crate::io;
-use crate::operation;
-use crate::buffer;
-use crate::util::{dump, buffer_allocate};
+use crate::util::{dump, dump_to_array};
 use crate::nes::{discrete_exp0_prgrom_wr, detect_mapper_mirroring, cpu_rd, cpu_wr, ppu_ram_sense};
 use crate::opcodes::buffer as op_buffer;
+use crate::progress::Tracker;
+use crate::nes_mappers::{nrom, NesMapper};
 
-pub fn test_unrom<T: UsbContext>(device_handle: &DeviceHandle<T>) {
-    println!("Testing UNROM");
-    println!("Detect mapper mirroring");
-    detect_mapper_mirroring(&device_handle).unwrap();
+pub fn test_unrom<T: Transport>(device_handle: &T) -> Result<()> {
+    info!("Testing UNROM");
+    debug!("Detect mapper mirroring");
+    detect_mapper_mirroring(device_handle)?;
 
-    ppu_ram_sense(&device_handle, 0x1000);
-    println!("EXP0 pull-up test: {}", io::exp0_pullup_test(&device_handle));
+    ppu_ram_sense(device_handle, 0x1000)?;
+    info!("EXP0 pull-up test: {}", io::exp0_pullup_test(device_handle)?);
 
     //    read PRG-ROM manf ID
     // init mapper
-    cpu_wr(&device_handle, 0x8000, 0x00);
+    cpu_wr(device_handle, 0x8000, 0x00)?;
 
-    discrete_exp0_prgrom_wr(&device_handle, 0x5555, 0xAA);
-    discrete_exp0_prgrom_wr(&device_handle, 0x2AAA, 0x55);
-    discrete_exp0_prgrom_wr(&device_handle, 0x5555, 0x90);
+    discrete_exp0_prgrom_wr(device_handle, 0x5555, 0xAA)?;
+    discrete_exp0_prgrom_wr(device_handle, 0x2AAA, 0x55)?;
+    discrete_exp0_prgrom_wr(device_handle, 0x5555, 0x90)?;
 
-    let rv = cpu_rd(&device_handle, 0x8000);
-    println!("PRG-ROM manf ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8000)?;
+    info!("PRG-ROM manf ID: 0x{:x}", rv);
 
-    let rv = cpu_rd(&device_handle, 0x8001);
-    println!("PRG-ROM prod ID: 0x{:x}", rv);
+    let rv = cpu_rd(device_handle, 0x8001)?;
+    info!("PRG-ROM prod ID: 0x{:x}", rv);
 
     // Exit
-    discrete_exp0_prgrom_wr(&device_handle, 0x8000, 0xF0);
+    discrete_exp0_prgrom_wr(device_handle, 0x8000, 0xF0)?;
+    return Ok(());
 }
 
-pub fn find_banktable<T: UsbContext>(device_handle: &DeviceHandle<T>, banktable_size: u8) -> u16 {
+pub fn find_banktable<T: Transport>(device_handle: &T, banktable_size: u8) -> Result<u16> {
     let search_base = 0x0C; // search in $C000-$F000, the fixed bank
     const KB_SEARCH_SPACE: u16 = 16;
-    let mut full_dump: [u8; 128 * (KB_SEARCH_SPACE as usize * 1024 / 128)] = [0; 128 * (KB_SEARCH_SPACE as usize * 1024 / 128)];
-
-
-    let size_kb: u16 = KB_SEARCH_SPACE;
-    let map: u16 = search_base;
-    let mem: u16 = op_buffer::NESCPU_4KB;
-    let buff0 = 0;
-    let buff1 = 1;
-    println!("SET_OPERATION RESET");
-    operation::set_operation(&device_handle, 0x01);
-    println!("RAW_BUFFER_RESET");
-    buffer::raw_buffer_reset(&device_handle);
-    buffer_allocate(&device_handle, 2, 128);
-    buffer::set_mem_n_part(&device_handle, (mem << 8) | 0xDD, buff0);
-    buffer::set_mem_n_part(&device_handle, (mem << 8) | 0xDD, buff1);
-    buffer::set_map_n_mapvar(&device_handle, (map << 8) | 0, buff0);
-    buffer::set_map_n_mapvar(&device_handle, (map << 8) | 0, buff1);
-
-    println!("SET_OPERATION STARTDUMP");
-    operation::set_operation(&device_handle, 0xD2);
-
-    let mut buf: [u8; 128] = [0; 128];
-    let mut buff_status = 0;
-    println!("sizeKB*1024/buff_size={}", size_kb * 1024 / 128);
-    for i in 0..(size_kb * 1024 / 128) {
-        for try_nbr in 0..20 {
-            buff_status = buffer::get_cur_buff_status(&device_handle);
-            // DUMPED = 0xD8
-            if buff_status == 0xD8 {
-                break;
-            }
-            if try_nbr == 19 {
-                println!("Did not get buff_status within 20 tries");
-            }
-        }
-        if buff_status != 0xD8 {
-            println!("DID NOT GET BUFF STATUS");
-            println!("STOPPING!");
-            break;
-        }
-
-        buffer::buff_payload(&device_handle, &mut buf);
-        full_dump[i as usize * 128..(i as usize + 1) * 128].copy_from_slice(&buf);
-    }
-
-    println!("SET_OPERATION RESET");
-    operation::set_operation(&device_handle, 0x01);
-    println!("RAW_BUFFER_RESET");
-    buffer::raw_buffer_reset(&device_handle);
+    let mut full_dump: [u8; KB_SEARCH_SPACE as usize * 1024] = [0; KB_SEARCH_SPACE as usize * 1024];
+    dump_to_array(device_handle, &mut full_dump, KB_SEARCH_SPACE, search_base, op_buffer::NESCPU_4KB, &mut Tracker::silent())?;
 
     let max_consec = banktable_size;
     let mut current_val: u8 = 0;
@@ -109,27 +64,152 @@ pub fn find_banktable<T: UsbContext>(device_handle: &DeviceHandle<T>, banktable_
             break;
         }
     }
-    return 0xC000 + (potential_index as u16);
+    return Ok(0xC000 + (potential_index as u16));
 }
 
-pub fn dump_prgrom_unrom<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+// PRG-ROM size in kilobytes. The last bank is fixed at $C000, so the ROM has
+// n banks when switching in bank n - 1 shows the fixed bank.
+pub fn detect_prg_size<T: Transport>(device_handle: &T) -> Result<u16> {
+    let mut fixed = vec![0; 16 * 1024];
+    dump_to_array(device_handle, &mut fixed, 16, 0x0C, op_buffer::NESCPU_4KB, &mut Tracker::silent())?;
+    let mut switched = vec![0; 16 * 1024];
+
+    let mut banks: u8 = 2;
+    while banks < 16 {
+        let bank = banks - 1;
+        // No bus conflict where the ROM holds 0xFF or the bank number itself.
+        let index = fixed
+            .iter()
+            .position(|byte| *byte == 0xFF)
+            .or_else(|| fixed.iter().position(|byte| *byte == bank));
+        let index = match index {
+            Some(index) => index,
+            None => {
+                warn!(
+                    "No place to switch in bank {} without a bus conflict, assuming {} KB PRG-ROM",
+                    bank,
+                    banks as u16 * 16
+                );
+                break;
+            }
+        };
+        cpu_wr(device_handle, 0xC000 + index as u16, bank as u16)?;
+        dump_to_array(device_handle, &mut switched, 16, 0x08, op_buffer::NESCPU_4KB, &mut Tracker::silent())?;
+        if switched == fixed {
+            break;
+        }
+        banks *= 2;
+    }
+    return Ok(banks as u16 * 16);
+}
+
+pub fn dump_prgrom<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     rom_size_kb: u16,
-    banktable_base: u16
-) {
+    banktable_base: u16,
+    progress: &mut Tracker,
+) -> Result<()> {
     let kb_per_read = 16;
     let num_reads = rom_size_kb / kb_per_read;
     let mut read_count = 0;
     let addr_base = 0x08;
     let fixed_bank_base = 0x0C;
 
-    while read_count < num_reads - 1 {
-        cpu_wr(&device_handle, banktable_base + read_count, read_count);
+    while read_count + 1 < num_reads {
+        cpu_wr(device_handle, banktable_base + read_count, read_count)?;
 
-        dump(&device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB);
+        progress.set_bank(read_count as u32);
+        dump(device_handle, file, kb_per_read, addr_base, op_buffer::NESCPU_4KB, progress)?;
         read_count += 1;
     }
 
-    dump(&device_handle, file, kb_per_read, fixed_bank_base, op_buffer::NESCPU_4KB);
+    progress.set_bank(read_count as u32);
+    dump(device_handle, file, kb_per_read, fixed_bank_base, op_buffer::NESCPU_4KB, progress)?;
+    return Ok(());
+}
+
+// Switch in the 16KB bank holding offset and return the map base of its 4KB
+// block. The last bank is fixed at $C000 and never switched.
+pub fn select_prg<T: Transport>(
+    device_handle: &T,
+    offset: u32,
+    rom_size_kb: u16,
+    banktable_base: u16
+) -> Result<u16> {
+    let bank = (offset / 0x4000) as u16;
+    let block = ((offset % 0x4000) >> 12) as u16;
+    if bank == rom_size_kb / 16 - 1 {
+        return Ok(0x0C + block);
+    }
+    cpu_wr(device_handle, banktable_base + bank, bank)?;
+    return Ok(0x08 + block);
+}
+
+pub struct Unrom;
+
+impl NesMapper for Unrom {
+    fn name(&self) -> &'static str {
+        return "unrom";
+    }
+
+    fn number(&self) -> u16 {
+        return 2;
+    }
+
+    fn prg_sizes(&self) -> (u16, u16) {
+        return (32, 256);
+    }
+
+    // UNROM boards have CHR-RAM.
+    fn max_chr_size(&self) -> u16 {
+        return 0;
+    }
+
+    fn test(&self, device_handle: &dyn Transport) -> Result<()> {
+        return test_unrom(&device_handle);
+    }
+
+    // Writes to switch banks have to match what the ROM drives at that address.
+    fn bank_table(&self, device_handle: &dyn Transport, prg_size: u16) -> Result<u16> {
+        // The fixed bank needs no entry.
+        let banktable_size = (prg_size / 16).saturating_sub(1);
+        let banktable_base = find_banktable(&device_handle, banktable_size as u8)?;
+        debug!("Found banktable addr = {}", banktable_base);
+        return Ok(banktable_base);
+    }
+
+    fn dump_prg(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        prg_size: u16,
+        bank_table: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return dump_prgrom(&device_handle, &mut file, prg_size, bank_table, progress);
+    }
+
+    // CHR is not banked, in case a board has CHR-ROM.
+    fn dump_chr(
+        &self,
+        device_handle: &dyn Transport,
+        mut file: &mut (dyn Write + Send),
+        chr_size: u16,
+        progress: &mut Tracker,
+    ) -> Result<()> {
+        return nrom::dump_chrrom(&device_handle, &mut file, chr_size, progress);
+    }
+
+    fn select_prg(&self, device_handle: &dyn Transport, offset: u32, prg_size: u16, bank_table: u16) -> Result<u16> {
+        return select_prg(&device_handle, offset, prg_size, bank_table);
+    }
+
+    fn select_chr(&self, device_handle: &dyn Transport, offset: u32) -> Result<u16> {
+        return nrom::select_chr(&device_handle, offset);
+    }
+
+    fn detect_prg_size(&self, device_handle: &dyn Transport) -> Result<Option<u16>> {
+        return detect_prg_size(&device_handle).map(Some);
+    }
 }
@@ -0,0 +1,32 @@
+pub const SUCCESS: u8 = 0;
+
+pub const ERR_UNKN_DICTIONARY: u8 = 128;
+pub const ERR_BAD_PINPORT_OP: u8 = 129;
+pub const ERR_UNKN_PINPORT_OPCODE: u8 = 130;
+pub const ERR_CTL_PIN_NOT_PRESENT: u8 = 131;
+
+pub const ERR_UNKN_IO_OPCODE: u8 = 140;
+
+pub const ERR_UNKN_NES_OPCODE: u8 = 150;
+
+pub const ERR_UNKN_SNES_OPCODE: u8 = 160;
+
+pub const ERR_UNKN_BUFF_OPCODE: u8 = 170;
+pub const ERR_BUFN_DOES_NOT_EXIST: u8 = 171;
+pub const ERR_BUFF_ALLOC_RANGE: u8 = 172;
+pub const ERR_BUFF_STATUS_ALREADY_ALLOC: u8 = 173;
+pub const ERR_BUFF_ID_ALREADY_ALLOC: u8 = 174;
+pub const ERR_BUFF_RAW_ALREADY_ALLOC: u8 = 175;
+pub const ERR_BUFF_ALLOC_SIZE_ZERO: u8 = 176;
+pub const ERR_BUFF_UNSUP_MEM_TYPE: u8 = 177;
+
+pub const ERR_OUT_CURLDBUF_STATUS: u8 = 180;
+pub const ERR_OUT_CURLDBUF_TO_SMALL: u8 = 181;
+
+pub const ERR_UNKN_OPER_OPCODE: u8 = 190;
+
+pub const ERR_UNKN_BOOTLOAD_OPCODE: u8 = 200;
+
+pub const ERR_UNKN_GAMEBOY_OPCODE: u8 = 210;
+pub const ERR_UNKN_GBA_OPCODE: u8 = 211;
+pub const ERR_UNKN_SEGA_OPCODE: u8 = 212;
@@ -8,3 +8,4 @@ pub mod operation;
 pub mod buffer;
 pub mod io;
 pub mod bootload;
+pub mod errors;
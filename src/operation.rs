@@ -1,18 +1,19 @@
-use rusb::{DeviceHandle, UsbContext};
+use crate::error::Result;
+use crate::transport::Transport;
 
 use crate::util;
 use crate::opcodes::operation::*;
 
-pub fn set_operation<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) {
+pub fn set_operation<T: Transport>(device_handle: &T, operand: u16) -> Result<()> {
     let request = 7; // 7 is for operation
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, SET_OPERATION, operand, 0);
+    util::read_device(device_handle, &mut buf, request, SET_OPERATION, operand, 0)
 }
 /*
-pub fn ppu_wr<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16, misc: u16) {
+pub fn ppu_wr<T: Transport>(device_handle: &T, operand: u16, misc: u16) -> Result<()> {
     let request = 3; // 3 is for nes
     let mut buf: [u8; 1] = [0; 1];
     let value: u16 = (misc << 8) | NES_PPU_WR;
-    util::read_device(device_handle, &mut buf, request, NES_PPU_WR, operand, value);
+    util::read_device(device_handle, &mut buf, request, NES_PPU_WR, operand, value)
 }
 */
@@ -0,0 +1,286 @@
+use log::{debug, info, warn};
+use rusb::{Context, DeviceHandle, UsbContext};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::bootload;
+use crate::buffer;
+use crate::emulator;
+use crate::error::{Error, Result};
+use crate::gba;
+use crate::io;
+use crate::operation;
+use crate::trace;
+use crate::transport::Transport;
+use crate::version::{self, Capability, Compatibility, FirmwareVersion};
+
+const VENDOR_ID: u16 = 0x16C0;
+const PRODUCT_ID: u16 = 0x05DC;
+
+const INL_MANUFACTURER: &str = "InfiniteNesLives.com";
+const INL_PRODUCT: &str = "INL Retro-Prog";
+
+// A session with an INL Retro-Prog, or something standing in for one.
+// Pass it as the device_handle to the console modules.
+pub struct Programmer {
+    transport: Box<dyn Transport>,
+    // Unknown for emulated and replayed sessions.
+    firmware: Option<FirmwareVersion>,
+    abort: Arc<AtomicBool>,
+}
+
+impl Programmer {
+    pub fn new(transport: Box<dyn Transport>) -> Programmer {
+        Programmer { transport, firmware: None, abort: Arc::new(AtomicBool::new(false)) }
+    }
+
+    // Open the INL Retro-Prog on the USB bus, there must be only one.
+    pub fn open() -> Result<Programmer> {
+        return Programmer::open_with(&DeviceFilter::default());
+    }
+
+    // Open the one INL Retro-Prog that matches filter.
+    pub fn open_with(filter: &DeviceFilter) -> Result<Programmer> {
+        let context = Context::new()?;
+        let (device_handle, firmware) = get_device_handle(&context, filter)?;
+        let mut programmer = Programmer::new(Box::new(device_handle));
+        programmer.firmware = Some(firmware);
+        return Ok(programmer);
+    }
+
+    // Software programmer with the ROM image in filename inserted in the console port.
+    pub fn emulate(console: &str, filename: &str) -> Result<Programmer> {
+        let emulator = emulator::load(console, filename)?;
+        return Ok(Programmer::new(Box::new(emulator)));
+    }
+
+    // Programmer that plays back a trace written by record.
+    pub fn replay(filename: &str) -> Result<Programmer> {
+        let replayer = trace::Replayer::open(filename)?;
+        return Ok(Programmer::new(Box::new(replayer)));
+    }
+
+    // Record every transfer of this session to filename.
+    pub fn record(self, filename: &str) -> Result<Programmer> {
+        let recorder = trace::Recorder::create(self.transport, filename)?;
+        return Ok(Programmer { transport: Box::new(recorder), firmware: self.firmware, abort: self.abort });
+    }
+
+    pub fn firmware_version(&self) -> Option<FirmwareVersion> {
+        return self.firmware;
+    }
+
+    pub fn app_version(&self) -> Result<u8> {
+        return bootload::get_app_ver(self);
+    }
+
+    // Make sure the firmware app is the one this host speaks, call this
+    // before anything else in a session.
+    pub fn check_compatibility(&self) -> Result<()> {
+        let app_version = self.app_version()?;
+        return version::check_app_version(self.compatibility()?, app_version);
+    }
+
+    // Fail early if the firmware lacks what an operation needs.
+    pub fn require(&self, capability: Capability) -> Result<()> {
+        return version::check_capability(self, capability);
+    }
+
+    // Without a firmware version we assume the newest release we know of.
+    fn compatibility(&self) -> Result<&'static Compatibility> {
+        match self.firmware {
+            Some(firmware) => version::lookup(firmware),
+            None => Ok(&version::COMPATIBILITY[version::COMPATIBILITY.len() - 1]),
+        }
+    }
+
+    pub fn reset(&self) -> Result<()> {
+        return io::reset(self);
+    }
+
+    // Once set every transfer fails with Error::Interrupted, so whatever is
+    // running stops at its next device call. Meant for signal handlers.
+    pub fn abort_flag(&self) -> Arc<AtomicBool> {
+        return Arc::clone(&self.abort);
+    }
+
+    // Start working with a cart, the bus is put back to a safe state when
+    // the session ends however that happens.
+    pub fn session(&self) -> Session<'_> {
+        return Session { programmer: self, closed: false };
+    }
+
+    // Stop any dump, let go of the GBA bus and reset all IO so the cart is
+    // no longer powered or driven. Everything is tried even if a step fails.
+    fn teardown(&self) -> Result<()> {
+        // The transfers below have to go through even after an abort.
+        self.abort.store(false, Ordering::SeqCst);
+        let stop_operation = operation::set_operation(self, 0x01);
+        let reset_buffers = buffer::raw_buffer_reset(self);
+        let release_bus = gba::release_bus(self);
+        let reset_io = io::reset(self);
+        return stop_operation.and(reset_buffers).and(release_bus).and(reset_io);
+    }
+}
+
+// Tears the session down on close, on an early return with an error and
+// while unwinding from a panic.
+pub struct Session<'a> {
+    programmer: &'a Programmer,
+    closed: bool,
+}
+
+impl Session<'_> {
+    // End the session and report if the teardown failed.
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        return self.programmer.teardown();
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(err) = self.programmer.teardown() {
+            warn!("Could not reset the programmer: {}", err);
+        }
+    }
+}
+
+// A programmer seen on the USB bus.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub bus: u8,
+    pub address: u8,
+    pub serial: Option<String>,
+    pub firmware_version: FirmwareVersion,
+}
+
+// Which programmer to open when more than one is connected. Empty fields
+// match anything.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFilter {
+    pub bus: Option<u8>,
+    pub address: Option<u8>,
+    pub serial: Option<String>,
+}
+
+impl DeviceFilter {
+    fn matches(&self, info: &DeviceInfo) -> bool {
+        if self.bus.is_some() && self.bus != Some(info.bus) {
+            return false;
+        }
+        if self.address.is_some() && self.address != Some(info.address) {
+            return false;
+        }
+        if self.serial.is_some() && self.serial != info.serial {
+            return false;
+        }
+        return true;
+    }
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Bus {:03} Device {:03} serial {} firmware {}",
+            self.bus,
+            self.address,
+            self.serial.as_deref().unwrap_or("-"),
+            self.firmware_version
+        )
+    }
+}
+
+// Every INL Retro-Prog on the bus.
+pub fn list_devices() -> Result<Vec<DeviceInfo>> {
+    let context = Context::new()?;
+    let devices = find_devices(&context)?;
+    return Ok(devices.into_iter().map(|(_, info)| info).collect());
+}
+
+impl Transport for Programmer {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize> {
+        if self.abort.load(Ordering::SeqCst) {
+            return Err(Error::Interrupted);
+        }
+        self.transport.control_in(request, value, index, buf)
+    }
+}
+
+fn get_device_handle<T: UsbContext>(context: &T, filter: &DeviceFilter) -> Result<(DeviceHandle<T>, FirmwareVersion)> {
+    debug!("Checking");
+    let mut devices: Vec<(DeviceHandle<T>, DeviceInfo)> = find_devices(context)?
+        .into_iter()
+        .filter(|(_, info)| filter.matches(info))
+        .collect();
+    if devices.is_empty() {
+        return Err(Error::Device(String::from("No matching INL Retro-Prog found, see list-devices.")));
+    }
+    if devices.len() > 1 {
+        let found: Vec<String> = devices.iter().map(|(_, info)| format!("  {}", info)).collect();
+        return Err(Error::Device(format!(
+            "Found {} INL Retro-Progs, pick one with --bus/--address or --serial:\n{}",
+            devices.len(),
+            found.join("\n")
+        )));
+    }
+    let (device_handle, info) = devices.remove(0);
+    let firmware = info.firmware_version;
+    version::lookup(firmware)?;
+    info!("INL retro-prog was found with firmware version {}", firmware);
+    return Ok((device_handle, firmware));
+}
+
+// Open everything with the V-USB VID/PID and keep the ones that say they are
+// an INL Retro-Prog. Other V-USB gadgets share the same IDs.
+fn find_devices<T: UsbContext>(context: &T) -> Result<Vec<(DeviceHandle<T>, DeviceInfo)>> {
+    let mut devices = Vec::new();
+    for device in context.devices()?.iter() {
+        let device_desc = match device.device_descriptor() {
+            Ok(device_desc) => device_desc,
+            Err(err) => {
+                warn!("Bus {:03} Device {:03}: could not read its descriptor: {}", device.bus_number(), device.address(), err);
+                continue;
+            }
+        };
+        if device_desc.vendor_id() != VENDOR_ID || device_desc.product_id() != PRODUCT_ID {
+            continue;
+        }
+        let device_handle = match device.open() {
+            Ok(device_handle) => device_handle,
+            Err(err) => {
+                warn!("Bus {:03} Device {:03}: could not open: {}", device.bus_number(), device.address(), err);
+                continue;
+            }
+        };
+        let manufacturer = device_handle.read_manufacturer_string_ascii(&device_desc).unwrap_or_default();
+        let product = device_handle.read_product_string_ascii(&device_desc).unwrap_or_default();
+        if manufacturer != INL_MANUFACTURER || product != INL_PRODUCT {
+            warn!(
+                "Bus {:03} Device {:03}: skipping \"{} {}\", not an INL Retro-Prog",
+                device.bus_number(),
+                device.address(),
+                manufacturer,
+                product
+            );
+            continue;
+        }
+        let serial = match device_desc.serial_number_string_index() {
+            Some(_) => device_handle.read_serial_number_string_ascii(&device_desc).ok(),
+            None => None,
+        };
+        let version = device_desc.device_version();
+        let info = DeviceInfo {
+            bus: device.bus_number(),
+            address: device.address(),
+            serial,
+            firmware_version: FirmwareVersion::new(version.major(), version.minor(), version.sub_minor()),
+        };
+        devices.push((device_handle, info));
+    }
+    return Ok(devices);
+}
@@ -0,0 +1,123 @@
+// Progress of a running dump. The dump functions take a ProgressObserver
+// and the dump engine updates it after every buffer read from the cart.
+use std::time::Instant;
+
+use crate::verify::Verify;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Progress {
+    pub bytes_done: u64,
+    // Expected size, a dump can end early when the ROM turns out smaller.
+    pub total_bytes: u64,
+    // The bank being read, its meaning depends on the console and mapper.
+    pub bank: u32,
+    pub bytes_per_second: f64,
+}
+
+impl Progress {
+    // Seconds left at the current rate, None until there is a rate.
+    pub fn eta_seconds(&self) -> Option<f64> {
+        if self.bytes_per_second <= 0.0 {
+            return None;
+        }
+        let left = self.total_bytes.saturating_sub(self.bytes_done);
+        return Some(left as f64 / self.bytes_per_second);
+    }
+}
+
+pub trait ProgressObserver {
+    fn update(&mut self, progress: &Progress);
+    // Called once when the dump is done.
+    fn finish(&mut self, _progress: &Progress) {}
+}
+
+// Any closure taking a &Progress can be used as observer.
+impl<F: FnMut(&Progress)> ProgressObserver for F {
+    fn update(&mut self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+// For callers that do not want progress.
+pub struct Silent;
+
+impl ProgressObserver for Silent {
+    fn update(&mut self, _progress: &Progress) {}
+}
+
+// Keeps count for the observer while a dump is running, and tells the dump
+// engine how to verify what it reads.
+pub(crate) struct Tracker<'a> {
+    observer: Option<&'a mut dyn ProgressObserver>,
+    progress: Progress,
+    started: Instant,
+    // Bytes from an earlier run, they do not count towards the rate.
+    resumed: u64,
+    verify: Verify,
+    // Bytes handed on after verification, where the next region starts.
+    dumped: u64,
+}
+
+impl<'a> Tracker<'a> {
+    // Every pass of a verified dump is counted, total_bytes is the size of one.
+    pub(crate) fn new(observer: &'a mut dyn ProgressObserver, total_bytes: u64, verify: Verify) -> Tracker<'a> {
+        let progress = Progress { total_bytes: total_bytes * verify.passes as u64, ..Progress::default() };
+        return Tracker { observer: Some(observer), progress, started: Instant::now(), resumed: 0, verify, dumped: 0 };
+    }
+
+    // For reads that are not worth reporting, like a header or a bank table.
+    pub(crate) fn silent() -> Tracker<'a> {
+        return Tracker {
+            observer: None,
+            progress: Progress::default(),
+            started: Instant::now(),
+            resumed: 0,
+            verify: Verify::OFF,
+            dumped: 0,
+        };
+    }
+
+    pub(crate) fn set_bank(&mut self, bank: u32) {
+        self.progress.bank = bank;
+    }
+
+    // Start counting after what an earlier run already dumped.
+    pub(crate) fn resume(&mut self, bytes: u64) {
+        self.progress.bytes_done = bytes * self.verify.passes as u64;
+        self.resumed = self.progress.bytes_done;
+        self.dumped = bytes;
+    }
+
+    pub(crate) fn verify(&self) -> Verify {
+        return self.verify;
+    }
+
+    pub(crate) fn bank(&self) -> u32 {
+        return self.progress.bank;
+    }
+
+    pub(crate) fn dumped(&self) -> u64 {
+        return self.dumped;
+    }
+
+    pub(crate) fn add_dumped(&mut self, bytes: usize) {
+        self.dumped += bytes as u64;
+    }
+
+    pub(crate) fn advance(&mut self, bytes: usize) {
+        self.progress.bytes_done += bytes as u64;
+        let seconds = self.started.elapsed().as_secs_f64();
+        if seconds > 0.0 {
+            self.progress.bytes_per_second = (self.progress.bytes_done - self.resumed) as f64 / seconds;
+        }
+        if let Some(observer) = self.observer.as_mut() {
+            observer.update(&self.progress);
+        }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        if let Some(observer) = self.observer.as_mut() {
+            observer.finish(&self.progress);
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use inlrust::progress::{Progress, ProgressObserver};
+
+const WIDTH: usize = 30;
+
+// Draws the dump progress on one line of stderr so stdout stays clean.
+#[derive(Default)]
+pub struct ProgressBar {
+    last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+    fn draw(&self, progress: &Progress) {
+        let total = progress.total_bytes.max(progress.bytes_done).max(1);
+        let filled = (progress.bytes_done * WIDTH as u64 / total) as usize;
+        let eta = match progress.eta_seconds() {
+            Some(seconds) => format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60),
+            None => String::from("-:--"),
+        };
+        eprint!(
+            "\r[{}{}] {:3}% {}/{} KB bank {} {:.1} KB/s ETA {}  ",
+            "#".repeat(filled),
+            "-".repeat(WIDTH - filled),
+            progress.bytes_done * 100 / total,
+            progress.bytes_done / 1024,
+            total / 1024,
+            progress.bank,
+            progress.bytes_per_second / 1024.0,
+            eta
+        );
+        let _ = io::stderr().flush();
+    }
+}
+
+impl ProgressObserver for ProgressBar {
+    fn update(&mut self, progress: &Progress) {
+        // Redrawing for every buffer would slow the dump down.
+        let now = Instant::now();
+        if self.last_draw.is_some_and(|last| now - last < Duration::from_millis(100)) {
+            return;
+        }
+        self.last_draw = Some(now);
+        self.draw(progress);
+    }
+
+    fn finish(&mut self, progress: &Progress) {
+        // The dump may have stopped early on a smaller ROM, it is complete anyway.
+        self.draw(&Progress { total_bytes: progress.bytes_done, ..*progress });
+        eprintln!();
+        self.last_draw = None;
+    }
+}
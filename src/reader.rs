@@ -0,0 +1,174 @@
+// Random access to the ROM of a cartridge. Each console module has a
+// cart_reader() that returns a CartReader over its ROM address space, the
+// bank switching needed to reach an offset is done by its BankSource.
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::error::Result;
+
+pub trait BankSource {
+    // Size of the linear ROM address space in bytes.
+    fn size(&self) -> u64;
+    // Reads are done in blocks of this many bytes, aligned to the block size.
+    fn block_size(&self) -> usize;
+    // Select whatever bank holds offset and read the block starting there.
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()>;
+}
+
+// Keeps the last block read so small reads next to each other only go to
+// the cartridge once.
+pub struct CartReader<S: BankSource> {
+    source: S,
+    pos: u64,
+    block: Vec<u8>,
+    block_start: Option<u64>,
+}
+
+impl<S: BankSource> CartReader<S> {
+    pub fn new(source: S) -> CartReader<S> {
+        return CartReader { source, pos: 0, block: Vec::new(), block_start: None };
+    }
+
+    pub fn size(&self) -> u64 {
+        return self.source.size();
+    }
+
+    pub fn into_inner(self) -> S {
+        return self.source;
+    }
+}
+
+impl<S: BankSource> Read for CartReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.source.size();
+        if self.pos >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_size = self.source.block_size();
+        let start = self.pos - self.pos % block_size as u64;
+        if self.block_start != Some(start) {
+            self.block.resize(block_size, 0);
+            self.block_start = None;
+            self.source.read_block(start, &mut self.block)?;
+            self.block_start = Some(start);
+        }
+
+        let offset = (self.pos - start) as usize;
+        let len = buf.len().min(block_size - offset).min((size - self.pos) as usize);
+        buf[..len].copy_from_slice(&self.block[offset..offset + len]);
+        self.pos += len as u64;
+        return Ok(len);
+    }
+}
+
+impl<S: BankSource> Seek for CartReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(delta) => (self.source.size(), delta),
+            SeekFrom::Current(delta) => (self.pos, delta),
+        };
+        match base.checked_add_signed(delta) {
+            Some(offset) => {
+                self.pos = offset;
+                return Ok(offset);
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ROM in memory that counts how often each block is read.
+    struct Rom {
+        data: Vec<u8>,
+        block_size: usize,
+        reads: Vec<u64>,
+    }
+
+    impl Rom {
+        fn new(size: usize, block_size: usize) -> Rom {
+            let data = (0..size).map(|i| (i * 7 + i / 256) as u8).collect();
+            return Rom { data, block_size, reads: Vec::new() };
+        }
+    }
+
+    impl BankSource for Rom {
+        fn size(&self) -> u64 {
+            return self.data.len() as u64;
+        }
+
+        fn block_size(&self) -> usize {
+            return self.block_size;
+        }
+
+        fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+            assert_eq!(offset % self.block_size as u64, 0, "unaligned block read");
+            self.reads.push(offset);
+            let offset = offset as usize;
+            buf.copy_from_slice(&self.data[offset..offset + self.block_size]);
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn read_all() {
+        let mut reader = CartReader::new(Rom::new(4096, 1024));
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert!(data == Rom::new(4096, 1024).data);
+        assert_eq!(reader.into_inner().reads, vec![0, 1024, 2048, 3072]);
+    }
+
+    #[test]
+    fn read_across_blocks() {
+        let expected = Rom::new(4096, 1024).data;
+        let mut reader = CartReader::new(Rom::new(4096, 1024));
+        reader.seek(SeekFrom::Start(1000)).unwrap();
+        let mut buf = [0; 100];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &expected[1000..1100]);
+        // A single read stops at the end of the block, still the one read last.
+        reader.seek(SeekFrom::Start(2040)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap(), 8);
+        assert_eq!(&buf[..8], &expected[2040..2048]);
+        assert_eq!(reader.into_inner().reads, vec![0, 1024]);
+    }
+
+    #[test]
+    fn reads_in_one_block_go_to_the_cart_once() {
+        let mut reader = CartReader::new(Rom::new(4096, 1024));
+        let mut buf = [0; 16];
+        for offset in [1100, 1030, 2000, 1024] {
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            reader.read_exact(&mut buf).unwrap();
+        }
+        assert_eq!(reader.into_inner().reads, vec![1024]);
+    }
+
+    #[test]
+    fn seek_from_end_and_current() {
+        let expected = Rom::new(4096, 1024).data;
+        let mut reader = CartReader::new(Rom::new(4096, 1024));
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 4086);
+        assert_eq!(reader.seek(SeekFrom::Current(-6)).unwrap(), 4080);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(&rest[..], &expected[4080..]);
+        assert!(reader.seek(SeekFrom::Current(-5000)).is_err());
+    }
+
+    #[test]
+    fn read_past_end() {
+        let mut reader = CartReader::new(Rom::new(4096, 1024));
+        reader.seek(SeekFrom::Start(5000)).unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.into_inner().reads.is_empty());
+    }
+}
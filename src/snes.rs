@@ -1,23 +1,97 @@
-use rusb::{DeviceHandle, UsbContext};
-use std::fs::File;
+use crate::error::{Error, Result};
+use crate::transport::Transport;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use log::{debug, info, warn};
+use serde::Serialize;
 
 use crate::io;
 use crate::util;
-use crate::util::{CommandLineOptions, dump_to_array, dump};
+use crate::util::{OutputFile, dump_to_array, dump};
+use crate::progress::{ProgressObserver, Tracker};
+use crate::verify::Verify;
+use crate::reader::{BankSource, CartReader};
+use crate::diagnose::{self, Bus, Pin, Report};
+use crate::naming::CartName;
 use crate::opcodes::snes::*;
 use crate::opcodes::buffer as op_buffer;
 
-pub fn dump_snes<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions) {
-    io::reset(&device_handle);
-    io::snes_init(&device_handle);
-
-    let header = dump_snes_header(&device_handle).unwrap();
+// Dump the ROM to filename and/or the save RAM to savefile. With resume the
+// ROM dump continues from where an earlier one was interrupted.
+pub fn dump_snes<T: Transport>(
+    device_handle: &T,
+    filename: Option<&str>,
+    savefile: Option<&str>,
+    resume: bool,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    init(device_handle)?;
+
+    let header = read_header(device_handle)?;
     print_header(&header);
 
-    let snes_mapping = match_map_mode(header.map_mode).unwrap();
-    println!("Autodetected {} mapping", snes_mapping);
+    if let Some(savefile) = savefile {
+        info!("Dumping SAVE RAM...");
+        dump_ram(device_handle, &header, savefile, verify, progress)?;
+    }
+
+    if let Some(filename) = filename {
+        info!("Dumping SNES ROM...");
+        dump_rom(device_handle, &header, filename, resume, verify, progress)?;
+    }
+
+    io::reset(device_handle)?;
+    return Ok(());
+}
+
+pub fn init<T: Transport>(device_handle: &T) -> Result<()> {
+    io::reset(device_handle)?;
+    io::snes_init(device_handle)?;
+    return Ok(());
+}
+
+pub fn dump_rom<T: Transport>(
+    device_handle: &T,
+    header: &SnesHeader,
+    filename: &str,
+    resume: bool,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let layout = get_layout(header)?;
+    let mut progress = Tracker::new(progress, layout.rom_size as u64 * 1024, verify);
+    let cart = format!("{} {} {} KB {:04X}", header.rom_name.trim(), layout.mapping, layout.rom_size, header.checksum);
+    return dump_rom_banks(device_handle, filename, &cart, resume, &layout, &mut progress);
+}
+
+pub fn dump_ram<T: Transport>(
+    device_handle: &T,
+    header: &SnesHeader,
+    filename: &str,
+    verify: Verify,
+    progress: &mut dyn ProgressObserver,
+) -> Result<()> {
+    let layout = get_layout(header)?;
+    let mut progress = Tracker::new(progress, layout.ram_size as u64 * 1024, verify);
+    debug!("rambank {}", layout.rambank);
+    debug!("ram_size {}", layout.ram_size);
+    debug!("snes_mapping {}", layout.mapping);
+    return dump_ram_banks(device_handle, filename, layout.rambank, layout.ram_size, layout.mapping, &mut progress);
+}
+
+// Where and how big ROM and SRAM are for a cart.
+struct Layout {
+    mapping: &'static str,
+    rombank: u16,
+    rambank: u16,
+    rom_size: u16,
+    ram_size: u16,
+}
+
+fn get_layout(header: &SnesHeader) -> Result<Layout> {
+    let snes_mapping = match_map_mode(header.map_mode)
+        .ok_or_else(|| Error::Header(format!("Unknown map mode: 0x{:X}", header.map_mode)))?;
+    info!("Autodetected {} mapping", snes_mapping);
 
     let rombank;
     let rambank;
@@ -28,8 +102,7 @@ pub fn dump_snes<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &C
         rombank = 0x00;
         rambank = 0x70;
     } else {
-        println!("Unsupported mapper: {}", snes_mapping);
-        return;
+        return Err(Error::Unsupported(format!("Unsupported mapper: {}", snes_mapping)));
     }
 
     // detect sram size
@@ -37,44 +110,31 @@ pub fn dump_snes<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &C
     if header.sram_size < 7 {
         ram_size = 2_u16.pow(header.sram_size.into());
     }
-    println!("ram_size: {} kilobytes", ram_size);
+    debug!("ram_size: {} kilobytes", ram_size);
 
     let mut exp_ram_size = 0;
     if header.exp_ram_size < 7 {
         exp_ram_size = 2_u16.pow(header.exp_ram_size.into());
     }
-    println!("exp_ram_size: {} kilobytes", exp_ram_size);
+    debug!("exp_ram_size: {} kilobytes", exp_ram_size);
     if ram_size == 0 && exp_ram_size  > 0 {
-        println!("ram size will be the exp ram size");
+        debug!("ram size will be the exp ram size");
         ram_size = exp_ram_size;
     }
 
     // detect rom size
-    let test_string: String = format!("Unknown: 0x{:X}", header.rom_size);
-    let rom_size = match_rom_size_kb(header.rom_size).expect(&test_string);
-    println!("rom_size: {} kilobytes", rom_size);
-
-    println!("{:?}", cmd_options);
-    if cmd_options.savefile != "" {
-        println!("Dumping SAVE RAM...");
-
-        println!("rambank {}", rambank);
-        println!("ram_size {}", ram_size);
-        println!("snes_mapping {}", snes_mapping);
-        dump_ram(&device_handle, &cmd_options, rambank, ram_size, snes_mapping);
-    }
+    let rom_size = match_rom_size_kb(header.rom_size)
+        .ok_or_else(|| Error::Header(format!("Unknown ROM size: 0x{:X}", header.rom_size)))?;
+    debug!("rom_size: {} kilobytes", rom_size);
 
-    if cmd_options.filename != "" {
-        println!("Dumping SNES ROM...");
-
-        dump_rom(&device_handle, &cmd_options, rombank, rom_size, snes_mapping);
-    }
-
-    io::reset(&device_handle);
+    return Ok(Layout { mapping: snes_mapping, rombank, rambank, rom_size, ram_size });
 }
 
-fn dump_rom<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions,
-    start_bank: u16, rom_size: u16, snes_mapping: &str) {
+fn dump_rom_banks<T: Transport>(device_handle: &T, filename: &str,
+    cart: &str, resume: bool, layout: &Layout, progress: &mut Tracker) -> Result<()> {
+    let start_bank = layout.rombank;
+    let rom_size = layout.rom_size;
+    let snes_mapping = layout.mapping;
 
 	let kb_per_bank;
 	let addr_base;
@@ -86,26 +146,34 @@ fn dump_rom<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &Comman
         kb_per_bank = 32;
         addr_base = 0x80;
     } else {
-        println!("Unsupported mapping: {}", snes_mapping);
-        return;
+        return Err(Error::Unsupported(format!("Unsupported mapping: {}", snes_mapping)));
     }
 
-    let file = File::create(&cmd_options.filename).unwrap();
-    let mut f = BufWriter::new(file);
+    let (mut f, done) = OutputFile::create_resumable(filename, cart, resume)?;
+    progress.resume(done);
 
 	let num_reads = rom_size / kb_per_bank;
-	let mut read_count = 0;
+	let mut read_count = (done / (kb_per_bank as u64 * 1024)) as u16;
     let mut size_detection = vec![0; (kb_per_bank as usize) * 1024];
 
+    // The size detection compares against a bank from before the resume point.
+    let reference = match read_count {
+        33.. => Some(32),
+        17.. => Some(16),
+        _ => None,
+    };
+    if let Some(bank) = reference {
+        set_bank(device_handle, start_bank + bank)?;
+        dump_to_array(device_handle, &mut size_detection, kb_per_bank, addr_base, op_buffer::SNESROM_PAGE, &mut Tracker::silent())?;
+    }
+
 	while read_count < num_reads {
-        if read_count % 8 == 0 {
-            println!("dumping ROM bank: {} of {}", read_count, num_reads-1);
-        }
         // select desired bank
-        set_bank(&device_handle, start_bank + read_count);
+        set_bank(device_handle, start_bank + read_count)?;
+        progress.set_bank((start_bank + read_count) as u32);
         let mut dump_array = vec![0; (kb_per_bank as usize) * 1024];
 
-        dump_to_array(&device_handle, &mut dump_array, kb_per_bank, addr_base, op_buffer::SNESROM_PAGE);
+        dump_to_array(device_handle, &mut dump_array, kb_per_bank, addr_base, op_buffer::SNESROM_PAGE, progress)?;
         read_count +=  1;
 
         // Auto size detection
@@ -118,20 +186,52 @@ fn dump_rom<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &Comman
         // read_count=49 is 30_0000 for hirom, 18_0000 for lorom.
         match read_count {
             17 | 33 => size_detection = dump_array.clone(),
-            21 | 25 | 41 | 49 => {
-                if size_detection == dump_array { // Repeating bank so we don't need to fetch more.
-                    break;
-                }
-            },
+            // Repeating bank so we don't need to fetch more.
+            21 | 25 | 41 | 49 if size_detection == dump_array => break,
             _ => {}
         }
-        f.write_all(&dump_array).unwrap();
+        f.write_all(&dump_array)?;
+        f.checkpoint()?;
     }
-    f.flush().unwrap();
+    progress.finish();
+    f.commit()?;
+    return Ok(());
 }
 
-fn dump_ram<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &CommandLineOptions,
-    start_bank: u16, ram_size: u16, snes_mapping: &str) {
+// The ROM through the HiROM or LoROM mapping, see cart_reader.
+pub struct RomBanks<'a, T: Transport> {
+    device_handle: &'a T,
+    layout: Layout,
+}
+
+// Random access to the ROM described by header.
+pub fn cart_reader<'a, T: Transport>(device_handle: &'a T, header: &SnesHeader) -> Result<CartReader<RomBanks<'a, T>>> {
+    let layout = get_layout(header)?;
+    return Ok(CartReader::new(RomBanks { device_handle, layout }));
+}
+
+impl<T: Transport> BankSource for RomBanks<'_, T> {
+    fn size(&self) -> u64 {
+        return self.layout.rom_size as u64 * 1024;
+    }
+
+    fn block_size(&self) -> usize {
+        return 4 * 1024;
+    }
+
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        // HiROM has 64KB at $0000 of each bank, LoROM 32KB at $8000.
+        let (bank_size, addr_base) = if self.layout.mapping == "LoROM" { (0x8000, 0x80) } else { (0x10000, 0x00) };
+        let bank = (offset / bank_size) as u16;
+        let page = ((offset % bank_size) >> 8) as u16;
+
+        set_bank(self.device_handle, self.layout.rombank + bank)?;
+        return dump_to_array(self.device_handle, buf, (buf.len() / 1024) as u16, addr_base + page, op_buffer::SNESROM_PAGE, &mut Tracker::silent());
+    }
+}
+
+fn dump_ram_banks<T: Transport>(device_handle: &T, filename: &str,
+    start_bank: u16, ram_size: u16, snes_mapping: &str, progress: &mut Tracker) -> Result<()> {
 
 	let mut kb_per_bank;
 	let addr_base;
@@ -143,12 +243,10 @@ fn dump_ram<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &Comman
             kb_per_bank = 32;
             addr_base = 0x00;
         } else {
-            println!("Unsupported mapping: {}", snes_mapping);
-            return;
+            return Err(Error::Unsupported(format!("Unsupported mapping: {}", snes_mapping)));
         }
 
-        let file = File::create(&cmd_options.savefile).unwrap();
-        let mut f = BufWriter::new(file);
+        let mut f = OutputFile::create(filename)?;
 
         let num_banks;
         if ram_size < kb_per_bank {
@@ -161,22 +259,25 @@ fn dump_ram<T: UsbContext>(device_handle: &DeviceHandle<T>, cmd_options: &Comman
 	let mut read_count = 0;
 	while read_count < num_banks {
 
-            println!("dump RAM part {} of {}", read_count, num_banks);
+            debug!("dump RAM part {} of {}", read_count, num_banks);
 
             // select desired bank
-            println!("set bank start_bank: {} read_count: {}", start_bank, read_count);
-            set_bank(&device_handle, start_bank + read_count);
+            debug!("set bank start_bank: {} read_count: {}", start_bank, read_count);
+            set_bank(device_handle, start_bank + read_count)?;
+            progress.set_bank((start_bank + read_count) as u32);
 
             if snes_mapping == "LoROM" {
-                println!("LoROM, kb_per_bank: {} , addr_base: {}", kb_per_bank, addr_base);
-                dump(&device_handle, &mut f, kb_per_bank, addr_base, op_buffer::SNESROM_PAGE);
+                debug!("LoROM, kb_per_bank: {} , addr_base: {}", kb_per_bank, addr_base);
+                dump(device_handle, &mut f, kb_per_bank, addr_base, op_buffer::SNESROM_PAGE, progress)?;
             } else {
-                dump(&device_handle, &mut f, kb_per_bank, addr_base, op_buffer::SNESSYS_PAGE);
+                dump(device_handle, &mut f, kb_per_bank, addr_base, op_buffer::SNESSYS_PAGE, progress)?;
             }
             read_count +=  1
         }
 
-        f.flush().unwrap();
+        progress.finish();
+        f.commit()?;
+    return Ok(());
 }
 
 fn match_rom_size_kb(rom_size: u8) -> Option<u16> {
@@ -191,7 +292,7 @@ fn match_rom_size_kb(rom_size: u8) -> Option<u16> {
     }
 }
 
-fn print_header(header: &SnesHeader) {
+pub fn print_header(header: &SnesHeader) {
     println!("------------ HEADER ------------");
     println!("Name: {}", header.rom_name);
     println!("map_mode: {}", match_map_mode(header.map_mode).unwrap_or("Unknown"));
@@ -209,6 +310,52 @@ fn print_header(header: &SnesHeader) {
     println!("--------------------------------");
 }
 
+// The header as print_header shows it, sizes in bytes and None for codes
+// that are not known.
+#[derive(Clone, Debug, Serialize)]
+pub struct SnesDecoded {
+    pub title: String,
+    pub map_mode: Option<&'static str>,
+    pub fast_rom: bool,
+    pub rom_type: Option<&'static str>,
+    pub rom_size_bytes: Option<u32>,
+    pub sram_size_bytes: Option<u32>,
+    pub exp_ram_size_bytes: Option<u32>,
+    pub destination: Option<&'static str>,
+    pub region: Option<&'static str>,
+    pub developer: Option<&'static str>,
+    pub version: u8,
+    pub checksum: u16,
+    pub compliment_check: u16,
+    // The checksum and its compliment add up to 0xFFFF.
+    pub checksum_pair_valid: bool,
+}
+
+pub fn decode_header(header: &SnesHeader) -> SnesDecoded {
+    let ram_bytes = |size: u8| match_ram_size(size).map(|_| if size == 0 { 0 } else { 1024 << size });
+    return SnesDecoded {
+        title: header.rom_name.trim_end().to_owned(),
+        map_mode: match_map_mode(header.map_mode),
+        fast_rom: (header.map_mode & 0x10) == 0x10,
+        rom_type: match_hardware_type(header.rom_type),
+        rom_size_bytes: match_rom_size_kb(header.rom_size).map(|kb| kb as u32 * 1024),
+        sram_size_bytes: ram_bytes(header.sram_size),
+        exp_ram_size_bytes: ram_bytes(header.exp_ram_size),
+        destination: match_destination(header.destination_code),
+        region: match_region(header.destination_code),
+        developer: match_developer(header.developer_code),
+        version: header.version,
+        checksum: header.checksum,
+        compliment_check: header.compliment_check,
+        checksum_pair_valid: header.checksum ^ header.compliment_check == 0xFFFF,
+    };
+}
+
+pub fn cart_name(header: &SnesHeader) -> CartName {
+    let region = match_region(header.destination_code).unwrap_or("Unknown");
+    return CartName::new("sfc", "srm").with_title(&header.rom_name).with_region(region);
+}
+
 
 fn is_valid_header(header: &SnesHeader) -> bool {
     if match_hardware_type(header.rom_type).is_none() {
@@ -226,26 +373,26 @@ fn is_valid_header(header: &SnesHeader) -> bool {
     return true;
 }
 
-fn dump_snes_header<T: UsbContext>(device_handle: &DeviceHandle<T>) -> Result<SnesHeader, &'static str> {
-    let hirom_header = get_header(&device_handle, 0x0000);
-    let lorom_header = get_header(&device_handle, 0x8000);
-    let exhirom_header = get_header(&device_handle, -0x400000);
+// Find and read the internal header, trying the exHiROM, HiROM and LoROM locations.
+pub fn read_header<T: Transport>(device_handle: &T) -> Result<SnesHeader> {
+    let hirom_header = get_header(device_handle, 0x0000)?;
+    let lorom_header = get_header(device_handle, 0x8000)?;
+    let exhirom_header = get_header(device_handle, -0x400000)?;
     if is_valid_header(&exhirom_header) {
-        println!("Valid header found at exHiROM address.");
+        debug!("Valid header found at exHiROM address.");
         return Ok(exhirom_header);
     } else if is_valid_header(&hirom_header) {
-        println!("Valid header found at HiROM address.");
+        debug!("Valid header found at HiROM address.");
         return Ok(hirom_header);
     } else if is_valid_header(&lorom_header) {
-        println!("Valid header found at LoROM address.");
+        debug!("Valid header found at LoROM address.");
         return Ok(lorom_header);
     }
 
-    return Err("Could not parse internal ROM header.");
+    return Err(Error::Header(String::from("Could not parse internal ROM header.")));
 }
 
-// Command line options
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct SnesHeader {
     pub rom_name: String,
     pub map_mode: u8,
@@ -261,7 +408,7 @@ pub struct SnesHeader {
 }
 use std::str;
 
-fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>, map_adjust: i32) -> SnesHeader {
+fn get_header<T: Transport>(device_handle: &T, map_adjust: i32) -> Result<SnesHeader> {
     let addr_expansion_ram_size = (0xFFBD - map_adjust) as u16; // 1 byte
 
     // ROM Specification Addresses (32 bytes)
@@ -274,33 +421,34 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>, map_adjust: i32) -
     let addr_developer_code   = (0xFFDA - map_adjust) as u16; // 1 byte
     let addr_version          = (0xFFDB - map_adjust) as u16; // 1 byte
     let addr_compliment_check = (0xFFDC - map_adjust) as u16; // 2 bytes
-    let addr_checksum         = (0xFFDD - map_adjust) as u16; // 2 bytes
-
-    let map_mode = rom_rd(&device_handle, addr_map_mode);
-    let rom_type = rom_rd(&device_handle, addr_rom_type);
-    let rom_size = rom_rd(&device_handle, addr_rom_size);
-    let sram_size = rom_rd(&device_handle, addr_sram_size);
-    let exp_ram_size = rom_rd(&device_handle, addr_expansion_ram_size);
-    let destination_code = rom_rd(&device_handle, addr_destination_code);
-    let developer_code = rom_rd(&device_handle, addr_developer_code);
-    let version = rom_rd(&device_handle, addr_version);
+    let addr_checksum         = (0xFFDE - map_adjust) as u16; // 2 bytes
+
+    let map_mode = rom_rd(device_handle, addr_map_mode)?;
+    let rom_type = rom_rd(device_handle, addr_rom_type)?;
+    let rom_size = rom_rd(device_handle, addr_rom_size)?;
+    let sram_size = rom_rd(device_handle, addr_sram_size)?;
+    let exp_ram_size = rom_rd(device_handle, addr_expansion_ram_size)?;
+    let destination_code = rom_rd(device_handle, addr_destination_code)?;
+    let developer_code = rom_rd(device_handle, addr_developer_code)?;
+    let version = rom_rd(device_handle, addr_version)?;
     
     let mut rom_name_array: [u8; 21] = [0; 21];
     for (index, item) in rom_name_array.iter_mut().enumerate() {
-        *item = rom_rd(&device_handle, addr_rom_name + index as u16);
+        *item = rom_rd(device_handle, addr_rom_name + index as u16)?;
     }
 
     let rom_name = String::from_utf8_lossy(&rom_name_array).to_string();
 
-    let upper = (rom_rd(&device_handle, addr_compliment_check) as u16) << 8;
-    let lower = rom_rd(&device_handle, addr_compliment_check + 1) as u16;
+    // Both are stored little-endian.
+    let lower = rom_rd(device_handle, addr_compliment_check)? as u16;
+    let upper = (rom_rd(device_handle, addr_compliment_check + 1)? as u16) << 8;
     let compliment_check = lower | upper;
 
-    let upper: u16 = (rom_rd(&device_handle, addr_checksum) as u16) << 8;
-    let lower: u16 = rom_rd(&device_handle, addr_checksum + 1) as u16;
+    let lower = rom_rd(device_handle, addr_checksum)? as u16;
+    let upper = (rom_rd(device_handle, addr_checksum + 1)? as u16) << 8;
     let checksum = lower | upper;
 
-    return SnesHeader { rom_name,
+    return Ok(SnesHeader { rom_name,
                         map_mode,
                         rom_type,
                         rom_size,
@@ -311,22 +459,118 @@ fn get_header<T: UsbContext>(device_handle: &DeviceHandle<T>, map_adjust: i32) -
                         version,
                         compliment_check,
                         checksum
+    });
+}
+
+// Data lines of the SNES 62-pin edge.
+const DATA_PINS: [Pin; 8] = [
+    Pin { signal: "D0", number: "19" },
+    Pin { signal: "D1", number: "20" },
+    Pin { signal: "D2", number: "21" },
+    Pin { signal: "D3", number: "22" },
+    Pin { signal: "D4", number: "50" },
+    Pin { signal: "D5", number: "51" },
+    Pin { signal: "D6", number: "52" },
+    Pin { signal: "D7", number: "53" },
+];
+
+// LoROM carts decode ROM at $8000-$FFFF, A15 only selects it.
+const LOROM_BUS: Bus = Bus {
+    connector: "SNES 62-pin, LoROM",
+    base: 0x80_8000,
+    address: &[
+        (0, Pin { signal: "A0", number: "17" }),
+        (1, Pin { signal: "A1", number: "16" }),
+        (2, Pin { signal: "A2", number: "15" }),
+        (3, Pin { signal: "A3", number: "14" }),
+        (4, Pin { signal: "A4", number: "13" }),
+        (5, Pin { signal: "A5", number: "12" }),
+        (6, Pin { signal: "A6", number: "11" }),
+        (7, Pin { signal: "A7", number: "10" }),
+        (8, Pin { signal: "A8", number: "9" }),
+        (9, Pin { signal: "A9", number: "8" }),
+        (10, Pin { signal: "A10", number: "7" }),
+        (11, Pin { signal: "A11", number: "6" }),
+        (12, Pin { signal: "A12", number: "37" }),
+        (13, Pin { signal: "A13", number: "38" }),
+        (14, Pin { signal: "A14", number: "39" }),
+        (16, Pin { signal: "BA0", number: "41" }),
+        (17, Pin { signal: "BA1", number: "42" }),
+        (18, Pin { signal: "BA2", number: "43" }),
+        (19, Pin { signal: "BA3", number: "44" }),
+        (20, Pin { signal: "BA4", number: "45" }),
+        (21, Pin { signal: "BA5", number: "46" }),
+        (22, Pin { signal: "BA6", number: "47" }),
+    ],
+    data: &DATA_PINS,
+};
+
+const HIROM_BUS: Bus = Bus {
+    connector: "SNES 62-pin, HiROM",
+    base: 0xC0_0000,
+    address: &[
+        (0, Pin { signal: "A0", number: "17" }),
+        (1, Pin { signal: "A1", number: "16" }),
+        (2, Pin { signal: "A2", number: "15" }),
+        (3, Pin { signal: "A3", number: "14" }),
+        (4, Pin { signal: "A4", number: "13" }),
+        (5, Pin { signal: "A5", number: "12" }),
+        (6, Pin { signal: "A6", number: "11" }),
+        (7, Pin { signal: "A7", number: "10" }),
+        (8, Pin { signal: "A8", number: "9" }),
+        (9, Pin { signal: "A9", number: "8" }),
+        (10, Pin { signal: "A10", number: "7" }),
+        (11, Pin { signal: "A11", number: "6" }),
+        (12, Pin { signal: "A12", number: "37" }),
+        (13, Pin { signal: "A13", number: "38" }),
+        (14, Pin { signal: "A14", number: "39" }),
+        (15, Pin { signal: "A15", number: "40" }),
+        (16, Pin { signal: "BA0", number: "41" }),
+        (17, Pin { signal: "BA1", number: "42" }),
+        (18, Pin { signal: "BA2", number: "43" }),
+        (19, Pin { signal: "BA3", number: "44" }),
+        (20, Pin { signal: "BA4", number: "45" }),
+        (21, Pin { signal: "BA5", number: "46" }),
+    ],
+    data: &DATA_PINS,
+};
+
+// Look for bad ROM lines. The mapping comes from the header, a cart with bad
+// lines may not have a readable one and is tested as LoROM.
+pub fn diagnose<T: Transport>(device_handle: &T) -> Result<Report> {
+    let mapping = read_header(device_handle).ok().and_then(|header| match_map_mode(header.map_mode));
+    let bus = match mapping {
+        Some(mapping) if mapping.starts_with("HiROM") => &HIROM_BUS,
+        Some(_) => &LOROM_BUS,
+        None => {
+            warn!("No valid header, testing the lines as LoROM");
+            &LOROM_BUS
+        }
     };
+    let mut current_bank = None;
+    return diagnose::probe(bus, |address| {
+        let bank = (address >> 16) as u16;
+        if current_bank != Some(bank) {
+            set_bank(device_handle, bank)?;
+            current_bank = Some(bank);
+        }
+        return Ok(rom_rd(device_handle, address as u16)? as u16);
+    });
 }
 
 // Device functions
 
-pub fn rom_rd<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) -> u8 {
+pub fn rom_rd<T: Transport>(device_handle: &T, operand: u16) -> Result<u8> {
     let request = 4; // 4 is for SNES
     let mut buf: [u8; 3] = [0; 3];
-    util::read_device(device_handle, &mut buf, request, SNES_ROM_RD, operand, 0);
-    return buf[2];
+    util::read_device(device_handle, &mut buf, request, SNES_ROM_RD, operand, 0)?;
+    return Ok(buf[2]);
 }
 
-pub fn set_bank<T: UsbContext>(device_handle: &DeviceHandle<T>, operand: u16) {
+pub fn set_bank<T: Transport>(device_handle: &T, operand: u16) -> Result<()> {
     let request = 4; // 4 is for SNES
     let mut buf: [u8; 1] = [0; 1];
-    util::read_device(device_handle, &mut buf, request, SNES_SET_BANK, operand, 0);
+    util::read_device(device_handle, &mut buf, request, SNES_SET_BANK, operand, 0)
 }
 
 fn match_developer(dev_code: u8) -> Option<&'static str> {
@@ -509,6 +753,27 @@ fn match_destination(dest_code: u8) -> Option<&'static str> {
     }
 }
 
+// Region as No-Intro names it.
+fn match_region(dest_code: u8) -> Option<&'static str> {
+    match dest_code {
+        0 => Some("Japan"),
+        1 => Some("USA"),
+        2 => Some("Europe"),
+        3 => Some("Sweden"),
+        4 => Some("Finland"),
+        5 => Some("Denmark"),
+        6 => Some("France"),
+        7 => Some("Netherlands"),
+        8 => Some("Spain"),
+        9 => Some("Germany"),
+        10 => Some("Italy"),
+        11 => Some("China"),
+        12 => Some("Indonesia"),
+        13 => Some("Korea"),
+        _ => None
+    }
+}
+
 fn match_hardware_type(rom_type: u8) -> Option<&'static str> {
     match rom_type {
         0x00 => Some("ROM Only"),
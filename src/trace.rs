@@ -0,0 +1,190 @@
+// Recording and replay of the control transfers sent to the programmer.
+//
+// A trace is a text file with one transfer per line:
+//   request opcode operand misc elapsed_us response
+// request is decimal, opcode/operand/misc are hex, elapsed_us is the time the
+// transfer took and response is the returned bytes as hex. A transfer that
+// failed has "!" followed by the error message instead of the response.
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{self, BufReader, BufWriter};
+use std::time::Instant;
+
+use crate::error::{Error, Result};
+use crate::transport::Transport;
+
+const TRACE_HEADER: &str = "# inlrust trace v1";
+
+pub struct Recorder<T: Transport> {
+    inner: T,
+    file: RefCell<BufWriter<File>>,
+}
+
+impl<T: Transport> Recorder<T> {
+    pub fn create(inner: T, filename: &str) -> Result<Recorder<T>> {
+        let file = File::create(filename)
+            .map_err(|err| io::Error::new(err.kind(), format!("Could not create {}: {}", filename, err)))?;
+        let mut file = BufWriter::new(file);
+        writeln!(file, "{}", TRACE_HEADER)?;
+        return Ok(Recorder { inner, file: RefCell::new(file) });
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize> {
+        let start = Instant::now();
+        let result = self.inner.control_in(request, value, index, buf);
+        let elapsed = start.elapsed().as_micros();
+
+        let response = match &result {
+            Ok(len) => to_hex(&buf[..*len]),
+            Err(Error::Usb(msg)) => format!("!{}", msg),
+            Err(err) => format!("!{}", err),
+        };
+        let mut file = self.file.borrow_mut();
+        writeln!(
+            file,
+            "{} {:02X} {:04X} {:02X} {} {}",
+            request,
+            value & 0xFF,
+            index,
+            value >> 8,
+            elapsed,
+            response
+        )
+        .map_err(|err| io::Error::new(err.kind(), format!("Could not write trace: {}", err)))?;
+        return result;
+    }
+}
+
+struct Transfer {
+    request: u8,
+    value: u16,
+    index: u16,
+    // Err holds the message of a transfer that failed when recorded.
+    response: std::result::Result<Vec<u8>, String>,
+}
+
+// Serves a recorded session back in order. The host has to issue the same
+// transfers as when it was recorded, otherwise the replay stops.
+pub struct Replayer {
+    transfers: Vec<Transfer>,
+    next: Cell<usize>,
+}
+
+impl Replayer {
+    pub fn open(filename: &str) -> Result<Replayer> {
+        let file = File::open(filename)
+            .map_err(|err| io::Error::new(err.kind(), format!("Could not open {}: {}", filename, err)))?;
+        let mut transfers = Vec::new();
+        for (line_nbr, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let transfer = parse_line(&line)
+                .ok_or_else(|| Error::Trace(format!("{}:{}: could not parse \"{}\"", filename, line_nbr + 1, line)))?;
+            transfers.push(transfer);
+        }
+        return Ok(Replayer { transfers, next: Cell::new(0) });
+    }
+}
+
+impl Transport for Replayer {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize> {
+        let nbr = self.next.get();
+        let transfer = self
+            .transfers
+            .get(nbr)
+            .ok_or_else(|| Error::Trace(format!("Trace ended after {} transfers", nbr)))?;
+        if transfer.request != request || transfer.value != value || transfer.index != index {
+            return Err(Error::Trace(format!(
+                "Trace diverged at transfer {}: recorded {} {:02X} {:04X} {:02X} but got {} {:02X} {:04X} {:02X}",
+                nbr,
+                transfer.request,
+                transfer.value & 0xFF,
+                transfer.index,
+                transfer.value >> 8,
+                request,
+                value & 0xFF,
+                index,
+                value >> 8
+            )));
+        }
+        self.next.set(nbr + 1);
+
+        let response = transfer.response.clone().map_err(Error::Usb)?;
+        let len = response.len().min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+        return Ok(len);
+    }
+}
+
+fn parse_line(line: &str) -> Option<Transfer> {
+    let mut fields = line.splitn(6, ' ');
+    let request = fields.next()?.parse::<u8>().ok()?;
+    let opcode = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let index = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let misc = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let _elapsed = fields.next()?.parse::<u128>().ok()?;
+    let response = fields.next().unwrap_or("");
+    let response = match response.strip_prefix('!') {
+        Some(err) => Err(err.to_string()),
+        None => Ok(from_hex(response)?),
+    };
+    return Some(Transfer { request, value: (misc << 8) | opcode, index, response });
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_transfer() {
+        let transfer = parse_line("3 1A 8000 02 120 0AFF").unwrap();
+        assert_eq!(transfer.request, 3);
+        assert_eq!(transfer.value, 0x021A);
+        assert_eq!(transfer.index, 0x8000);
+        assert_eq!(transfer.response, Ok(vec![0x0A, 0xFF]));
+    }
+
+    #[test]
+    fn parse_empty_and_failed_responses() {
+        assert_eq!(parse_line("5 00 0000 00 7 ").unwrap().response, Ok(vec![]));
+        assert_eq!(parse_line("5 00 0000 00 7").unwrap().response, Ok(vec![]));
+        let failed = parse_line("2 00 0000 00 15 !Pipe error: stalled").unwrap();
+        assert_eq!(failed.response, Err(String::from("Pipe error: stalled")));
+    }
+
+    #[test]
+    fn reject_bad_lines() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("300 00 0000 00 7 00").is_none());
+        assert!(parse_line("3 1A 8000 02").is_none());
+        assert!(parse_line("3 1A 8000 02 x 00").is_none());
+        assert!(parse_line("3 1A 8000 02 7 ABC").is_none());
+        assert!(parse_line("3 1A 8000 02 7 ZZ").is_none());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = [0x00, 0x7F, 0x80, 0xFF];
+        assert_eq!(to_hex(&bytes), "007F80FF");
+        assert_eq!(from_hex(&to_hex(&bytes)), Some(bytes.to_vec()));
+    }
+}
@@ -0,0 +1,34 @@
+use rusb::{request_type, DeviceHandle, Direction, Recipient, RequestType, UsbContext};
+use std::time::Duration;
+
+use crate::error::Result;
+
+// Anything that can carry an IN vendor request to the INL firmware.
+// request is the dictionary (see shared_dictionaries.h), value is
+// (misc << 8) | opcode and index is the operand. The response is written
+// into buf and the number of bytes received is returned.
+pub trait Transport {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize>;
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize> {
+        (**self).control_in(request, value, index, buf)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize> {
+        (**self).control_in(request, value, index, buf)
+    }
+}
+
+// The real programmer over libusb.
+impl<T: UsbContext> Transport for DeviceHandle<T> {
+    fn control_in(&self, request: u8, value: u16, index: u16, buf: &mut [u8]) -> Result<usize> {
+        let request_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
+        let timeout = Duration::from_secs(1);
+        let len = self.read_control(request_type, request, value, index, buf, timeout)?;
+        return Ok(len);
+    }
+}
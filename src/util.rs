@@ -1,302 +1,248 @@
-use rusb::{request_type, DeviceHandle, Direction, Recipient, RequestType, UsbContext};
-use std::time::Duration;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
-use std::io::BufWriter;
+use std::io::{self, BufWriter, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use log::{info, trace, warn};
 
-use crate::buffer;
-use crate::operation;
+use crate::engine::{self, Region};
+use crate::error::{Error, Result};
+use crate::progress::Tracker;
+use crate::opcodes::errors::SUCCESS;
+use crate::transport::Transport;
 
 const RETURN_ERR_IDX: usize = 0;
 
-const RED_START: &str = "\x1b[0;31m";
-const COLOR_END: &str = "\x1b[0m";
-
-pub fn dump_to_array<T: UsbContext>(
-    device_handle: &DeviceHandle<T>,
-    dump_array: &mut Vec<u8>,
+pub fn dump_to_array<T: Transport>(
+    device_handle: &T,
+    dump_array: &mut [u8],
     size_kb: u16,
     map: u16,
     mem: u16,
-) {
-    let buff0 = 0;
-    let buff1 = 1;
-
-    operation::set_operation(&device_handle, 0x01);
-
-    buffer::raw_buffer_reset(&device_handle);
-
-    buffer_allocate(&device_handle, 2, 128);
-
-    buffer::set_mem_n_part(&device_handle, (mem << 8) | 0xDD, buff0);
-    buffer::set_mem_n_part(&device_handle, (mem << 8) | 0xDD, buff1);
-
-    buffer::set_map_n_mapvar(&device_handle, (map << 8) | 0, buff0);
-    buffer::set_map_n_mapvar(&device_handle, (map << 8) | 0, buff1);
-
-    operation::set_operation(&device_handle, 0xD2);
-
-    let mut buf: [u8; 128] = [0; 128];
-    let mut buff_status = 0;
-
-    for i in 0..((size_kb as usize) * 1024 / 128) {
-        for try_nbr in 0..20 {
-            buff_status = buffer::get_cur_buff_status(&device_handle);
-            // DUMPED = 0xD8
-            if buff_status == 0xD8 {
-                break;
-            }
-            if try_nbr == 19 {
-                println!("Did not get buff_status within 20 tries");
-            }
-        }
-        if buff_status != 0xD8 {
-            println!("DID NOT GET BUFF STATUS");
-            println!("STOPPING!");
-            break;
-        }
-   
-        buffer::buff_payload(&device_handle, &mut buf);
-
-        // Save buffer into larger array
-        dump_array[i*128..(i+1)*128].clone_from_slice(&buf);
-    }
-
-    operation::set_operation(&device_handle, 0x01);
-    buffer::raw_buffer_reset(&device_handle);
+    progress: &mut Tracker,
+) -> Result<()> {
+    let size = (size_kb as usize) * 1024;
+    return engine::run(device_handle, Region { mem, map, size_kb }, &mut dump_array[..size], progress);
 }
 
-pub fn dump<T: UsbContext, W: Write>(
-    device_handle: &DeviceHandle<T>,
-    file: &mut BufWriter<W>,
+pub fn dump<T: Transport, W: Write + Send>(
+    device_handle: &T,
+    file: &mut W,
     size_kb: u16,
     map: u16,
     mem: u16,
-) {
-    let buff0 = 0;
-    let buff1 = 1;
-
-    operation::set_operation(&device_handle, 0x01);
-
-    buffer::raw_buffer_reset(&device_handle);
-
-    buffer_allocate(&device_handle, 2, 128);
-
-    buffer::set_mem_n_part(&device_handle, (mem << 8) | 0xDD, buff0);
-    buffer::set_mem_n_part(&device_handle, (mem << 8) | 0xDD, buff1);
-
-    buffer::set_map_n_mapvar(&device_handle, (map << 8) | 0, buff0);
-    buffer::set_map_n_mapvar(&device_handle, (map << 8) | 0, buff1);
-
-    operation::set_operation(&device_handle, 0xD2);
-
-    let mut buf: [u8; 128] = [0; 128];
-    let mut buff_status = 0;
-    for _ in 0..((size_kb as u32) * 1024 / 128) {
-        for try_nbr in 0..20 {
-            buff_status = buffer::get_cur_buff_status(&device_handle);
-            // DUMPED = 0xD8
-            if buff_status == 0xD8 {
-                break;
-            }
-            if try_nbr == 19 {
-                println!("Did not get buff_status within 20 tries");
-            }
-        }
-        if buff_status != 0xD8 {
-            println!("DID NOT GET BUFF STATUS");
-            println!("STOPPING!");
-            break;
-        }
-   
-        buffer::buff_payload(&device_handle, &mut buf);
-
-        file.write_all(&buf).unwrap();
-    }
-
-    operation::set_operation(&device_handle, 0x01);
-    buffer::raw_buffer_reset(&device_handle);
-}
-
-pub fn buffer_allocate<T: UsbContext>(
-    device_handle: &DeviceHandle<T>,
-    num_buffers: u16,
-    buff_size: u16,
-) {
-    let buff0basebank = 0;
-    // shared_dict_buffer.h:  #define RAW_BANK_SIZE   32
-    // local numbanks = buff_size/ (op_buffer["RAW_BANK_SIZE"])
-    let numbanks = buff_size / 32;
-    let buff1basebank = numbanks;
-
-    let mut buff0id = 0;
-    let mut buff1id = 0;
-    let mut reload = 0;
-    let mut buff0_firstpage = 0;
-    let mut buff1_firstpage = 0;
-
-    if (num_buffers == 2) && (buff_size == 128) {
-        //buff0 dumps first half of page, buff1 dumps second half, repeat
-        //MSB tells buffer value of A7 when operating
-        buff0id = 0x00;
-        buff1id = 0x80;
-        //set reload (value added to page_num after each load/dump to sum of buffers
-        // 2 * 128 = 256 -> reload = 1
-        reload = 0x01;
-        //set first page
-        buff0_firstpage = 0x0000;
-        buff1_firstpage = 0x0000;
-    } else if (num_buffers == 2) && (buff_size == 256) {
-        //buff0 dumps even pages, buff1 dumps odd pages
-        //buffer id not used for addressing both id zero for now..
-        buff0id = 0x00;
-        buff1id = 0x00;
-        //set reload (value added to page_num after each load/dump to sum of buffers
-        // 2 * 256 = 512 -> reload = 2
-        reload = 0x02;
-        //set first page of each buffer
-        buff0_firstpage = 0x0000;
-        buff1_firstpage = 0x0001;
-    } else {
-        println!("ERROR! Not setup to handle this buffer config");
-    }
-    buffer::allocate_buffer0(&device_handle, (buff0id << 8) | buff0basebank, numbanks);
-    buffer::allocate_buffer1(&device_handle, (buff1id << 8) | buff1basebank, numbanks);
-    buffer::set_reload_pagenum0(&device_handle, buff0_firstpage, reload);
-    buffer::set_reload_pagenum1(&device_handle, buff1_firstpage, reload);
+    progress: &mut Tracker,
+) -> Result<()> {
+    return engine::run(device_handle, Region { mem, map, size_kb }, file, progress);
 }
 
-pub fn read_device<T: UsbContext>(
-    device_handle: &DeviceHandle<T>,
-    mut buf: &mut [u8],
-    request: u8,
+pub fn read_device<T: Transport>(
+    device_handle: &T,
+    buf: &mut [u8],
+    request: u8, // 2 is for IO, 3 is for NES, 10 is bootload see shared_dictionaries.h
     opcode: u16,
     operand: u16,
     misc: u16,
-) {
-    let request_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
-    let request = request; // 2 is for IO, 3 is for NES, 10 is bootload see shared_dictionaries.h
-                           // let value: u16 = io::IO_RESET; // op_io[opcode] | misc << 8
-    let value: u16 = (misc << 8) | opcode; // op_io[opcode] | misc << 8
-    let index = operand; // operand 0 is default see Rlen in shared_io.h
-                         // let mut buf: Vec<u8> = vec![0; size];
-                         // let mut buf:[u8; 1]=[0; 1]; // no Rlen so 1
-    let timeout = Duration::from_secs(1);
-
-    let _ = device_handle
-        .read_control(request_type, request, value, index, &mut buf, timeout)
-        .unwrap();
+) -> Result<()> {
+    read_device_no_check(device_handle, buf, request, opcode, operand, misc)?;
     let error_code = buf[RETURN_ERR_IDX];
-    // println!("{} bytes read", bytes);
-    //TODO: return error if not error_code 0
-    // TODO: Check returned bytes
-    if error_code != 0 {
-        println!("{}GOT ERROR{}: {}", RED_START, COLOR_END, error_code)
+    if error_code != SUCCESS {
+        return Err(Error::Firmware { dictionary: request, opcode, code: error_code });
     }
+    return Ok(());
 }
 
-pub fn read_device_no_check<T: UsbContext>(
-    device_handle: &DeviceHandle<T>,
-    mut buf: &mut [u8],
+pub fn read_device_no_check<T: Transport>(
+    device_handle: &T,
+    buf: &mut [u8],
     request: u8,
     opcode: u16,
     operand: u16,
     misc: u16,
-) {
-    let request_type = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
-    let request = request; // 2 is for IO, 3 is for NES, 10 is bootload see shared_dictionaries.h
-                           // let value: u16 = io::IO_RESET; // op_io[opcode] | misc << 8
+) -> Result<()> {
     let value: u16 = (misc << 8) | opcode; // op_io[opcode] | misc << 8
     let index = operand; // operand 0 is default see Rlen in shared_io.h
-                         // let mut buf: Vec<u8> = vec![0; size];
-                         // let mut buf:[u8; 1]=[0; 1]; // no Rlen so 1
-    let timeout = Duration::from_secs(1);
+    trace!("request {} opcode 0x{:02X} operand 0x{:04X} misc 0x{:02X}", request, opcode, operand, misc);
 
-    device_handle
-        .read_control(request_type, request, value, index, &mut buf, timeout)
-        .unwrap();
+    let len = device_handle.control_in(request, value, index, buf)?;
+    if len < buf.len() {
+        return Err(Error::Usb(format!("expected {} bytes but got {}", buf.len(), len)));
+    }
+    return Ok(());
 }
 
-// Command line options
-#[derive(Debug)]
-pub struct CommandLineOptions {
-    pub console: String,
-    pub filename: String,
-    pub savefile: String,
-    pub mapper: String,
-    pub prg_size: u16, // x
-    pub chr_size: u16  // y
+// A file that is written under a temporary name and only renamed to the real
+// one by commit(), so a failed dump never leaves a file that looks complete.
+pub struct OutputFile {
+    path: PathBuf,
+    part_path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    started: Instant,
+    written: u64,
+    resumed: u64,
+    checkpoint: Option<Checkpoint>,
 }
 
-pub fn help() {
-    println!("
-Usage: program [options]
-
-Options/Flags:
-  --help, -h                                    Displays this message.
-  -c console                                    Console port, (NES, SNES, GB, GBA, GENESIS)
-  -d filename                                   Dump cartridge RAM to this filename
-  -a filename                                   If provided, write ram to this filename
-  -m mapper                                     NES: (nrom, cnrom, unrom, mmc1, mmc3)
-  -x size_kbytes                                NES-only, size of PRG-ROM in kilobytes
-  -y size_kbytes                                NES-only, size of CHR-ROM in kilobytes
-  -w size_kbytes                                NES-only, size of WRAM in kilobytes
-")
+// Next to the .part file of a resumable dump: the cart it came from and how
+// many bytes of it are complete banks.
+struct Checkpoint {
+    path: PathBuf,
+    cart: String,
 }
 
-pub fn parse_command_line(args: &[String]) -> Result<CommandLineOptions, String> {
-    if args.len() < 2 {
-        return Err(String::from("Not enough arguments."))
+impl OutputFile {
+    pub fn create(filename: &str) -> Result<OutputFile> {
+        let path = PathBuf::from(filename);
+        let part_path = with_suffix(&path, ".part");
+        let file = File::create(&part_path).map_err(|err| file_error(&part_path, err))?;
+        let writer = BufWriter::new(file);
+        return Ok(OutputFile {
+            path,
+            part_path,
+            writer: Some(writer),
+            started: Instant::now(),
+            written: 0,
+            resumed: 0,
+            checkpoint: None,
+        });
     }
-    let mut console = "".to_owned();
-    let mut filename = "".to_owned();
-    let mut savefile = "".to_owned();
-    let mut mapper = "".to_owned();
-    let mut prg_size = 0;
-    let mut chr_size = 0;
 
-    let mut i = 0;
-    while i < args.len() {
-        match args[i].as_str() {
-            "-h" | "--help" => return Err(String::from("")),
-            "-c" =>  {
-                console = args[i+1].clone();
-                i += 1;
-            },
-            "-d" =>  {
-                filename = args[i+1].clone();
-                i += 1;
-            },
-            "-a" =>  {
-                savefile = args[i+1].clone();
-                i += 1;
-            },
-            "-m" =>  {
-                mapper = args[i+1].clone();
-                i += 1;
-            },
-            "-x" =>  {
-                prg_size = parse_number(&args[i+1])?;
-                i += 1;
-            },
-            "-y" =>  {
-                chr_size = parse_number(&args[i+1])?;
-                i += 1;
-            },
-            _ => (),
+    // Like create, but the .part file is kept when the dump fails and
+    // checkpoint() records how much of it can be trusted. With resume an
+    // earlier .part file of the same cart is continued, cart is anything that
+    // tells carts apart like title and checksum. Returns the bytes already
+    // dumped, always a whole number of banks.
+    pub fn create_resumable(filename: &str, cart: &str, resume: bool) -> Result<(OutputFile, u64)> {
+        let path = PathBuf::from(filename);
+        let part_path = with_suffix(&path, ".part");
+        let checkpoint = Checkpoint { path: with_suffix(&path, ".resume"), cart: cart.to_owned() };
+        let previous = read_checkpoint(&checkpoint.path)?;
+
+        let mut done = 0;
+        if let Some((previous_cart, bytes)) = previous {
+            if !resume {
+                warn!("Discarding the partial dump in {}, use --resume to continue it", part_path.display());
+            } else if previous_cart != cart {
+                return Err(Error::Resume(format!(
+                    "{} was dumped from \"{}\" but the cart is \"{}\"",
+                    part_path.display(),
+                    previous_cart,
+                    cart
+                )));
+            } else {
+                done = bytes;
+            }
+        } else if resume {
+            warn!("Nothing to resume for {}, starting from the beginning", path.display());
         }
-        i += 1;
+
+        let mut output = if done == 0 {
+            OutputFile::create(filename)?
+        } else {
+            // Whatever came after the last checkpoint may be a partial bank.
+            let mut file = OpenOptions::new().write(true).open(&part_path).map_err(|err| file_error(&part_path, err))?;
+            file.set_len(done)?;
+            file.seek(SeekFrom::End(0))?;
+            info!("Resuming {} at {} KB", path.display(), done / 1024);
+            OutputFile {
+                path,
+                part_path,
+                writer: Some(BufWriter::new(file)),
+                started: Instant::now(),
+                written: done,
+                resumed: done,
+                checkpoint: None,
+            }
+        };
+        output.checkpoint = Some(checkpoint);
+        output.checkpoint()?;
+        return Ok((output, done));
+    }
+
+    // Call after every complete bank of a resumable dump.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if let Some(checkpoint) = &self.checkpoint {
+            self.writer.as_mut().unwrap().flush()?;
+            fs::write(&checkpoint.path, format!("{}\n{}\n", checkpoint.cart, self.written))?;
+        }
+        return Ok(());
     }
 
-    return Ok(CommandLineOptions { console, filename , savefile, mapper, prg_size, chr_size})
+    pub fn commit(mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        fs::rename(&self.part_path, &self.path)?;
+        if let Some(checkpoint) = self.checkpoint.take() {
+            fs::remove_file(&checkpoint.path)?;
+        }
+
+        let seconds = self.started.elapsed().as_secs_f64();
+        let kbytes = (self.written - self.resumed) as f64 / 1024.0;
+        info!(
+            "Wrote {} KB to {} in {:.2} s ({:.1} KB/s)",
+            self.written / 1024,
+            self.path.display(),
+            seconds,
+            kbytes / seconds.max(0.001)
+        );
+        return Ok(());
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.writer.as_mut().unwrap().write(buf)?;
+        self.written += len as u64;
+        return Ok(len);
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
 }
 
-fn parse_number(argument: &String) -> Result<u16, String> {
-    let input_opt = argument.clone().parse::<u16>();
-    let size = match input_opt {
-        Ok(size) => size,
-        Err(e) => {
-            return Err(format!("While parsing \"{}\" got err: {}", argument, e));
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_none() {
+            return;
+        }
+        match &self.checkpoint {
+            Some(checkpoint) => {
+                if let Ok(Some((_, bytes))) = read_checkpoint(&checkpoint.path) {
+                    info!("Kept {} KB in {}, run again with --resume to continue", bytes / 1024, self.part_path.display());
+                }
+            }
+            // Not committed, throw away what we have.
+            None => {
+                let _ = fs::remove_file(&self.part_path);
+            }
         }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    return PathBuf::from(name);
+}
+
+fn file_error(path: &Path, err: io::Error) -> io::Error {
+    return io::Error::new(err.kind(), format!("Could not create {}: {}", path.display(), err));
+}
+
+// The cart and byte count of a checkpoint, None if there is none.
+fn read_checkpoint(path: &Path) -> Result<Option<(String, u64)>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
     };
-    return Ok(size);
+    let mut lines = text.lines();
+    let cart = lines.next().unwrap_or_default().to_owned();
+    let bytes = lines
+        .next()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| Error::Resume(format!("{} is not a checkpoint file", path.display())))?;
+    return Ok(Some((cart, bytes)));
 }
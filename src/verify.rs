@@ -0,0 +1,142 @@
+// Reading every region more than once to catch dirty contacts. The dump
+// engine reads a region passes times and only hands on what the passes agree
+// on, or what most of them read with Mode::Majority.
+use log::warn;
+
+use crate::error::{Error, Result};
+
+// Differing bytes logged per region, the rest are only counted.
+const MAX_REPORTED: usize = 16;
+
+// What to do with a byte the passes do not agree on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    // Stop the dump.
+    Fail,
+    // Keep the value read by more than half of the passes, stop if there is none.
+    Majority,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Verify {
+    pub passes: u8,
+    pub mode: Mode,
+}
+
+impl Verify {
+    // Read once and trust it, like before verification existed.
+    pub const OFF: Verify = Verify { passes: 1, mode: Mode::Fail };
+}
+
+impl Default for Verify {
+    fn default() -> Verify {
+        return Verify::OFF;
+    }
+}
+
+// Combine the passes over one region. offset is where the region starts in
+// the dump and bank the bank being read, both only for the report.
+pub(crate) fn merge(passes: &[Vec<u8>], mode: Mode, offset: u64, bank: u32) -> Result<Vec<u8>> {
+    let mut merged = passes[0].clone();
+    let mut differing = 0;
+    let mut unresolved = 0;
+    let mut first_unresolved = 0;
+    for i in 0..merged.len() {
+        let bits = passes.iter().fold(0, |bits, pass| bits | (pass[i] ^ merged[i]));
+        if bits == 0 {
+            continue;
+        }
+        let values: Vec<u8> = passes.iter().map(|pass| pass[i]).collect();
+        differing += 1;
+        if differing <= MAX_REPORTED {
+            warn!(
+                "Bank {} offset 0x{:06X}: passes read {:02X?}, differing bits 0x{:02X}",
+                bank,
+                offset + i as u64,
+                values,
+                bits
+            );
+        }
+        match (mode, majority(&values)) {
+            (Mode::Majority, Some(value)) => merged[i] = value,
+            _ => {
+                if unresolved == 0 {
+                    first_unresolved = offset + i as u64;
+                }
+                unresolved += 1;
+            }
+        }
+    }
+    if differing > MAX_REPORTED {
+        warn!("Bank {}: {} more differing bytes not shown", bank, differing - MAX_REPORTED);
+    }
+
+    if unresolved > 0 {
+        let reason = if mode == Mode::Majority { "have no majority" } else { "differ between passes" };
+        return Err(Error::Verify(format!(
+            "{} bytes in bank {} {}, the first at offset 0x{:06X}",
+            unresolved, bank, reason, first_unresolved
+        )));
+    }
+    if differing > 0 {
+        warn!("Bank {}: {} differing bytes settled by majority vote", bank, differing);
+    }
+    return Ok(merged);
+}
+
+// The value read by more than half of the passes.
+fn majority(values: &[u8]) -> Option<u8> {
+    for value in values {
+        let count = values.iter().filter(|other| *other == value).count();
+        if count * 2 > values.len() {
+            return Some(*value);
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agreeing_passes() {
+        let passes = vec![vec![1, 2, 3], vec![1, 2, 3], vec![1, 2, 3]];
+        assert_eq!(merge(&passes, Mode::Fail, 0, 0).unwrap(), vec![1, 2, 3]);
+        assert_eq!(merge(&passes, Mode::Majority, 0, 0).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn differing_passes_fail() {
+        let passes = vec![vec![1, 2, 3, 4], vec![1, 2, 0x13, 5]];
+        let err = merge(&passes, Mode::Fail, 0x4000, 2).err().unwrap().to_string();
+        assert!(err.contains("2 bytes in bank 2 differ"), "{}", err);
+        assert!(err.contains("0x004002"), "{}", err);
+    }
+
+    #[test]
+    fn majority_settles_differences() {
+        let passes = vec![vec![1, 2, 3], vec![1, 0xFF, 3], vec![0, 2, 3]];
+        assert_eq!(merge(&passes, Mode::Majority, 0, 0).unwrap(), vec![1, 2, 3]);
+        // The first pass can be the one outvoted.
+        let passes = vec![vec![9], vec![7], vec![7]];
+        assert_eq!(merge(&passes, Mode::Majority, 0, 0).unwrap(), vec![7]);
+    }
+
+    #[test]
+    fn no_majority_fails() {
+        let passes = vec![vec![1, 5], vec![2, 5], vec![3, 5]];
+        let err = merge(&passes, Mode::Majority, 0x100, 0).err().unwrap().to_string();
+        assert!(err.contains("1 bytes in bank 0 have no majority"), "{}", err);
+        assert!(err.contains("0x000100"), "{}", err);
+    }
+
+    #[test]
+    fn majority_needs_more_than_half() {
+        assert_eq!(majority(&[4, 4, 4]), Some(4));
+        assert_eq!(majority(&[4, 5, 4]), Some(4));
+        assert_eq!(majority(&[4, 5, 4, 5]), None);
+        assert_eq!(majority(&[4, 5, 6, 4, 4]), Some(4));
+        assert_eq!(majority(&[4, 5, 6]), None);
+    }
+}
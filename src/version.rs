@@ -0,0 +1,170 @@
+// Which firmware releases this host can talk to and what they can do.
+use std::fmt;
+
+use crate::error::{Error, Result};
+use crate::opcodes::bootload::APP_VERSION;
+use crate::opcodes::errors::ERR_UNKN_DICTIONARY;
+use crate::transport::Transport;
+use crate::util;
+
+// Past every opcode the dictionaries define, and in the upper half that only
+// reads, so asking for it does nothing but get an error code back.
+const PROBE_OPCODE: u16 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub sub_minor: u8,
+}
+
+impl FirmwareVersion {
+    pub const fn new(major: u8, minor: u8, sub_minor: u8) -> FirmwareVersion {
+        FirmwareVersion { major, minor, sub_minor }
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.sub_minor)
+    }
+}
+
+// Features that need a firmware dictionary not every release has. Which
+// releases have one is not documented, the firmware is asked instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    Nes,
+    Snes,
+    Gameboy,
+    Gba,
+    Genesis,
+}
+
+impl Capability {
+    pub fn for_console(console: &str) -> Option<Capability> {
+        match console.to_lowercase().as_str() {
+            "nes" => Some(Capability::Nes),
+            "snes" => Some(Capability::Snes),
+            "gb" => Some(Capability::Gameboy),
+            "gba" => Some(Capability::Gba),
+            "genesis" => Some(Capability::Genesis),
+            _ => None,
+        }
+    }
+
+    // See shared_dictionaries.h
+    fn dictionary(self) -> u8 {
+        match self {
+            Capability::Nes => 3,
+            Capability::Snes => 4,
+            Capability::Gameboy => 12,
+            Capability::Gba => 13,
+            Capability::Genesis => 14,
+        }
+    }
+}
+
+// A range of firmware releases, both ends included.
+pub struct Compatibility {
+    pub min: FirmwareVersion,
+    pub max: FirmwareVersion,
+    pub app_version: u8,
+}
+
+pub const COMPATIBILITY: &[Compatibility] = &[Compatibility {
+    min: FirmwareVersion::new(2, 0, 0),
+    max: FirmwareVersion::new(2, 255, 255),
+    app_version: APP_VERSION,
+}];
+
+// Look up a firmware release in the table.
+pub fn lookup(version: FirmwareVersion) -> Result<&'static Compatibility> {
+    if let Some(entry) = COMPATIBILITY.iter().find(|entry| entry.min <= version && version <= entry.max) {
+        return Ok(entry);
+    }
+    let oldest = COMPATIBILITY[0].min;
+    if version < oldest {
+        return Err(Error::Incompatible(format!(
+            "Firmware {} is too old, {} or newer is needed. Update the programmer with the INL host tool.",
+            version, oldest
+        )));
+    }
+    return Err(Error::Incompatible(format!(
+        "Firmware {} is newer than this version of inlrust supports (up to {}). Update inlrust.",
+        version,
+        COMPATIBILITY[COMPATIBILITY.len() - 1].max
+    )));
+}
+
+// The app version reported by the firmware has to be the one the table expects.
+pub fn check_app_version(entry: &Compatibility, app_version: u8) -> Result<()> {
+    if app_version != entry.app_version {
+        return Err(Error::Incompatible(format!(
+            "Firmware app version is {} but {} is needed. Reflash the programmer with a firmware app matching this host.",
+            app_version, entry.app_version
+        )));
+    }
+    return Ok(());
+}
+
+// Firmware without the dictionary answers any opcode in it with unknown
+// dictionary, one that has it with unknown opcode.
+pub fn check_capability<T: Transport>(device_handle: &T, capability: Capability) -> Result<()> {
+    let mut buf = [0; 1];
+    util::read_device_no_check(device_handle, &mut buf, capability.dictionary(), PROBE_OPCODE, 0, 0)?;
+    if buf[0] == ERR_UNKN_DICTIONARY {
+        return Err(Error::Incompatible(format!(
+            "The firmware can not do {:?}, update the programmer to a newer firmware.",
+            capability
+        )));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::errors::ERR_UNKN_GBA_OPCODE;
+
+    // Firmware that only knows the given dictionaries.
+    struct Firmware(&'static [u8]);
+
+    impl Transport for Firmware {
+        fn control_in(&self, request: u8, _value: u16, _index: u16, buf: &mut [u8]) -> Result<usize> {
+            buf[0] = if self.0.contains(&request) { ERR_UNKN_GBA_OPCODE } else { ERR_UNKN_DICTIONARY };
+            return Ok(1);
+        }
+    }
+
+    #[test]
+    fn lookup_known_releases() {
+        assert!(lookup(FirmwareVersion::new(2, 0, 0)).is_ok());
+        assert!(lookup(FirmwareVersion::new(2, 3, 12)).is_ok());
+        assert!(lookup(FirmwareVersion::new(2, 255, 255)).is_ok());
+    }
+
+    #[test]
+    fn lookup_unknown_releases() {
+        let too_old = lookup(FirmwareVersion::new(1, 9, 9)).err().unwrap().to_string();
+        assert!(too_old.contains("too old"), "{}", too_old);
+        let too_new = lookup(FirmwareVersion::new(3, 0, 0)).err().unwrap().to_string();
+        assert!(too_new.contains("Update inlrust"), "{}", too_new);
+    }
+
+    #[test]
+    fn app_version_has_to_match() {
+        let entry = lookup(FirmwareVersion::new(2, 3, 0)).unwrap();
+        assert!(check_app_version(entry, APP_VERSION).is_ok());
+        assert!(check_app_version(entry, APP_VERSION.wrapping_add(1)).is_err());
+    }
+
+    #[test]
+    fn capability_from_dictionary() {
+        let firmware = Firmware(&[3, 4, 12]);
+        assert!(check_capability(&firmware, Capability::Nes).is_ok());
+        assert!(check_capability(&firmware, Capability::Gameboy).is_ok());
+        assert!(check_capability(&firmware, Capability::Gba).is_err());
+        assert!(check_capability(&firmware, Capability::Genesis).is_err());
+    }
+}
@@ -0,0 +1,347 @@
+#![allow(clippy::needless_return)]
+
+// Dumps synthetic ROM images through the emulated programmer and compares
+// the result with the image they were built from.
+use std::fs;
+use std::path::PathBuf;
+
+use inlrust::genesis;
+use inlrust::gb;
+use inlrust::gba;
+use inlrust::nes::{self, Board};
+use inlrust::programmer::Programmer;
+use inlrust::progress::Silent;
+use inlrust::snes;
+use inlrust::verify::Verify;
+
+// A directory of its own for each test so they can run in parallel, removed
+// with everything in it when the test is done.
+struct Scratch {
+    dir: PathBuf,
+}
+
+impl Scratch {
+    fn new(test: &str) -> Scratch {
+        let dir = std::env::temp_dir().join(format!("inlrust-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        return Scratch { dir };
+    }
+
+    fn path(&self, name: &str) -> String {
+        return self.dir.join(name).to_string_lossy().into_owned();
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> String {
+        let file = self.path(name);
+        fs::write(&file, data).unwrap();
+        return file;
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Bytes that never repeat within a ROM, so size detection can not be fooled.
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9) | 1;
+    let mut bytes = Vec::with_capacity(len);
+    for _ in 0..len {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        bytes.push((state >> 24) as u8);
+    }
+    return bytes;
+}
+
+// iNES 1.0 image with horizontal mirroring.
+fn ines(mapper: u8, prg_kb: usize, chr_kb: usize) -> Vec<u8> {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, (prg_kb / 16) as u8, (chr_kb / 8) as u8, (mapper & 0x0F) << 4, mapper & 0xF0];
+    image.extend_from_slice(&[0; 8]);
+    image.extend(noise((prg_kb + chr_kb) * 1024, mapper as u32 * 1000 + prg_kb as u32 + chr_kb as u32));
+    return image;
+}
+
+// Mark the image as NES 2.0 with wram_kb of battery backed PRG-RAM.
+fn with_wram(mut image: Vec<u8>, wram_kb: usize) -> Vec<u8> {
+    image[6] |= 0x02;
+    image[7] |= 0x08;
+    image[10] = (((wram_kb * 1024).trailing_zeros() - 6) as u8) << 4;
+    return image;
+}
+
+fn dump_nes(programmer: &Programmer, mapper: &str, prg_kb: Option<u16>, chr_kb: Option<u16>, file: &str) -> Vec<u8> {
+    nes::dump_nes(programmer, mapper, prg_kb, chr_kb, &Board::default(), file, Verify::OFF, &mut Silent).unwrap();
+    return fs::read(file).unwrap();
+}
+
+#[test]
+fn nes_dump_matches_image() {
+    let scratch = Scratch::new("nes_dump_matches_image");
+    let carts = [("nrom", 0, 32, 8), ("nrom", 0, 16, 8), ("cnrom", 3, 32, 32), ("unrom", 2, 128, 0), ("mmc1", 1, 128, 32), ("mmc3", 4, 256, 128)];
+    for (name, number, prg_kb, chr_kb) in carts {
+        let image = ines(number, prg_kb, chr_kb);
+        let rom = scratch.write(&format!("{}_{}.nes", name, prg_kb), &image);
+        let programmer = Programmer::emulate("nes", &rom).unwrap();
+        let dump = dump_nes(&programmer, name, Some(prg_kb as u16), Some(chr_kb as u16), &scratch.path("out.nes"));
+        // The header is made from what was read, compare what follows it.
+        assert!(dump[16..] == image[16..], "{} {} KB dump differs from the image", name, prg_kb);
+        assert_eq!(&dump[4..6], &image[4..6], "{} sizes in the header", name);
+        assert_eq!(dump[6] >> 4, number, "{} mapper number", name);
+    }
+}
+
+#[test]
+fn nes_mmc1_mirroring_left_to_mapper() {
+    let scratch = Scratch::new("nes_mmc1_mirroring_left_to_mapper");
+    let mut image = ines(1, 128, 32);
+    image[6] |= 0x01;
+    let rom = scratch.write("mmc1.nes", &image);
+    let programmer = Programmer::emulate("nes", &rom).unwrap();
+    let dump = dump_nes(&programmer, "mmc1", Some(128), Some(32), &scratch.path("out.nes"));
+    assert_eq!(dump[6] & 0x01, 0);
+}
+
+#[test]
+fn nes_sizes_detected() {
+    let scratch = Scratch::new("nes_sizes_detected");
+    let carts = [
+        ("nrom", 0, 16, 8),
+        ("cnrom", 3, 32, 16),
+        ("unrom", 2, 64, 0),
+        ("unrom", 2, 256, 0),
+        ("mmc1", 1, 128, 32),
+        ("mmc1", 1, 256, 0),
+        ("mmc3", 4, 128, 128),
+        ("mmc3", 4, 512, 256),
+    ];
+    for (name, number, prg_kb, chr_kb) in carts {
+        let rom = scratch.write(&format!("{}_{}_{}.nes", name, prg_kb, chr_kb), &ines(number, prg_kb, chr_kb));
+        let programmer = Programmer::emulate("nes", &rom).unwrap();
+        let sizes = nes::detect_sizes(&programmer, name).unwrap();
+        assert_eq!(sizes, (prg_kb as u16, chr_kb as u16), "{} {} KB PRG {} KB CHR", name, prg_kb, chr_kb);
+    }
+}
+
+#[test]
+fn nes_rom_sizes_checked() {
+    let scratch = Scratch::new("nes_rom_sizes_checked");
+    assert!(nes::check_rom_sizes("unrom", Some(8), None).is_err());
+    assert!(nes::check_rom_sizes("unrom", Some(64), Some(8)).is_err());
+    assert!(nes::check_rom_sizes("mmc1", Some(48), None).is_err());
+    assert!(nes::check_rom_sizes("mmc3", Some(128), Some(4)).is_err());
+    assert!(nes::check_rom_sizes("mmc3", Some(128), Some(0)).is_ok());
+
+    // Too small for UNROM is an error, not an underflow.
+    let rom = scratch.write("unrom.nes", &ines(2, 64, 0));
+    let programmer = Programmer::emulate("nes", &rom).unwrap();
+    let file = scratch.path("out.nes");
+    assert!(nes::dump_nes(&programmer, "unrom", Some(8), Some(0), &Board::default(), &file, Verify::OFF, &mut Silent).is_err());
+}
+
+// Image with a .sav next to it, which the emulator loads into WRAM.
+fn nes_with_save(scratch: &Scratch, wram_kb: usize) -> (String, Vec<u8>) {
+    let rom = scratch.write(&format!("sxrom{}.nes", wram_kb), &with_wram(ines(1, 128, 0), wram_kb));
+    let save = noise(wram_kb * 1024, wram_kb as u32);
+    fs::write(PathBuf::from(&rom).with_extension("sav"), &save).unwrap();
+    return (rom, save);
+}
+
+#[test]
+fn nes_wram_dumped() {
+    let scratch = Scratch::new("nes_wram_dumped");
+    for wram_kb in [8, 16, 32] {
+        let (rom, save) = nes_with_save(&scratch, wram_kb);
+        let programmer = Programmer::emulate("nes", &rom).unwrap();
+        let dumped = scratch.path(&format!("dump{}.sav", wram_kb));
+        nes::dump_nes_save(&programmer, "mmc1", wram_kb as u16, &dumped, Verify::OFF, &mut Silent).unwrap();
+        assert!(fs::read(&dumped).unwrap() == save, "{} KB save dumped", wram_kb);
+    }
+}
+
+#[test]
+fn nes_wram_written() {
+    let scratch = Scratch::new("nes_wram_written");
+    for wram_kb in [8, 16, 32] {
+        let (rom, _) = nes_with_save(&scratch, wram_kb);
+        let programmer = Programmer::emulate("nes", &rom).unwrap();
+        let written = scratch.write(&format!("new{}.sav", wram_kb), &noise(wram_kb * 1024, 100 + wram_kb as u32));
+        nes::write_nes_save(&programmer, "mmc1", &written, &mut Silent).unwrap();
+        let dumped = scratch.path(&format!("dump{}.sav", wram_kb));
+        nes::dump_nes_save(&programmer, "mmc1", wram_kb as u16, &dumped, Verify::OFF, &mut Silent).unwrap();
+        assert!(fs::read(&dumped).unwrap() == fs::read(&written).unwrap(), "{} KB save written", wram_kb);
+    }
+}
+
+// LoROM or HiROM image with the checksum 0x1234 and its complement.
+fn snes_image(size: usize, hirom: bool) -> Vec<u8> {
+    let mut image = noise(size, size as u32 + hirom as u32);
+    let base = if hirom { 0xFFC0 } else { 0x7FC0 };
+    image[base..base + 21].copy_from_slice(b"TEST GAME            ");
+    image[base + 0x15] = if hirom { 0x21 } else { 0x20 };
+    image[base + 0x16] = 0x02;
+    image[base + 0x17] = (size / 1024).trailing_zeros() as u8;
+    image[base + 0x18] = 3;
+    image[base + 0x19] = 1;
+    image[base + 0x1A] = 1;
+    image[base + 0x1B] = 0;
+    image[base + 0x1C..base + 0x1E].copy_from_slice(&0xEDCBu16.to_le_bytes());
+    image[base + 0x1E..base + 0x20].copy_from_slice(&0x1234u16.to_le_bytes());
+    image[base - 3] = 0;
+    return image;
+}
+
+#[test]
+fn snes_dump_matches_image() {
+    let scratch = Scratch::new("snes_dump_matches_image");
+    for (name, size, hirom) in [("lorom", 1024 * 1024, false), ("hirom", 2048 * 1024, true)] {
+        let image = snes_image(size, hirom);
+        let rom = scratch.write(&format!("{}.sfc", name), &image);
+        let programmer = Programmer::emulate("snes", &rom).unwrap();
+        let file = scratch.path(&format!("out_{}.sfc", name));
+        snes::dump_snes(&programmer, Some(&file), None, false, Verify::OFF, &mut Silent).unwrap();
+        assert!(fs::read(&file).unwrap() == image, "{} dump differs from the image", name);
+    }
+}
+
+#[test]
+fn snes_header_decoded() {
+    let scratch = Scratch::new("snes_header_decoded");
+    for (name, hirom) in [("lorom", false), ("hirom", true)] {
+        let rom = scratch.write(&format!("{}.sfc", name), &snes_image(2048 * 1024, hirom));
+        let programmer = Programmer::emulate("snes", &rom).unwrap();
+        snes::init(&programmer).unwrap();
+        let header = snes::read_header(&programmer).unwrap();
+        assert_eq!(header.checksum, 0x1234, "{} checksum", name);
+        assert_eq!(header.compliment_check, 0xEDCB, "{} complement", name);
+        let decoded = snes::decode_header(&header);
+        assert!(decoded.checksum_pair_valid, "{} checksum pair", name);
+        assert_eq!(decoded.title, "TEST GAME");
+        assert_eq!(decoded.rom_size_bytes, Some(2048 * 1024));
+    }
+}
+
+#[test]
+fn gb_dump_matches_image() {
+    let scratch = Scratch::new("gb_dump_matches_image");
+    // MBC5 with 1MB ROM and MBC1 with 256KB.
+    for (name, size, cart_type, rom_size) in [("mbc5", 1024 * 1024, 0x1B, 5), ("mbc1", 256 * 1024, 0x03, 3)] {
+        let mut image = noise(size, cart_type as u32);
+        image[0x134..0x144].copy_from_slice(b"TESTGAME\0\0\0\0\0\0\0\0");
+        image[0x146] = 0;
+        image[0x147] = cart_type;
+        image[0x148] = rom_size;
+        image[0x149] = 2;
+        image[0x14B] = 1;
+        let rom = scratch.write(&format!("{}.gb", name), &image);
+        let programmer = Programmer::emulate("gb", &rom).unwrap();
+        let file = scratch.path(&format!("out_{}.gb", name));
+        gb::dump_gb(&programmer, Some(&file), None, Verify::OFF, &mut Silent).unwrap();
+        assert!(fs::read(&file).unwrap() == image, "{} dump differs from the image", name);
+    }
+}
+
+#[test]
+fn gba_dump_matches_image() {
+    let scratch = Scratch::new("gba_dump_matches_image");
+    let mut image = noise(4 * 1024 * 1024, 4);
+    image[0xA0..0xAC].copy_from_slice(b"TESTGAMEGBA ");
+    image[0xAC..0xB0].copy_from_slice(b"ATST");
+    let rom = scratch.write("test.gba", &image);
+    let programmer = Programmer::emulate("gba", &rom).unwrap();
+    let file = scratch.path("out.gba");
+    gba::dump_gba(&programmer, Some(&file), false, Verify::OFF, &mut Silent).unwrap();
+    assert!(fs::read(&file).unwrap() == image);
+}
+
+// 1MB with 16KB of save RAM on the odd bytes.
+fn genesis_image() -> Vec<u8> {
+    let mut image = noise(1024 * 1024, 68000);
+    image[0x100..0x110].copy_from_slice(b"SEGA GENESIS    ");
+    image[0x110..0x120].copy_from_slice(b"(C)TEST 2020.JAN");
+    image[0x120..0x150].copy_from_slice(format!("{:<48}", "TEST GAME").as_bytes());
+    image[0x150..0x180].copy_from_slice(format!("{:<48}", "TEST GAME OVERSEAS").as_bytes());
+    image[0x180..0x18E].copy_from_slice(b"GM 00000000-00");
+    image[0x18E..0x190].copy_from_slice(&0x6DC7u16.to_be_bytes());
+    for (offset, value) in [(0x1A0, 0), (0x1A4, 0xF_FFFF), (0x1A8, 0xFF_0000), (0x1AC, 0xFF_FFFF), (0x1B4, 0x20_0001), (0x1B8, 0x20_3FFF)] {
+        image[offset..offset + 4].copy_from_slice(&(value as u32).to_be_bytes());
+    }
+    image[0x1B0..0x1B4].copy_from_slice(b"RA\xF8\x20");
+    image[0x1F0..0x1F3].copy_from_slice(b"JUE");
+    return image;
+}
+
+#[test]
+fn genesis_dump_matches_image() {
+    let scratch = Scratch::new("genesis_dump_matches_image");
+    let image = genesis_image();
+    let rom = scratch.write("test.md", &image);
+    let programmer = Programmer::emulate("genesis", &rom).unwrap();
+    let file = scratch.path("out.md");
+    genesis::dump_genesis(&programmer, Some(&file), None, false, Verify::OFF, &mut Silent).unwrap();
+    assert!(fs::read(&file).unwrap() == image);
+}
+
+fn read_genesis_header(scratch: &Scratch, image: &[u8]) -> inlrust::Result<genesis::GenesisHeader> {
+    let rom = scratch.write("header.md", image);
+    let programmer = Programmer::emulate("genesis", &rom).unwrap();
+    genesis::init(&programmer).unwrap();
+    return genesis::read_header(&programmer);
+}
+
+#[test]
+fn genesis_header_decoded() {
+    let scratch = Scratch::new("genesis_header_decoded");
+    let header = read_genesis_header(&scratch, &genesis_image()).unwrap();
+    assert_eq!(header.rom_size, 1024);
+    assert_eq!(header.ram_size, 64);
+    assert_eq!(header.extra_memory_size, 16);
+    assert_eq!(header.checksum, 0x6DC7);
+    assert_eq!(genesis::decode_header(&header).extra_memory_size_bytes, 16 * 1024);
+
+    // Save RAM on the even bytes spans as much address space.
+    let mut image = genesis_image();
+    image[0x1B4..0x1B8].copy_from_slice(&0x20_0000u32.to_be_bytes());
+    image[0x1B8..0x1BC].copy_from_slice(&0x20_3FFEu32.to_be_bytes());
+    assert_eq!(read_genesis_header(&scratch, &image).unwrap().extra_memory_size, 16);
+}
+
+#[test]
+fn genesis_reversed_range_rejected() {
+    let scratch = Scratch::new("genesis_reversed_range_rejected");
+    let mut image = genesis_image();
+    image[0x1A0..0x1A4].copy_from_slice(&0x10_0000u32.to_be_bytes());
+    assert!(read_genesis_header(&scratch, &image).is_err());
+    // The whole address space does not fit the size.
+    let mut image = genesis_image();
+    image[0x1A4..0x1A8].copy_from_slice(&0xFFFF_FFFFu32.to_be_bytes());
+    assert!(read_genesis_header(&scratch, &image).is_err());
+}
+
+#[test]
+fn replay_matches_recording() {
+    let scratch = Scratch::new("replay_matches_recording");
+    let image = ines(4, 128, 128);
+    let rom = scratch.write("mmc3.nes", &image);
+    let trace = scratch.path("mmc3.trace");
+    let recorded = scratch.path("recorded.nes");
+    {
+        let programmer = Programmer::emulate("nes", &rom).unwrap().record(&trace).unwrap();
+        dump_nes(&programmer, "mmc3", Some(128), Some(128), &recorded);
+    }
+
+    // The replay serves the same dump without the image.
+    fs::remove_file(&rom).unwrap();
+    let programmer = Programmer::replay(&trace).unwrap();
+    let replayed = dump_nes(&programmer, "mmc3", Some(128), Some(128), &scratch.path("replayed.nes"));
+    assert!(replayed == fs::read(&recorded).unwrap());
+    assert!(replayed[16..] == image[16..]);
+
+    // Anything but the recorded transfers stops the replay.
+    let programmer = Programmer::replay(&trace).unwrap();
+    let file = scratch.path("diverged.nes");
+    assert!(nes::dump_nes(&programmer, "mmc3", Some(64), Some(128), &Board::default(), &file, Verify::OFF, &mut Silent).is_err());
+}